@@ -0,0 +1,1625 @@
+//! 用电量的统计分析: 用电量, 功率, 重采样和汇总统计.
+//!
+//! 服务端只有在度数变化时才会写入记录, 因此相邻两条记录之间的度数差通常正好是一个量化单位
+//! ([`QUANTUM`]), 只用相邻两条记录计算出来的功率误差很大, 需要在时间窗口内平滑 ([`smoothed_rates`]).
+//!
+//! 除特别说明外, 所有函数都要求记录已经按照时间排序.
+use std::collections::BTreeMap;
+
+use chrono::{
+    DateTime, Datelike, DurationRound, FixedOffset, Local, NaiveDate, TimeDelta, Timelike,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::TimeSpan;
+use crate::config::AcademicPeriod;
+
+type Record = (DateTime<FixedOffset>, f32);
+
+/// 读数的量化单位 (度).
+pub const QUANTUM: f32 = 0.01;
+
+/// 度数上升超过此值视为充值, 不超过此值的上升视为读数抖动 (取 1.5 个量化单位以避免浮点误差).
+pub const RECHARGE_THRESHOLD: f32 = QUANTUM * 1.5;
+
+/// 计算峰值功率时使用的平滑窗口.
+const PEAK_RATE_WINDOW: TimeDelta = TimeDelta::hours(1);
+
+/// 相邻两条记录间隔超过此值时, 认为这段时间没有被采样 (如服务端没有运行).
+pub const MAX_SAMPLE_GAP: TimeDelta = TimeDelta::hours(1);
+
+#[inline]
+#[must_use]
+pub fn is_recharge(prev_degree: f32, next_degree: f32) -> bool {
+    next_degree - prev_degree > RECHARGE_THRESHOLD
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn hours(delta: TimeDelta) -> f32 {
+    delta.num_milliseconds() as f32 / 3_600_000.0
+}
+
+/// 以充值为界将记录分段, 每一段内的度数只会减少 (除了读数抖动).
+fn segments(records: &[Record]) -> impl Iterator<Item = &[Record]> {
+    records.chunk_by(|a, b| !is_recharge(a.1, b.1))
+}
+
+/// 时间范围内的记录.
+#[must_use]
+pub fn within<'a>(records: &'a [Record], time_span: &TimeSpan) -> &'a [Record] {
+    let start = time_span
+        .start_time
+        .map_or(0, |st| records.partition_point(|x| x.0 < st));
+    let end = time_span
+        .end_time
+        .map_or(records.len(), |et| records.partition_point(|x| x.0 <= et));
+    &records[start..end.max(start)]
+}
+
+/// 一次充值.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Recharge {
+    /// 充值后第一条记录的时间.
+    pub time: DateTime<FixedOffset>,
+    pub before: f32,
+    pub after: f32,
+    /// 充值的度数, 不包含两条记录之间消耗的部分.
+    pub amount: f32,
+}
+
+#[must_use]
+pub fn recharges(records: &[Record]) -> Vec<Recharge> {
+    records
+        .windows(2)
+        .filter(|w| is_recharge(w[0].1, w[1].1))
+        .map(|w| Recharge {
+            time: w[1].0,
+            before: w[0].1,
+            after: w[1].1,
+            amount: w[1].1 - w[0].1,
+        })
+        .collect()
+}
+
+/// 时间范围内的用电量 (度), 不包含充值带来的度数上升.
+///
+/// 只统计时间范围内的记录之间的变化, 范围边界与最近一条记录之间的用电量不计入.
+#[must_use]
+pub fn consumption(records: &[Record], time_span: &TimeSpan) -> f32 {
+    segments(within(records, time_span))
+        .map(|seg| (seg[0].1 - seg[seg.len() - 1].1).max(0.0))
+        .sum()
+}
+
+/// 某一时刻的功率.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub time: DateTime<FixedOffset>,
+    /// 功率 (度/小时).
+    pub rate: f32,
+    /// 读数量化带来的误差上界 (度/小时).
+    pub error: f32,
+}
+
+impl Rate {
+    fn between(from: &Record, to: &Record) -> Option<Self> {
+        let h = hours(to.0 - from.0);
+        (h > 0.0).then(|| Rate {
+            time: to.0,
+            rate: (from.1 - to.1).max(0.0) / h,
+            error: QUANTUM / h,
+        })
+    }
+}
+
+/// 相邻两条记录之间的功率, 跨越充值的两条记录会被跳过.
+#[must_use]
+pub fn instant_rates(records: &[Record]) -> Vec<Rate> {
+    segments(records)
+        .flat_map(|seg| seg.windows(2).filter_map(|w| Rate::between(&w[0], &w[1])))
+        .collect()
+}
+
+/// 每条记录之前 `window` 时间内的平均功率.
+///
+/// 窗口内没有更早的记录时使用上一条记录, 窗口不会跨越充值. 窗口越长, 量化误差越小.
+#[must_use]
+pub fn smoothed_rates(records: &[Record], window: TimeDelta) -> Vec<Rate> {
+    let mut rates = Vec::new();
+    for seg in segments(records) {
+        let mut j = 0;
+        for i in 1..seg.len() {
+            while j + 1 < i && seg[i].0 - seg[j].0 > window {
+                j += 1;
+            }
+            if let Some(rate) = Rate::between(&seg[j], &seg[i]) {
+                rates.push(rate);
+            }
+        }
+    }
+    rates
+}
+
+/// 按照固定的时间间隔重采样, 每个采样点取该时刻之前最后一条记录的度数.
+///
+/// 采样点对齐到 unix 时间戳的 `interval` 整数倍, 位于第一条和最后一条记录之间 (包含).
+/// `interval` 不为正时返回空记录.
+#[must_use]
+pub fn resample(records: &[Record], interval: TimeDelta) -> crate::Records {
+    let mut resampled = Vec::new();
+    let (Some(first), Some(last)) = (records.first(), records.last()) else {
+        return crate::Records(resampled);
+    };
+    let step = interval.num_milliseconds();
+    if step <= 0 {
+        return crate::Records(resampled);
+    }
+    let offset = first.0.offset();
+    let mut ts = first.0.timestamp_millis().div_euclid(step) * step;
+    if ts < first.0.timestamp_millis() {
+        ts += step;
+    }
+    let mut idx = 0;
+    while ts <= last.0.timestamp_millis() {
+        while idx + 1 < records.len() && records[idx + 1].0.timestamp_millis() <= ts {
+            idx += 1;
+        }
+        if let Some(time) = DateTime::from_timestamp_millis(ts) {
+            resampled.push((time.with_timezone(offset), records[idx].1));
+        }
+        ts += step;
+    }
+    crate::Records(resampled)
+}
+
+/// 一段时间内的汇总统计.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub records_num: usize,
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    /// 用电量 (度), 不包含充值.
+    pub consumption: f32,
+    pub recharges: Vec<Recharge>,
+    /// 充值总度数.
+    pub recharged: f32,
+    /// 平均功率 (度/小时).
+    pub average_rate: Option<f32>,
+    /// 一小时窗口内平均功率的最大值.
+    pub peak_rate: Option<Rate>,
+    pub min_degree: Option<f32>,
+    pub max_degree: Option<f32>,
+    pub last_degree: Option<f32>,
+}
+
+#[must_use]
+pub fn summarize(records: &[Record], time_span: &TimeSpan) -> Summary {
+    let records = within(records, time_span);
+    let consumption = consumption(records, &TimeSpan::ALL);
+    let recharges = recharges(records);
+    let start_time = records.first().map(|x| x.0);
+    let end_time = records.last().map(|x| x.0);
+    let average_rate = start_time
+        .zip(end_time)
+        .map(|(st, et)| hours(et - st))
+        .filter(|&h| h > 0.0)
+        .map(|h| consumption / h);
+    Summary {
+        records_num: records.len(),
+        start_time,
+        end_time,
+        consumption,
+        recharged: recharges.iter().map(|r| r.amount).sum(),
+        recharges,
+        average_rate,
+        peak_rate: smoothed_rates(records, PEAK_RATE_WINDOW)
+            .into_iter()
+            .max_by(|a, b| a.rate.total_cmp(&b.rate)),
+        min_degree: records.iter().map(|x| x.1).min_by(f32::total_cmp),
+        max_degree: records.iter().map(|x| x.1).max_by(f32::total_cmp),
+        last_degree: records.last().map(|x| x.1),
+    }
+}
+
+/// 用电量分布的分组方式, 按照本机 (服务端) 时区划分.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Grouping {
+    /// 一天中的各个小时, 标签为 `00` ~ `23`.
+    HourOfDay,
+    /// 一周中的各天, 标签为 `Mon` ~ `Sun`.
+    Weekday,
+    /// 各个日期, 标签如 `2026-03-02`.
+    Day,
+    /// 各个月, 标签如 `2026-03`.
+    Month,
+}
+
+impl Grouping {
+    /// 用于排序的分组键.
+    fn key(self, time: &DateTime<Local>) -> (i32, u32) {
+        match self {
+            Grouping::HourOfDay => (0, time.hour()),
+            Grouping::Weekday => (0, time.weekday().num_days_from_monday()),
+            Grouping::Day => (time.date_naive().num_days_from_ce(), 0),
+            Grouping::Month => (time.year(), time.month()),
+        }
+    }
+
+    fn label(self, key: (i32, u32)) -> String {
+        match self {
+            Grouping::HourOfDay => format!("{:02}", key.1),
+            Grouping::Weekday => ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+                .get(key.1 as usize)
+                .map_or_else(String::new, ToString::to_string),
+            Grouping::Day => NaiveDate::from_num_days_from_ce_opt(key.0)
+                .map_or_else(String::new, |d| d.format("%Y-%m-%d").to_string()),
+            Grouping::Month => format!("{:04}-{:02}", key.0, key.1),
+        }
+    }
+}
+
+/// 一个分组内的用电量.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct UsageBucket {
+    pub label: String,
+    /// 用电量 (度), 不包含充值.
+    pub consumption: f32,
+    /// 分组在统计范围内的总时长 (小时).
+    pub hours: f32,
+    /// 有记录覆盖的时长占比 (0 ~ 1), 参见 [`MAX_SAMPLE_GAP`].
+    pub coverage: f32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct UsageStats {
+    pub grouping: Grouping,
+    /// 实际统计的时间范围, 没有给出边界时取记录的起止时间.
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    pub consumption: f32,
+    /// 按照时间顺序 (小时/星期按照其自然顺序) 排列, 统计范围内没有用电的分组也会给出.
+    pub buckets: Vec<UsageBucket>,
+}
+
+/// 将 `[start, end)` 按照本地时间的整点切分.
+fn hour_pieces(
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+) -> impl Iterator<Item = (DateTime<Local>, TimeDelta)> {
+    let mut cur = start.with_timezone(&Local);
+    let end = end.with_timezone(&Local);
+    std::iter::from_fn(move || {
+        if cur >= end {
+            return None;
+        }
+        let next = cur
+            .duration_trunc(TimeDelta::hours(1))
+            .map_or(cur, |t| t + TimeDelta::hours(1))
+            .min(end);
+        let next = if next > cur { next } else { end };
+        let piece = (cur, next - cur);
+        cur = next;
+        Some(piece)
+    })
+}
+
+#[derive(Default)]
+struct BucketAcc {
+    consumption: f32,
+    total: TimeDelta,
+    covered: TimeDelta,
+}
+
+/// 将相邻两条记录之间的用电量按照时间比例分摊到 `[start, end)` 内各个整点小时所属的分组中.
+fn accumulate<K: Ord>(
+    records: &[Record],
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    key: impl Fn(&DateTime<Local>) -> K,
+) -> BTreeMap<K, BucketAcc> {
+    let mut accs: BTreeMap<K, BucketAcc> = BTreeMap::new();
+    for (piece_start, len) in hour_pieces(start, end) {
+        accs.entry(key(&piece_start)).or_default().total += len;
+    }
+    for w in records.windows(2) {
+        let (a, b) = (w[0].0.max(start), w[1].0.min(end));
+        if a >= b {
+            continue;
+        }
+        let gap = hours(w[1].0 - w[0].0);
+        let drop = if is_recharge(w[0].1, w[1].1) {
+            0.0
+        } else {
+            (w[0].1 - w[1].1).max(0.0)
+        };
+        let sampled = w[1].0 - w[0].0 <= MAX_SAMPLE_GAP;
+        for (piece_start, len) in hour_pieces(a, b) {
+            let acc = accs.entry(key(&piece_start)).or_default();
+            acc.consumption += drop * hours(len) / gap;
+            if sampled {
+                acc.covered += len;
+            }
+        }
+    }
+    accs
+}
+
+/// 统计时间范围内按照 `grouping` 分组的用电量.
+///
+/// 相邻两条记录之间的用电量按照时间比例分摊到它们跨越的各个分组中, 因此跨越统计范围边界的部分也会按比例计入.
+#[must_use]
+pub fn usage_stats(records: &[Record], time_span: &TimeSpan, grouping: Grouping) -> UsageStats {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let accs = match (start_time, end_time) {
+        (Some(start), Some(end)) => accumulate(records, start, end, |t| grouping.key(t)),
+        _ => BTreeMap::new(),
+    };
+    let buckets: Vec<_> = accs
+        .into_iter()
+        .map(|(key, acc)| UsageBucket {
+            label: grouping.label(key),
+            consumption: acc.consumption,
+            hours: hours(acc.total),
+            coverage: if acc.total > TimeDelta::zero() {
+                (hours(acc.covered) / hours(acc.total)).min(1.0)
+            } else {
+                0.0
+            },
+        })
+        .collect();
+    UsageStats {
+        grouping,
+        start_time,
+        end_time,
+        consumption: buckets.iter().map(|b| b.consumption).sum(),
+        buckets,
+    }
+}
+
+/// 电量耗尽的预测.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Forecast {
+    /// 预测的起点, 即最后一条记录的时间.
+    pub from_time: DateTime<FixedOffset>,
+    /// 预测起点的剩余度数.
+    pub degree: f32,
+    /// 预计耗尽的时间, 在预测范围 ([`FORECAST_HORIZON`]) 内不会耗尽时为 None.
+    pub depletion_time: Option<DateTime<FixedOffset>>,
+    /// 置信区间 (约 80%) 的下界, 即用电较多时的耗尽时间.
+    pub earliest: Option<DateTime<FixedOffset>>,
+    /// 置信区间的上界, 即用电较少时的耗尽时间.
+    pub latest: Option<DateTime<FixedOffset>>,
+    /// 按照每天各个小时的平均功率计算出的日均用电量 (度).
+    pub daily_consumption: f32,
+    /// 用于预测的历史记录覆盖的小时数.
+    pub history_hours: f32,
+}
+
+/// 预测使用的历史记录长度.
+pub const FORECAST_HISTORY: TimeDelta = TimeDelta::days(14);
+
+/// 预测的最长范围.
+pub const FORECAST_HORIZON: TimeDelta = TimeDelta::days(120);
+
+/// 置信区间对应的标准正态分位数 (双侧 80%).
+const FORECAST_Z: f32 = 1.28;
+
+/// 按照当前功率曲线推算度数降到 0 的时间.
+fn depletion_time(
+    degree: f32,
+    from: DateTime<FixedOffset>,
+    hourly_rates: &[f32; 24],
+) -> Option<DateTime<FixedOffset>> {
+    let mut remaining = degree;
+    if remaining <= 0.0 {
+        return Some(from);
+    }
+    for (piece_start, len) in hour_pieces(from, from + FORECAST_HORIZON) {
+        let rate = hourly_rates[piece_start.hour() as usize];
+        let used = rate * hours(len);
+        if used >= remaining {
+            #[allow(clippy::cast_possible_truncation)]
+            let millis = (f64::from(remaining / rate) * 3_600_000.0) as i64;
+            return Some((piece_start + TimeDelta::milliseconds(millis)).fixed_offset());
+        }
+        remaining -= used;
+    }
+    None
+}
+
+/// 每天各个小时的功率 (度/小时) 估计.
+struct HourlyProfile {
+    central: [f32; 24],
+    /// 置信区间上界, 即用电较多的情况.
+    high: [f32; 24],
+    /// 置信区间下界, 即用电较少的情况.
+    low: [f32; 24],
+    history: TimeDelta,
+}
+
+impl HourlyProfile {
+    /// 根据 `until` 之前 [`FORECAST_HISTORY`] 内的记录估计, 充值带来的度数上升会被忽略.
+    ///
+    /// 同一小时在不同天的功率视为同一分布的样本, 没有足够样本的小时使用整体的平均功率.
+    /// 记录不足以估计功率时返回 None.
+    fn estimate(records: &[Record], until: DateTime<FixedOffset>) -> Option<Self> {
+        let start = (until - FORECAST_HISTORY).max(records.first()?.0);
+        let accs = accumulate(records, start, until, |t| {
+            (t.date_naive().num_days_from_ce(), t.hour())
+        });
+        // 每个小时的功率样本, 只使用大部分时间有记录覆盖的小时.
+        let mut samples: [Vec<f32>; 24] = Default::default();
+        let mut history = TimeDelta::zero();
+        for ((_, hour), acc) in &accs {
+            if acc.covered * 2 >= acc.total && acc.total > TimeDelta::zero() {
+                samples[*hour as usize].push(acc.consumption / hours(acc.total));
+                history += acc.total;
+            }
+        }
+        let all: Vec<f32> = samples.iter().flatten().copied().collect();
+        if all.is_empty() {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let mean = |xs: &[f32]| xs.iter().sum::<f32>() / xs.len() as f32;
+        #[allow(clippy::cast_precision_loss)]
+        let std = |xs: &[f32], m: f32| {
+            (xs.iter().map(|x| (x - m).powi(2)).sum::<f32>() / xs.len() as f32).sqrt()
+        };
+        let overall_mean = mean(&all);
+        let overall_std = std(&all, overall_mean);
+
+        let mut profile = HourlyProfile {
+            central: [0.0; 24],
+            high: [0.0; 24],
+            low: [0.0; 24],
+            history,
+        };
+        for (hour, xs) in samples.iter().enumerate() {
+            let (m, sd) = match xs.as_slice() {
+                [] => (overall_mean, overall_std),
+                // 只有一个样本时无法估计方差, 使用整体的标准差.
+                [x] => (*x, overall_std),
+                xs => {
+                    let m = mean(xs);
+                    (m, std(xs, m))
+                }
+            };
+            profile.central[hour] = m;
+            profile.high[hour] = m + FORECAST_Z * sd;
+            profile.low[hour] = (m - FORECAST_Z * sd).max(0.0);
+        }
+        Some(profile)
+    }
+
+    /// 按照功率曲线在 `[from, to)` 内各个小时的用电量加权的平均电价, 预计不用电时取各个小时的平均电价.
+    fn weighted_price(
+        rates: &[f32; 24],
+        prices: &[f32; 24],
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+    ) -> f32 {
+        let (mut energy, mut cost) = (0.0, 0.0);
+        for (piece_start, len) in hour_pieces(from, to) {
+            let hour = piece_start.hour() as usize;
+            let e = rates[hour] * hours(len);
+            energy += e;
+            cost += e * prices[hour];
+        }
+        if energy > 0.0 {
+            cost / energy
+        } else {
+            prices.iter().sum::<f32>() / 24.0
+        }
+    }
+
+    /// 按照功率曲线估计 `[from, to)` 内的用电量.
+    fn consumption(
+        rates: &[f32; 24],
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+    ) -> f32 {
+        hour_pieces(from, to)
+            .map(|(piece_start, len)| rates[piece_start.hour() as usize] * hours(len))
+            .sum()
+    }
+}
+
+/// 根据最近 [`FORECAST_HISTORY`] 内每天各个小时的功率预测电量耗尽的时间, 充值带来的度数上升会被忽略.
+///
+/// 记录不足以估计功率时返回 None.
+#[must_use]
+pub fn forecast(records: &[Record]) -> Option<Forecast> {
+    let &(from_time, degree) = records.last()?;
+    let profile = HourlyProfile::estimate(records, from_time)?;
+    Some(Forecast {
+        from_time,
+        degree,
+        depletion_time: depletion_time(degree, from_time, &profile.central),
+        earliest: depletion_time(degree, from_time, &profile.high),
+        latest: depletion_time(degree, from_time, &profile.low),
+        daily_consumption: profile.central.iter().sum(),
+        history_hours: hours(profile.history),
+    })
+}
+
+/// 充值建议.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct RechargePlan {
+    /// 计划的起点, 即最后一条记录的时间.
+    pub from_time: DateTime<FixedOffset>,
+    pub target_time: DateTime<FixedOffset>,
+    /// 起点的剩余度数.
+    pub degree: f32,
+    /// 预计到目标时间为止的用电量 (度).
+    pub expected_consumption: f32,
+    /// 用电较多时 (置信区间上界) 的用电量 (度).
+    pub high_consumption: f32,
+    /// 在预计用电量之上额外预留的比例, 如 0.1 表示 10%.
+    pub margin: f32,
+    /// 建议充值的度数 (向上取整), 剩余度数足够时为 0.
+    pub kwh_to_buy: f32,
+    /// 按照配置的分时电价估算的费用 (元), 没有配置电价时为 None.
+    ///
+    /// 电价按照预计到目标时间为止各个小时的用电量加权, 用电集中在高价时段时费用相应更高.
+    pub cost: Option<f32>,
+}
+
+/// 根据最近的用电情况计算到 `target_time` 为止需要充值的度数.
+///
+/// 记录不足以估计功率, 或者 `target_time` 不晚于最后一条记录时返回 None.
+#[must_use]
+pub fn plan_recharge(
+    records: &[Record],
+    target_time: DateTime<FixedOffset>,
+    margin: f32,
+    accounting: &Accounting,
+) -> Option<RechargePlan> {
+    let &(from_time, degree) = records.last()?;
+    if target_time <= from_time {
+        return None;
+    }
+    let profile = HourlyProfile::estimate(records, from_time)?;
+    let expected_consumption = HourlyProfile::consumption(&profile.central, from_time, target_time);
+    let high_consumption = HourlyProfile::consumption(&profile.high, from_time, target_time);
+    let margin = margin.max(0.0);
+    let kwh_to_buy = (expected_consumption * (1.0 + margin) - degree)
+        .max(0.0)
+        .ceil();
+    Some(RechargePlan {
+        from_time,
+        target_time,
+        degree,
+        expected_consumption,
+        high_consumption,
+        margin,
+        kwh_to_buy,
+        cost: accounting.hourly_prices.map(|prices| {
+            kwh_to_buy
+                * HourlyProfile::weighted_price(&profile.central, &prices, from_time, target_time)
+        }),
+    })
+}
+
+/// 费用和碳排放的计算参数.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Accounting {
+    /// 每天各个小时 (本机时区) 的电价 (元/度), 为 None 时不计算费用.
+    pub hourly_prices: Option<[f32; 24]>,
+    /// 电网排放因子 (kg CO2/度), 为 None 时不计算碳排放.
+    pub emission_factor: Option<f32>,
+}
+
+/// 一段时间的用电量, 费用和碳排放.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CostEntry {
+    pub label: String,
+    pub consumption: f32,
+    /// 费用 (元).
+    pub cost: Option<f32>,
+    /// 碳排放 (kg CO2).
+    pub emission: Option<f32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CostReport {
+    /// 实际统计的时间范围, 没有给出边界时取记录的起止时间.
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    pub consumption: f32,
+    pub cost: Option<f32>,
+    pub emission: Option<f32>,
+    /// 按月统计, 标签如 `2026-03`.
+    pub months: Vec<CostEntry>,
+}
+
+/// 统计时间范围内的费用和碳排放, 分时电价按照用电量在各个小时的分布计算.
+#[must_use]
+pub fn cost_report(
+    records: &[Record],
+    time_span: &TimeSpan,
+    accounting: &Accounting,
+) -> CostReport {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let accs = match (start_time, end_time) {
+        (Some(start), Some(end)) => {
+            accumulate(records, start, end, |t| (Grouping::Month.key(t), t.hour()))
+        }
+        _ => BTreeMap::new(),
+    };
+    let mut months: Vec<CostEntry> = Vec::new();
+    let mut last_month = None;
+    for ((month, hour), acc) in accs {
+        if last_month != Some(month) {
+            last_month = Some(month);
+            months.push(CostEntry {
+                label: Grouping::Month.label(month),
+                consumption: 0.0,
+                cost: accounting.hourly_prices.map(|_| 0.0),
+                emission: None,
+            });
+        }
+        if let Some(entry) = months.last_mut() {
+            entry.consumption += acc.consumption;
+            if let (Some(cost), Some(prices)) = (entry.cost.as_mut(), &accounting.hourly_prices) {
+                *cost += acc.consumption * prices[hour as usize];
+            }
+        }
+    }
+    for entry in &mut months {
+        entry.emission = accounting.emission_factor.map(|f| entry.consumption * f);
+    }
+    CostReport {
+        start_time,
+        end_time,
+        consumption: months.iter().map(|m| m.consumption).sum(),
+        cost: accounting
+            .hourly_prices
+            .map(|_| months.iter().filter_map(|m| m.cost).sum()),
+        emission: accounting
+            .emission_factor
+            .map(|_| months.iter().filter_map(|m| m.emission).sum()),
+        months,
+    }
+}
+
+/// 一个整点小时内的用电量.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HourUsage {
+    /// 这一小时在统计范围内的开始时间, 统计范围的第一个小时可能不是整点.
+    pub start_time: DateTime<FixedOffset>,
+    /// 这一小时在统计范围内的时长 (小时).
+    pub hours: f32,
+    pub consumption: f32,
+    pub cost: Option<f32>,
+}
+
+/// 按照本机时区的整点小时统计时间范围内的用电量和费用.
+#[must_use]
+pub fn hourly_usage(
+    records: &[Record],
+    time_span: &TimeSpan,
+    accounting: &Accounting,
+) -> Vec<HourUsage> {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let (Some(start), Some(end)) = (start_time, end_time) else {
+        return Vec::new();
+    };
+    let mut starts: BTreeMap<i64, DateTime<FixedOffset>> = BTreeMap::new();
+    for (piece_start, _) in hour_pieces(start, end) {
+        starts.insert(piece_start.timestamp(), piece_start.fixed_offset());
+    }
+    // 用所在小时的第一个片段的开始时间作为键.
+    let hour_key = |t: &DateTime<Local>| {
+        starts
+            .range(..=t.timestamp())
+            .next_back()
+            .map_or(t.timestamp(), |(k, _)| *k)
+    };
+    accumulate(records, start, end, hour_key)
+        .into_iter()
+        .filter_map(|(key, acc)| {
+            let start_time = *starts.get(&key)?;
+            let hour = start_time.with_timezone(&Local).hour() as usize;
+            Some(HourUsage {
+                start_time,
+                hours: hours(acc.total),
+                consumption: acc.consumption,
+                cost: accounting
+                    .hourly_prices
+                    .map(|prices| acc.consumption * prices[hour]),
+            })
+        })
+        .collect()
+}
+
+/// 估计基础负载时, 以连续若干个整点小时的平均功率作为一个样本.
+const BASELINE_WINDOW: usize = 3;
+
+/// 以样本中此分位数处的功率作为基础负载.
+const BASELINE_PERCENTILE: f32 = 0.1;
+
+/// 至少需要这么多个样本才能估计基础负载.
+const BASELINE_MIN_SAMPLES: usize = 4;
+
+/// 各个整点小时 (本机时区) 的用电量, 按照时间排列.
+fn hour_accs(
+    records: &[Record],
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+) -> BTreeMap<DateTime<Local>, BucketAcc> {
+    accumulate(records, start, end, |t| {
+        t.duration_trunc(TimeDelta::hours(1)).unwrap_or(*t)
+    })
+}
+
+/// 估计时间范围内的基础负载功率 (度/小时), 即冰箱, 路由器等一直开着的电器的功率.
+///
+/// 取连续 [`BASELINE_WINDOW`] 个有记录覆盖的完整小时的平均功率作为样本, 以较低分位数处的样本作为基础负载,
+/// 这样夜间等持续低功率的时段决定基础负载, 而短暂的读数抖动不会. 样本不足时返回 None.
+#[must_use]
+pub fn baseline_rate(records: &[Record], time_span: &TimeSpan) -> Option<f32> {
+    let start = time_span.start_time.or(records.first().map(|x| x.0))?;
+    let end = time_span.end_time.or(records.last().map(|x| x.0))?;
+    let hours_list: Vec<_> = hour_accs(records, start, end)
+        .into_iter()
+        .filter(|(_, acc)| acc.total >= TimeDelta::hours(1) && acc.covered * 2 >= acc.total)
+        .collect();
+    #[allow(clippy::cast_precision_loss)]
+    let mut samples: Vec<f32> = hours_list
+        .windows(BASELINE_WINDOW)
+        .filter(|w| {
+            w[BASELINE_WINDOW - 1].0 - w[0].0 == TimeDelta::hours(BASELINE_WINDOW as i64 - 1)
+        })
+        .map(|w| w.iter().map(|(_, acc)| acc.consumption).sum::<f32>() / BASELINE_WINDOW as f32)
+        .collect();
+    if samples.len() < BASELINE_MIN_SAMPLES {
+        return None;
+    }
+    samples.sort_by(f32::total_cmp);
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    let index = ((samples.len() - 1) as f32 * BASELINE_PERCENTILE).round() as usize;
+    Some(samples[index])
+}
+
+/// 基础负载和主动用电的划分.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BaselineSplit {
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    /// 基础负载功率 (度/小时).
+    pub baseline_rate: f32,
+    /// 总用电量 (度).
+    pub consumption: f32,
+    /// 基础负载的用电量 (度).
+    pub baseline: f32,
+    /// 主动用电的用电量 (度), 即总用电量减去基础负载.
+    pub active: f32,
+}
+
+/// 按照基础负载功率划分时间范围内的用电量.
+///
+/// 每个整点小时内不超过 `baseline_rate` 的部分计为基础负载, 其余计为主动用电.
+#[must_use]
+pub fn split_baseline(
+    records: &[Record],
+    time_span: &TimeSpan,
+    baseline_rate: f32,
+) -> BaselineSplit {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let mut split = BaselineSplit {
+        start_time,
+        end_time,
+        baseline_rate,
+        consumption: 0.0,
+        baseline: 0.0,
+        active: 0.0,
+    };
+    if let (Some(start), Some(end)) = (start_time, end_time) {
+        for acc in hour_accs(records, start, end).values() {
+            let baseline = acc.consumption.min(baseline_rate * hours(acc.total));
+            split.consumption += acc.consumption;
+            split.baseline += baseline;
+            split.active += acc.consumption - baseline;
+        }
+    }
+    split
+}
+
+/// 一个电器在标注的使用时间段内的用电.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ApplianceUsage {
+    pub name: String,
+    /// 使用次数, 即与时间范围重叠的使用时间段数.
+    pub uses: usize,
+    /// 使用时长 (小时).
+    pub hours: f32,
+    /// 估计的用电量 (度), 即使用期间超出基础负载的部分.
+    pub energy: f32,
+    /// 费用 (元), 没有配置电价时为 None.
+    pub cost: Option<f32>,
+    /// 占时间范围内总用电量的比例.
+    pub share: f32,
+    /// 平均每次使用的用电量 (度).
+    pub energy_per_use: f32,
+    /// 平均每次使用的费用 (元).
+    pub cost_per_use: Option<f32>,
+}
+
+/// 时间范围内各个电器的用电估计.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ApplianceReport {
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    /// 基础负载功率 (度/小时).
+    pub baseline_rate: f32,
+    /// 总用电量 (度).
+    pub consumption: f32,
+    /// 没有归属到任何电器的用电量 (度), 包括基础负载.
+    pub unattributed: f32,
+    /// 按照用电量从多到少排列.
+    pub appliances: Vec<ApplianceUsage>,
+}
+
+/// 根据电器的使用时间段 (电器名称, 时间范围) 估计各个电器的用电量.
+///
+/// 每个整点小时内超出基础负载的用电量按照各个电器在这一小时内的使用时长分摊给电器,
+/// 使用时长之和不足一小时的部分不归属任何电器, 多个电器同时使用时按照使用时长的比例分摊.
+#[must_use]
+pub fn attribute_appliances(
+    records: &[Record],
+    time_span: &TimeSpan,
+    intervals: &[(String, TimeSpan)],
+    baseline_rate: f32,
+    accounting: &Accounting,
+) -> ApplianceReport {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let mut report = ApplianceReport {
+        start_time,
+        end_time,
+        baseline_rate,
+        consumption: 0.0,
+        unattributed: 0.0,
+        appliances: Vec::new(),
+    };
+    let (Some(start), Some(end)) = (start_time, end_time) else {
+        return report;
+    };
+    // 截取到时间范围内的使用时间段.
+    let clipped: Vec<_> = intervals
+        .iter()
+        .filter_map(|(name, span)| {
+            let st = span.start_time.map_or(start, |t| t.max(start));
+            let et = span.end_time.map_or(end, |t| t.min(end));
+            (st < et).then_some((name, st, et))
+        })
+        .collect();
+    let mut usages: BTreeMap<&str, ApplianceUsage> = BTreeMap::new();
+    for &(name, st, et) in &clipped {
+        let usage = usages.entry(name).or_insert_with(|| ApplianceUsage {
+            name: name.clone(),
+            uses: 0,
+            hours: 0.0,
+            energy: 0.0,
+            cost: accounting.hourly_prices.map(|_| 0.0),
+            share: 0.0,
+            energy_per_use: 0.0,
+            cost_per_use: None,
+        });
+        usage.uses += 1;
+        usage.hours += hours(et - st);
+    }
+
+    for (hour, acc) in hour_accs(records, start, end) {
+        report.consumption += acc.consumption;
+        let piece_start = hour.fixed_offset().max(start);
+        let piece_end = (hour + TimeDelta::hours(1)).fixed_offset().min(end);
+        let overlaps: Vec<_> = clipped
+            .iter()
+            .filter_map(|&(name, st, et)| {
+                let overlap = et.min(piece_end) - st.max(piece_start);
+                (overlap > TimeDelta::zero()).then(|| (name, hours(overlap)))
+            })
+            .collect();
+        let excess = (acc.consumption - baseline_rate * hours(acc.total)).max(0.0);
+        let used: f32 = overlaps.iter().map(|x| x.1).sum();
+        let denom = hours(piece_end - piece_start).max(used);
+        if denom <= 0.0 {
+            continue;
+        }
+        let price = accounting.hourly_prices.map(|p| p[hour.hour() as usize]);
+        for (name, overlap) in overlaps {
+            let energy = excess * overlap / denom;
+            if let Some(usage) = usages.get_mut(name.as_str()) {
+                usage.energy += energy;
+                if let (Some(cost), Some(price)) = (usage.cost.as_mut(), price) {
+                    *cost += energy * price;
+                }
+            }
+        }
+    }
+
+    let mut appliances: Vec<_> = usages.into_values().collect();
+    #[allow(clippy::cast_precision_loss)]
+    for usage in &mut appliances {
+        if report.consumption > 0.0 {
+            usage.share = usage.energy / report.consumption;
+        }
+        usage.energy_per_use = usage.energy / usage.uses as f32;
+        usage.cost_per_use = usage.cost.map(|c| c / usage.uses as f32);
+    }
+    appliances.sort_by(|a, b| b.energy.total_cmp(&a.energy));
+    report.unattributed =
+        (report.consumption - appliances.iter().map(|a| a.energy).sum::<f32>()).max(0.0);
+    report.appliances = appliances;
+    report
+}
+
+/// 用于对比的一段时间的统计.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PeriodStats {
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    /// 用电量 (度), 不包含充值.
+    pub consumption: f32,
+    /// 时间范围的长度 (天).
+    pub days: f32,
+    /// 平均每天的用电量 (度), 时间范围不足一小时时为 None.
+    pub daily_average: Option<f32>,
+    /// 用电量最多的整点小时.
+    pub peak_hour: Option<HourUsage>,
+    /// 一天中各个小时的用电量.
+    pub by_hour: Vec<UsageBucket>,
+    /// 一周中各天的用电量.
+    pub by_weekday: Vec<UsageBucket>,
+    /// 按照日期排列的每天用电量.
+    pub by_day: Vec<UsageBucket>,
+    pub recharges: Vec<Recharge>,
+    /// 充值总度数.
+    pub recharged: f32,
+}
+
+/// 对齐后的一天, `day` 为从各自时间范围开始算起的第几天 (从 0 开始).
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AlignedDay {
+    pub day: usize,
+    /// 第一段时间中这一天的日期和用电量, 超出第一段时间的长度时为 None.
+    pub first: Option<UsageBucket>,
+    pub second: Option<UsageBucket>,
+}
+
+/// 两段时间的对比.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub first: PeriodStats,
+    pub second: PeriodStats,
+    /// 第二段时间的日均用电量相对于第一段时间的变化比例, 如 0.1 表示多用了 10%.
+    pub daily_average_change: Option<f32>,
+    /// 按照从各自开始算起的天数对齐的每天用电量, 用于叠加绘图.
+    pub series: Vec<AlignedDay>,
+}
+
+/// 统计时间范围内的用电情况, 各个分组按照本机时区划分.
+#[must_use]
+pub fn period_stats(records: &[Record], time_span: &TimeSpan) -> PeriodStats {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let span = TimeSpan::new(start_time, end_time);
+    let days = match (start_time, end_time) {
+        (Some(st), Some(et)) if et > st => hours(et - st) / 24.0,
+        _ => 0.0,
+    };
+    let consumption = consumption(records, &span);
+    let recharges: Vec<_> = recharges(within(records, &span));
+    PeriodStats {
+        start_time,
+        end_time,
+        consumption,
+        days,
+        daily_average: (days * 24.0 >= 1.0).then(|| consumption / days),
+        peak_hour: hourly_usage(records, &span, &Accounting::default())
+            .into_iter()
+            .max_by(|a, b| a.consumption.total_cmp(&b.consumption)),
+        by_hour: usage_stats(records, &span, Grouping::HourOfDay).buckets,
+        by_weekday: usage_stats(records, &span, Grouping::Weekday).buckets,
+        by_day: usage_stats(records, &span, Grouping::Day).buckets,
+        recharged: recharges.iter().map(|r| r.amount).sum(),
+        recharges,
+    }
+}
+
+/// 对比两段时间的用电情况, 两段时间可以来自不同的记录 (如两个 archive).
+#[must_use]
+pub fn compare(
+    first_records: &[Record],
+    first_span: &TimeSpan,
+    second_records: &[Record],
+    second_span: &TimeSpan,
+) -> Comparison {
+    let first = period_stats(first_records, first_span);
+    let second = period_stats(second_records, second_span);
+    let series = (0..first.by_day.len().max(second.by_day.len()))
+        .map(|day| AlignedDay {
+            day,
+            first: first.by_day.get(day).cloned(),
+            second: second.by_day.get(day).cloned(),
+        })
+        .collect();
+    let daily_average_change = match (first.daily_average, second.daily_average) {
+        (Some(a), Some(b)) if a > 0.0 => Some(b / a - 1.0),
+        _ => None,
+    };
+    Comparison {
+        first,
+        second,
+        daily_average_change,
+        series,
+    }
+}
+
+/// 校历中一个时间段的统计.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AcademicStats {
+    pub period: AcademicPeriod,
+    pub stats: PeriodStats,
+}
+
+/// 按照校历中的各个时间段 (本机时区) 统计用电情况, 结果按照开始日期排列.
+#[must_use]
+pub fn academic_stats(records: &[Record], periods: &[AcademicPeriod]) -> Vec<AcademicStats> {
+    let mut stats: Vec<_> = periods
+        .iter()
+        .filter_map(|period| {
+            Some(AcademicStats {
+                period: period.clone(),
+                stats: period_stats(records, &period.time_span(&Local)?),
+            })
+        })
+        .collect();
+    stats.sort_by_key(|s| s.period.start);
+    stats
+}
+
+/// 学习用电习惯时使用的历史长度.
+pub const PROFILE_HISTORY: TimeDelta = TimeDelta::days(28);
+
+/// 同一星期同一小时的样本少于此数时, 使用同一小时在所有天的样本.
+const PROFILE_MIN_SAMPLES: usize = 2;
+
+/// 功率标准差的下限 (度/小时), 避免用电非常稳定的时段因为读数量化而被误报.
+const PROFILE_MIN_STD: f32 = 0.05;
+
+/// 功率超出平均值不到此值 (度/小时) 时不视为异常.
+pub const ANOMALY_MIN_EXCESS: f32 = 0.3;
+
+/// 各个严重程度对应的 z 分数下限, 依次为 [`Severity::Low`], [`Severity::Medium`], [`Severity::High`].
+const ANOMALY_Z: [f32; 3] = [3.0, 5.0, 8.0];
+
+/// 按照星期和小时 (本机时区) 划分的用电习惯.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct UsageProfile {
+    /// `mean[weekday][hour]`: 平均功率 (度/小时), `weekday` 从周一 (0) 开始.
+    pub mean: [[f32; 24]; 7],
+    /// `std[weekday][hour]`: 功率的标准差 (度/小时).
+    pub std: [[f32; 24]; 7],
+    /// 用于学习的有记录覆盖的小时数.
+    pub history_hours: f32,
+}
+
+/// 根据 `until` 之前 [`PROFILE_HISTORY`] 内的记录学习用电习惯, 充值带来的度数上升会被忽略.
+///
+/// 同一星期同一小时的样本不足时使用同一小时在所有天的平均功率; 标准差总是取同一小时在所有天的样本,
+/// 以免样本太少时过于敏感. 有记录覆盖的时间不足一天时返回 None.
+#[must_use]
+pub fn usage_profile(records: &[Record], until: DateTime<FixedOffset>) -> Option<UsageProfile> {
+    let start = (until - PROFILE_HISTORY).max(records.first()?.0);
+    let accs = accumulate(records, start, until, |t| {
+        (
+            t.date_naive().num_days_from_ce(),
+            t.weekday().num_days_from_monday(),
+            t.hour(),
+        )
+    });
+    let mut by_cell: [[Vec<f32>; 24]; 7] = Default::default();
+    let mut by_hour: [Vec<f32>; 24] = Default::default();
+    let mut history = TimeDelta::zero();
+    for ((_, weekday, hour), acc) in &accs {
+        if acc.covered * 2 >= acc.total && acc.total > TimeDelta::zero() {
+            let rate = acc.consumption / hours(acc.total);
+            by_cell[*weekday as usize][*hour as usize].push(rate);
+            by_hour[*hour as usize].push(rate);
+            history += acc.total;
+        }
+    }
+    if history < TimeDelta::days(1) {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let mean = |xs: &[f32]| xs.iter().sum::<f32>() / xs.len() as f32;
+    #[allow(clippy::cast_precision_loss)]
+    let std = |xs: &[f32], m: f32| {
+        (xs.iter().map(|x| (x - m).powi(2)).sum::<f32>() / xs.len() as f32).sqrt()
+    };
+    let all: Vec<f32> = by_hour.iter().flatten().copied().collect();
+    let overall_mean = mean(&all);
+    let overall_std = std(&all, overall_mean);
+
+    let mut profile = UsageProfile {
+        mean: [[0.0; 24]; 7],
+        std: [[0.0; 24]; 7],
+        history_hours: hours(history),
+    };
+    for (weekday, cells) in by_cell.iter().enumerate() {
+        for (hour, xs) in cells.iter().enumerate() {
+            let hour_xs = &by_hour[hour];
+            let (hour_mean, hour_std) = if hour_xs.len() >= PROFILE_MIN_SAMPLES {
+                let m = mean(hour_xs);
+                (m, std(hour_xs, m))
+            } else {
+                (overall_mean, overall_std)
+            };
+            profile.mean[weekday][hour] = if xs.len() >= PROFILE_MIN_SAMPLES {
+                mean(xs)
+            } else {
+                hour_mean
+            };
+            profile.std[weekday][hour] = hour_std.max(PROFILE_MIN_STD);
+        }
+    }
+    Some(profile)
+}
+
+/// 异常的严重程度.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+/// 一段连续的用电异常 (按照整点小时划分).
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+    /// 这段时间内的用电量 (度).
+    pub consumption: f32,
+    /// 按照用电习惯预期的用电量 (度).
+    pub expected: f32,
+    /// 这段时间内最高的小时功率 (度/小时).
+    pub peak_rate: f32,
+    /// 各小时中最高的严重程度.
+    pub severity: Severity,
+}
+
+/// 检测时间范围内功率远超用电习惯的小时, 相邻的异常小时合并为一个 [`Anomaly`].
+///
+/// 只检测完整落在时间范围内, 且大部分时间有记录覆盖的整点小时 (本机时区).
+#[must_use]
+pub fn detect_anomalies(
+    records: &[Record],
+    profile: &UsageProfile,
+    time_span: &TimeSpan,
+) -> Vec<Anomaly> {
+    let start_time = time_span.start_time.or(records.first().map(|x| x.0));
+    let end_time = time_span.end_time.or(records.last().map(|x| x.0));
+    let (Some(start), Some(end)) = (start_time, end_time) else {
+        return Vec::new();
+    };
+    let accs = hour_accs(records, start, end);
+    let mut anomalies: Vec<Anomaly> = Vec::new();
+    for (hour_start, acc) in accs {
+        if acc.total < TimeDelta::hours(1) || acc.covered * 2 < acc.total {
+            continue;
+        }
+        let (weekday, hour) = (
+            hour_start.weekday().num_days_from_monday() as usize,
+            hour_start.hour() as usize,
+        );
+        let rate = acc.consumption / hours(acc.total);
+        let expected = profile.mean[weekday][hour];
+        let excess = rate - expected;
+        if excess < ANOMALY_MIN_EXCESS {
+            continue;
+        }
+        let z = excess / profile.std[weekday][hour].max(PROFILE_MIN_STD);
+        let severity = match ANOMALY_Z.iter().filter(|&&zl| z >= zl).count() {
+            0 => continue,
+            1 => Severity::Low,
+            2 => Severity::Medium,
+            _ => Severity::High,
+        };
+        let hour_start = hour_start.fixed_offset();
+        let hour_end = hour_start + acc.total;
+        match anomalies.last_mut() {
+            Some(last) if last.end_time == hour_start => {
+                last.end_time = hour_end;
+                last.consumption += acc.consumption;
+                last.expected += expected * hours(acc.total);
+                last.peak_rate = last.peak_rate.max(rate);
+                last.severity = last.severity.max(severity);
+            }
+            _ => anomalies.push(Anomaly {
+                start_time: hour_start,
+                end_time: hour_end,
+                consumption: acc.consumption,
+                expected: expected * hours(acc.total),
+                peak_rate: rate,
+                severity,
+            }),
+        }
+    }
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::{DateTime, FixedOffset, Local, TimeDelta, TimeZone, Timelike};
+
+    use crate::analytics::{
+        Accounting, Grouping, QUANTUM, Severity, attribute_appliances, baseline_rate, compare,
+        consumption, cost_report, detect_anomalies, forecast, instant_rates, plan_recharge,
+        recharges, resample, smoothed_rates, split_baseline, summarize, usage_profile, usage_stats,
+    };
+    use crate::{Records, TimeSpan};
+
+    /// 2026-03-02 ~ 2026-03-03 两天的记录, 03-03 凌晨服务端停止了 3 小时, 中午充值了 20 度.
+    async fn sample() -> Records {
+        Records::from_csv(Cursor::new(include_str!("../assets/records-sample.csv")))
+            .await
+            .unwrap()
+    }
+
+    fn t(day: u32, hour: u32) -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(8 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2026, 3, day, hour, 0, 0)
+            .unwrap()
+    }
+
+    /// 本机时区的时间, 按照本机时区的整点分桶的测试需要使用.
+    fn local_t(day: u32, hour: u32) -> DateTime<FixedOffset> {
+        Local
+            .with_ymd_and_hms(2026, 3, day, hour, 0, 0)
+            .unwrap()
+            .fixed_offset()
+    }
+
+    /// 将记录的时间按照相同的墙上时间换到本机时区.
+    fn in_local(records: &Records) -> Records {
+        Records(
+            records
+                .iter()
+                .map(|&(time, degree)| {
+                    let time = Local
+                        .from_local_datetime(&time.naive_local())
+                        .earliest()
+                        .unwrap();
+                    (time.fixed_offset(), degree)
+                })
+                .collect(),
+        )
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < QUANTUM / 2.0
+    }
+
+    #[tokio::test]
+    async fn consumption_excludes_recharges() {
+        let records = sample().await;
+        let recharges = recharges(&records);
+        assert_eq!(recharges.len(), 1);
+        assert_eq!(recharges[0].time, t(3, 12));
+        assert!(approx(recharges[0].amount, 20.0));
+
+        // 15.00 -> 4.58, 充值后 24.58 -> 18.33
+        assert!(approx(consumption(&records, &TimeSpan::ALL), 16.67));
+        let day = TimeSpan::new(Some(t(2, 0)), Some(t(3, 0) - TimeDelta::seconds(1)));
+        assert!(approx(consumption(&records, &day), 8.33));
+        let none = TimeSpan::new(Some(t(5, 0)), None);
+        assert!(approx(consumption(&records, &none), 0.0));
+    }
+
+    #[tokio::test]
+    async fn rates() {
+        let records = sample().await;
+        let instant = instant_rates(&records);
+        // 跨越充值的两条记录被跳过.
+        assert_eq!(instant.len(), records.len() - 2);
+        assert!(instant.iter().all(|r| r.rate >= 0.0 && r.error > 0.0));
+
+        let smoothed = smoothed_rates(&records, TimeDelta::hours(1));
+        let at = |time| smoothed.iter().find(|r| r.time >= time).unwrap();
+        // 夜间待机约 0.12 度/小时, 晚上约 0.9 度/小时.
+        assert!((0.08..0.16).contains(&at(t(2, 5)).rate));
+        assert!((0.75..1.05).contains(&at(t(2, 22)).rate));
+        assert!(at(t(2, 22)).error < 0.02);
+    }
+
+    #[tokio::test]
+    async fn resample_hourly() {
+        let records = sample().await;
+        let resampled = resample(&records, TimeDelta::hours(1));
+        assert_eq!(resampled.len(), 48);
+        assert_eq!(resampled[0], (t(2, 0), 15.0));
+        assert!(resampled.iter().all(|x| x.0.minute() == 0));
+        assert!(
+            resampled
+                .iter()
+                .any(|x| x.0 == t(3, 12) && approx(x.1, 24.58))
+        );
+        assert!(resample(&records, TimeDelta::zero()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn summary() {
+        let records = sample().await;
+        let summary = summarize(&records, &TimeSpan::ALL);
+        assert_eq!(summary.records_num, records.len());
+        assert!(approx(summary.consumption, 16.67));
+        assert!(approx(summary.recharged, 20.0));
+        assert!(approx(summary.min_degree.unwrap(), 4.58));
+        assert!(approx(summary.last_degree.unwrap(), 18.33));
+        let peak = summary.peak_rate.unwrap();
+        assert!(peak.time.hour() >= 19);
+        assert!((0.8..1.2).contains(&peak.rate));
+    }
+
+    #[tokio::test]
+    async fn usage_by_hour() {
+        let records = sample().await;
+        let stats = usage_stats(&records, &TimeSpan::ALL, Grouping::HourOfDay);
+        assert_eq!(stats.buckets.len(), 24);
+        assert!((stats.consumption - 16.67).abs() < QUANTUM);
+        let total_hours: f32 = stats.buckets.iter().map(|b| b.hours).sum();
+        let covered_hours: f32 = stats.buckets.iter().map(|b| b.hours * b.coverage).sum();
+        assert!((total_hours - 47.99).abs() < 0.01);
+        // 03-03 01:56:50 ~ 05:00:00 服务端没有运行.
+        assert!((total_hours - covered_hours - 3.05).abs() < 0.01);
+
+        // 晚上 19 点之后用电最多 (分组按照本机时区).
+        let evening: Vec<_> = (19..24)
+            .map(|h| format!("{:02}", t(2, h).with_timezone(&Local).hour()))
+            .collect();
+        let peak = stats
+            .buckets
+            .iter()
+            .max_by(|a, b| a.consumption.total_cmp(&b.consumption))
+            .unwrap();
+        assert!(evening.contains(&peak.label));
+    }
+
+    #[tokio::test]
+    async fn usage_by_day_within_span() {
+        let records = sample().await;
+        let span = TimeSpan::new(Some(t(2, 19)), Some(t(2, 23)));
+        let stats = usage_stats(&records, &span, Grouping::Day);
+        // 边界处的用电量按照比例计入, 与按记录统计的结果最多相差一个量化单位.
+        assert!((stats.consumption - consumption(&records, &span)).abs() <= QUANTUM * 1.5);
+        assert!(
+            stats
+                .buckets
+                .iter()
+                .all(|b| (b.coverage - 1.0).abs() < 1e-3)
+        );
+        assert_eq!(
+            usage_stats(&records, &span, Grouping::Weekday)
+                .buckets
+                .len(),
+            stats.buckets.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn forecast_depletion() {
+        let records = sample().await;
+        let forecast = forecast(&records).unwrap();
+        assert!(approx(forecast.degree, 18.33));
+        // 每天约 8.3 度, 剩余 18.33 度大约还能用两天多.
+        assert!((7.5..9.0).contains(&forecast.daily_consumption));
+        let depletion = forecast.depletion_time.unwrap();
+        assert!(depletion > t(5, 12) && depletion < t(6, 18));
+        assert!(forecast.earliest.unwrap() <= depletion);
+        assert!(forecast.latest.is_none_or(|t| t >= depletion));
+
+        assert!(super::forecast(&records[..1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn recharge_plan() {
+        let records = sample().await;
+        // 剩余 18.33 度, 一周大约需要 58 度.
+        let none = Accounting::default();
+        let plan = plan_recharge(&records, t(10, 23) + TimeDelta::hours(1), 0.1, &none).unwrap();
+        assert!((52.0..64.0).contains(&plan.expected_consumption));
+        assert!(plan.high_consumption >= plan.expected_consumption);
+        let expected = (plan.expected_consumption * 1.1 - 18.33).ceil();
+        assert!(approx(plan.kwh_to_buy, expected));
+        assert_eq!(plan.cost, None);
+
+        // 分时电价下的费用介于最低和最高电价之间, 不同于按时长平均.
+        let mut prices = [0.3; 24];
+        prices[8..22].fill(0.6);
+        let tou = Accounting {
+            hourly_prices: Some(prices),
+            emission_factor: None,
+        };
+        let plan = plan_recharge(&records, t(10, 23) + TimeDelta::hours(1), 0.1, &tou).unwrap();
+        let cost = plan.cost.unwrap();
+        assert!(cost > plan.kwh_to_buy * 0.3 && cost < plan.kwh_to_buy * 0.6);
+
+        let plan = plan_recharge(&records, t(4, 12), 0.0, &none).unwrap();
+        assert!(approx(plan.kwh_to_buy, 0.0));
+        assert!(plan_recharge(&records, t(3, 0), 0.0, &none).is_none());
+    }
+
+    #[tokio::test]
+    async fn time_of_use_cost() {
+        let records = sample().await;
+        // 本机时区 8 点到 22 点为峰时段.
+        let mut prices = [0.3; 24];
+        prices[8..22].fill(0.6);
+        let accounting = Accounting {
+            hourly_prices: Some(prices),
+            emission_factor: Some(0.5),
+        };
+        let report = cost_report(&records, &TimeSpan::ALL, &accounting);
+        assert!((report.consumption - 16.67).abs() < QUANTUM);
+        let cost = report.cost.unwrap();
+        assert!(cost > 16.67 * 0.3 && cost < 16.67 * 0.6);
+        assert!((report.emission.unwrap() - 16.67 * 0.5).abs() < QUANTUM);
+        assert_eq!(report.months.len(), 1);
+        assert!((report.months[0].cost.unwrap() - cost).abs() < 1e-3);
+
+        let report = cost_report(&records, &TimeSpan::ALL, &Accounting::default());
+        assert!(report.cost.is_none() && report.emission.is_none());
+    }
+
+    #[test]
+    fn anomalies_above_profile() {
+        // 8 天每小时 0.1 度的待机用电, 最后一天 2 点到 5 点开着取暖器 (每小时 1.5 度).
+        let t = local_t;
+        let start = t(1, 0);
+        let heater = TimeSpan::new(Some(t(9, 2)), Some(t(9, 5)));
+        let mut degree = 100.0;
+        let mut records = Vec::new();
+        let mut time = start;
+        while time <= t(9, 12) {
+            records.push((time, degree));
+            let rate = if heater.contains(&time) && time != t(9, 5) {
+                1.5
+            } else {
+                0.1
+            };
+            degree -= rate / 10.0;
+            time += TimeDelta::minutes(6);
+        }
+
+        let profile = usage_profile(&records, t(9, 0)).unwrap();
+        assert!(profile.history_hours >= 24.0 * 7.0);
+        assert!(
+            profile
+                .mean
+                .iter()
+                .flatten()
+                .all(|m| (m - 0.1).abs() < 0.02)
+        );
+
+        let window = TimeSpan::new(Some(t(9, 0)), Some(t(9, 12)));
+        let anomalies = detect_anomalies(&records, &profile, &window);
+        assert!(!anomalies.is_empty());
+        for anomaly in &anomalies {
+            assert!(anomaly.start_time < t(9, 6) && anomaly.end_time > t(9, 1));
+        }
+        let consumption: f32 = anomalies.iter().map(|a| a.consumption).sum();
+        assert!((3.0..=4.6).contains(&consumption));
+        assert!(anomalies.iter().any(|a| a.severity == Severity::High));
+        assert!(
+            anomalies
+                .iter()
+                .all(|a| a.peak_rate > 1.0 && a.expected < 0.5)
+        );
+    }
+
+    #[tokio::test]
+    async fn baseline_and_active() {
+        let records = sample().await;
+        // 夜间待机约 0.12 度/小时.
+        let rate = baseline_rate(&records, &TimeSpan::ALL).unwrap();
+        assert!((0.08..0.18).contains(&rate));
+
+        let split = split_baseline(&records, &TimeSpan::ALL, rate);
+        assert!(approx(split.consumption, 16.67));
+        assert!(approx(split.baseline + split.active, split.consumption));
+        assert!(split.baseline <= rate * 48.0 && split.baseline > rate * 30.0);
+        assert!(split.active > split.baseline);
+
+        assert!(baseline_rate(&records[..10], &TimeSpan::ALL).is_none());
+    }
+
+    #[tokio::test]
+    async fn appliances_above_baseline() {
+        let records = in_local(&sample().await);
+        let t = local_t;
+        let rate = baseline_rate(&records, &TimeSpan::ALL).unwrap();
+        let span = |st, et| TimeSpan::new(Some(st), Some(et));
+        let intervals = [
+            ("ac".to_string(), span(t(2, 18), t(2, 22))),
+            // 与空调同时使用, 按照使用时长分摊.
+            ("heater".to_string(), span(t(2, 20), t(2, 22))),
+            ("ac".to_string(), span(t(3, 18), t(3, 20))),
+            // 不在统计的时间范围内.
+            ("kettle".to_string(), span(t(5, 8), t(5, 9))),
+        ];
+        let accounting = Accounting {
+            hourly_prices: Some([0.5; 24]),
+            emission_factor: None,
+        };
+        let report = attribute_appliances(&records, &TimeSpan::ALL, &intervals, rate, &accounting);
+        assert!(approx(report.consumption, 16.67));
+        assert_eq!(report.appliances.len(), 2);
+        let ac = &report.appliances[0];
+        let heater = &report.appliances[1];
+        assert_eq!((&ac.name[..], ac.uses), ("ac", 2));
+        assert!((ac.hours - 6.0).abs() < 1e-3 && (heater.hours - 2.0).abs() < 1e-3);
+        assert!(ac.energy > heater.energy && heater.energy > 0.0);
+        let attributed = ac.energy + heater.energy;
+        assert!((report.unattributed + attributed - report.consumption).abs() < 1e-3);
+        // 超出基础负载的部分才归属电器.
+        let active = split_baseline(&records, &span(t(2, 18), t(2, 22)), rate).active;
+        assert!(
+            attributed
+                <= active + split_baseline(&records, &span(t(3, 18), t(3, 20)), rate).active + 1e-3
+        );
+        assert!((ac.cost.unwrap() - ac.energy * 0.5).abs() < 1e-3);
+        assert!((ac.energy_per_use - ac.energy / 2.0).abs() < 1e-4);
+        assert!((ac.share - ac.energy / report.consumption).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn compare_two_days() {
+        let records = sample().await;
+        let day = |d| TimeSpan::new(Some(t(d, 0)), Some(t(d + 1, 0) - TimeDelta::seconds(1)));
+        let comparison = compare(&records, &day(2), &records, &day(3));
+        let (first, second) = (&comparison.first, &comparison.second);
+        assert!(approx(first.consumption, 8.33));
+        // 跨越两天分界的两条记录之间的用电量不计入任何一天.
+        assert!((first.consumption + second.consumption - 16.67).abs() <= QUANTUM * 1.5);
+        assert!(first.recharges.is_empty());
+        assert!(approx(second.recharged, 20.0));
+        assert!((first.days - 1.0).abs() < 1e-3);
+        assert!(approx(
+            first.daily_average.unwrap(),
+            first.consumption / first.days
+        ));
+        assert_eq!(first.by_hour.len(), 24);
+        assert!(first.peak_hour.as_ref().unwrap().consumption > 0.5);
+
+        let change = comparison.daily_average_change.unwrap();
+        assert!((second.consumption / first.consumption - 1.0 - change).abs() < 1e-3);
+        assert_eq!(
+            comparison.series.len(),
+            first.by_day.len().max(second.by_day.len())
+        );
+        assert!(
+            comparison
+                .series
+                .iter()
+                .all(|d| d.first.is_some() || d.second.is_some())
+        );
+    }
+}
@@ -1,9 +1,46 @@
-use clap::Parser;
-use ecnu_power_usage::server::run_app;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use ecnu_power_usage::server::{ImportMode, run_app, run_import, run_migrate};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct AppArgs {}
+struct AppArgs {
+    /// 不指定子命令时启动服务.
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// 离线导入 csv 记录 (如 python 版本的数据), 服务运行时请使用 /import-records 接口.
+    ///
+    /// 支持表头, 逗号或制表符分隔, 以及多种时间格式.
+    Import {
+        /// 需要导入的 csv 文件.
+        file: PathBuf,
+
+        /// 导入到的房间号 (roomNo), 默认为当前配置的房间.
+        #[arg(long)]
+        room: Option<String>,
+
+        /// 作为新的 archive 导入, 而不是合并到 records.csv.
+        #[arg(long)]
+        archive: bool,
+
+        /// archive 名称, 仅在 --archive 时有效.
+        #[arg(long, requires = "archive")]
+        name: Option<String>,
+    },
+    /// 将数据目录迁移到当前的布局版本, 迁移前会备份到数据目录的 backups/ 中.
+    ///
+    /// 服务启动时也会自动迁移.
+    Migrate {
+        /// 只列出将要执行的操作, 不修改数据目录.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
 
 #[allow(dead_code)]
 struct App {
@@ -16,7 +53,44 @@ impl App {
     }
 
     async fn run(self) -> anyhow::Result<()> {
-        run_app().await?;
+        match self.args.command {
+            None => run_app().await?,
+            Some(Commands::Import {
+                file,
+                room,
+                archive,
+                name,
+            }) => {
+                let mode = if archive {
+                    ImportMode::Archive
+                } else {
+                    ImportMode::Merge
+                };
+                let report = run_import(file, room, mode, name).await?;
+                for issue in &report.issues {
+                    println!("line {}: {} ({})", issue.line, issue.reason, issue.content);
+                }
+                println!(
+                    "parsed: {}, imported: {}, duplicates: {}",
+                    report.parsed, report.imported, report.duplicates
+                );
+                if let Some(archive) = report.archive {
+                    println!("archive: {}", archive.archive_name);
+                }
+            }
+            Some(Commands::Migrate { dry_run }) => {
+                let report = run_migrate(dry_run).await?;
+                if report.from_version == report.to_version {
+                    println!("data dir layout is up to date (v{})", report.to_version);
+                }
+                for action in &report.actions {
+                    println!("{action}");
+                }
+                if let Some(backup) = report.backup {
+                    println!("backup: {}", backup.display());
+                }
+            }
+        }
         Ok(())
     }
 }
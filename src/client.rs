@@ -1,19 +1,34 @@
-use std::{io::Cursor, path::Path, time::Duration};
+use std::{collections::VecDeque, io::Cursor, path::Path, time::Duration};
 
 use chromiumoxide::{Browser, BrowserConfig, Page};
-use futures::StreamExt;
+use chrono::{DateTime, FixedOffset};
+use futures::{Stream, StreamExt, stream};
 use reqwest::{Certificate, Identity, StatusCode, Url};
 use tokio::{fs, task::JoinHandle};
 use tracing::{error, info, warn};
 
 use crate::{
     Cookies, Records, TimeSpan,
-    config::RoomConfig,
+    analytics::{
+        AcademicStats, ApplianceReport, BaselineSplit, Comparison, CostReport, Forecast, Grouping,
+        RechargePlan, Severity, UsageStats,
+    },
+    config::{AcademicPeriod, DigestSchedule, PeriodKind, RoomConfig},
     error::{CSError, CSResult, Error},
     rooms::RoomInfo,
     server::{
-        ArchiveMeta,
-        route::{CreateArchiveArgs, DeleteArchiveArgs, DownloadArchiveArgs},
+        APPLIANCE_TAG_PREFIX, AlertDelivery, AnnotatedRecords, Annotation, AnomalyEvent,
+        ArchiveMeta, AwayPeriod, Budget, BudgetPeriod, BudgetProgress, BudgetUnit, CorrectionEntry,
+        Digest, DigestFormat, EventEnvelope, ImportMode, ImportReport, RetentionReport, Roommate,
+        Settlement,
+        route::{
+            AddAnnotationArgs, AddRoommateArgs, CancelAwayArgs, CompareArgs, CorrectRecordArgs,
+            CreateArchiveArgs, DeleteAnnotationArgs, DeleteArchiveArgs, DeleteBudgetArgs,
+            DeleteRecordsArgs, DeleteRoommateArgs, DownloadArchiveArgs, GetAcademicStatsArgs,
+            GetAnomaliesArgs, GetDigestArgs, GetRecordsArgs, GetSettlementArgs, GetUsageStatsArgs,
+            ImportRecordsArgs, InsertRecordArgs, ListAnnotationsArgs, PlanRechargeArgs,
+            ResolveSpanArgs, SetAwayArgs, SetBudgetArgs, SpanArgs, UpdateAnnotationArgs,
+        },
     },
 };
 
@@ -256,6 +271,109 @@ impl BrowserExecutor {
     }
 }
 
+/// 事件流断开后重新连接的间隔.
+const SUBSCRIBE_RETRY: Duration = Duration::from_secs(3);
+
+/// 解析一个 SSE 事件块, 返回 (编号, 数据), 没有数据的块 (例如保活的注释) 返回 `None`.
+fn parse_sse_block(block: &str) -> Option<(Option<String>, String)> {
+    let mut id = None;
+    let mut data: Option<String> = None;
+    for line in block.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "id" => id = Some(value.to_string()),
+            "data" => match &mut data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => data = Some(value.to_string()),
+            },
+            _ => (),
+        }
+    }
+    data.map(|data| (id, data))
+}
+
+/// [`Client::subscribe`] 的连接状态.
+struct Subscription {
+    client: reqwest::Client,
+    url: Url,
+    last_event_id: Option<String>,
+    resp: Option<reqwest::Response>,
+    /// 尚未组成完整事件块的数据.
+    buf: Vec<u8>,
+    /// 已经解析, 尚未返回的事件.
+    pending: VecDeque<EventEnvelope>,
+    /// 连接失败或者断开, 下次连接之前需要等待.
+    retry: bool,
+}
+
+impl Subscription {
+    /// 从 `buf` 中取出完整的事件块并解析.
+    fn drain_events(&mut self) -> crate::Result<()> {
+        while let Some(pos) = self.buf.windows(2).position(|w| w == b"\n\n") {
+            let block: Vec<u8> = self.buf.drain(..pos + 2).collect();
+            let Some((id, data)) = parse_sse_block(&String::from_utf8_lossy(&block)) else {
+                continue;
+            };
+            if let Some(id) = &id {
+                self.last_event_id = Some(id.clone());
+            }
+            self.pending.push_back(EventEnvelope {
+                id: id.unwrap_or_default(),
+                event: serde_json::from_str(&data)?,
+            });
+        }
+        Ok(())
+    }
+
+    /// 下一个事件, 连接出错时返回错误并在下次调用时重新连接.
+    async fn next(&mut self) -> crate::Result<EventEnvelope> {
+        loop {
+            if let Some(envelope) = self.pending.pop_front() {
+                return Ok(envelope);
+            }
+            let Some(resp) = &mut self.resp else {
+                if self.retry {
+                    tokio::time::sleep(SUBSCRIBE_RETRY).await;
+                }
+                self.retry = true;
+                let mut req = self.client.get(self.url.clone());
+                if let Some(id) = &self.last_event_id {
+                    req = req.header("Last-Event-ID", id);
+                }
+                let resp = req.send().await?;
+                if resp.status() != StatusCode::OK {
+                    return Err(Error::CS(resp.json().await?));
+                }
+                self.retry = false;
+                self.buf.clear();
+                self.resp = Some(resp);
+                continue;
+            };
+            match resp.chunk().await {
+                Ok(Some(chunk)) => {
+                    // 服务端以 `\n` 分隔, 兼容以 `\r\n` 分隔的实现.
+                    self.buf.extend(chunk.iter().filter(|&&b| b != b'\r'));
+                    self.drain_events()?;
+                }
+                Ok(None) => {
+                    info!("event stream closed, reconnecting");
+                    self.resp = None;
+                    self.retry = true;
+                }
+                Err(e) => {
+                    self.resp = None;
+                    self.retry = true;
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+}
+
 pub struct Client {
     /// 服务端地址 e.g. `http://localhost:20531`
     server_base: Url,
@@ -326,12 +444,50 @@ impl Client {
         Ok(resp?)
     }
 
+    /// 获取时间范围内的记录.
+    pub async fn get_records_in(&self, time_span: TimeSpan) -> crate::Result<Records> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-records")?)
+            .query(&GetRecordsArgs {
+                start_time: time_span.start_time,
+                end_time: time_span.end_time,
+                span: None,
+                annotations: false,
+            })
+            .send()
+            .await?;
+        let resp: CSResult<Records> = resp.json().await?;
+        Ok(resp?)
+    }
+
+    /// 获取时间范围内的记录, 以及与时间范围重叠的标注.
+    pub async fn get_annotated_records(
+        &self,
+        time_span: TimeSpan,
+    ) -> crate::Result<AnnotatedRecords> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-records")?)
+            .query(&GetRecordsArgs {
+                start_time: time_span.start_time,
+                end_time: time_span.end_time,
+                span: None,
+                annotations: true,
+            })
+            .send()
+            .await?;
+        let resp: CSResult<AnnotatedRecords> = resp.json().await?;
+        Ok(resp?)
+    }
+
     pub async fn download_archive(&self, name: impl AsRef<str>) -> crate::Result<Records> {
         let resp = self
             .client
             .get(self.server_base.join("/download-archive")?)
             .query(&DownloadArchiveArgs {
                 name: name.as_ref().to_string(),
+                annotations: false,
             })
             .send()
             .await?;
@@ -341,6 +497,26 @@ impl Client {
         }
     }
 
+    /// 下载 archive 的记录, 以及其中的标注和当前记录中与其时间范围重叠的标注.
+    pub async fn download_annotated_archive(
+        &self,
+        name: impl AsRef<str>,
+    ) -> crate::Result<AnnotatedRecords> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/download-archive")?)
+            .query(&DownloadArchiveArgs {
+                name: name.as_ref().to_string(),
+                annotations: true,
+            })
+            .send()
+            .await?;
+        match resp.status() {
+            StatusCode::OK => Ok(resp.json().await?),
+            _ => Err(Error::CS(resp.json().await?)),
+        }
+    }
+
     pub async fn create_archive(
         &self,
         archive_name: Option<String>,
@@ -351,6 +527,29 @@ impl Client {
             .post(self.server_base.join("/create-archive")?)
             .json(&CreateArchiveArgs {
                 time_span,
+                span: None,
+                archive_name,
+            })
+            .send()
+            .await?;
+        let result: CSResult<ArchiveMeta> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 按照时间范围表达式创建 archive, 如 `semester:last`.
+    ///
+    /// 表达式为校历时间段且没有给出名称时, 使用时间段的名称作为 archive 名称.
+    pub async fn create_archive_by_expr(
+        &self,
+        archive_name: Option<String>,
+        expr: impl AsRef<str>,
+    ) -> crate::Result<ArchiveMeta> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/create-archive")?)
+            .json(&CreateArchiveArgs {
+                time_span: TimeSpan::ALL,
+                span: Some(expr.as_ref().to_string()),
                 archive_name,
             })
             .send()
@@ -422,6 +621,804 @@ impl Client {
         Ok(result?)
     }
 
+    /// 上传 csv 内容导入记录, 合并到当前记录或者作为新的 archive.
+    ///
+    /// 无法解析的行不会导致失败, 而是在 [`ImportReport::issues`] 中给出.
+    pub async fn import_records(
+        &self,
+        csv_content: impl Into<String>,
+        mode: ImportMode,
+        archive_name: Option<String>,
+    ) -> crate::Result<ImportReport> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/import-records")?)
+            .query(&ImportRecordsArgs { mode, archive_name })
+            .header("Content-Type", "text/csv")
+            .body(csv_content.into())
+            .send()
+            .await?;
+        let result: CSResult<ImportReport> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 插入一条手动读数, `archive` 为 None 时插入到当前记录中.
+    pub async fn insert_record(
+        &self,
+        archive: Option<String>,
+        time: DateTime<FixedOffset>,
+        degree: f32,
+        source: impl Into<String>,
+    ) -> crate::Result<CorrectionEntry> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/insert-record")?)
+            .json(&InsertRecordArgs {
+                archive,
+                time,
+                degree,
+                source: source.into(),
+            })
+            .send()
+            .await?;
+        let result: CSResult<CorrectionEntry> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 按时间点删除读数, `archive` 为 None 时删除当前记录中的读数, `times` 不能为空.
+    pub async fn delete_records(
+        &self,
+        archive: Option<String>,
+        times: Vec<DateTime<FixedOffset>>,
+    ) -> crate::Result<CorrectionEntry> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/delete-records")?)
+            .json(&DeleteRecordsArgs { archive, times })
+            .send()
+            .await?;
+        let result: CSResult<CorrectionEntry> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 修改某一时间点的读数, `archive` 为 None 时修改当前记录中的读数.
+    pub async fn correct_record(
+        &self,
+        archive: Option<String>,
+        time: DateTime<FixedOffset>,
+        degree: f32,
+    ) -> crate::Result<CorrectionEntry> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/correct-record")?)
+            .json(&CorrectRecordArgs {
+                archive,
+                time,
+                degree,
+            })
+            .send()
+            .await?;
+        let result: CSResult<CorrectionEntry> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 撤销最后一次修正.
+    pub async fn undo_correction(&self) -> crate::Result<CorrectionEntry> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/undo-correction")?)
+            .send()
+            .await?;
+        let result: CSResult<CorrectionEntry> = resp.json().await?;
+        Ok(result?)
+    }
+
+    pub async fn list_corrections(&self) -> crate::Result<Vec<CorrectionEntry>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/list-corrections")?)
+            .send()
+            .await?;
+        let result: CSResult<Vec<CorrectionEntry>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 添加标注, `archive` 为 None 时添加到当前记录的标注中.
+    pub async fn add_annotation(
+        &self,
+        archive: Option<String>,
+        time_span: TimeSpan,
+        text: impl Into<String>,
+        tags: Vec<String>,
+    ) -> crate::Result<Annotation> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/add-annotation")?)
+            .json(&AddAnnotationArgs {
+                archive,
+                text: text.into(),
+                tags,
+                time_span,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Annotation> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 使用 `annotation` 的内容更新同编号的标注.
+    pub async fn update_annotation(
+        &self,
+        archive: Option<String>,
+        annotation: &Annotation,
+    ) -> crate::Result<Annotation> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/update-annotation")?)
+            .json(&UpdateAnnotationArgs {
+                archive,
+                id: annotation.id,
+                text: annotation.text.clone(),
+                tags: annotation.tags.clone(),
+                time_span: annotation.time_span.clone(),
+            })
+            .send()
+            .await?;
+        let result: CSResult<Annotation> = resp.json().await?;
+        Ok(result?)
+    }
+
+    pub async fn delete_annotation(&self, archive: Option<String>, id: u64) -> crate::Result<()> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/delete-annotation")?)
+            .json(&DeleteAnnotationArgs { archive, id })
+            .send()
+            .await?;
+        let result: CSResult<()> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 列出标注, `archive` 为 None 时列出当前记录的标注.
+    pub async fn list_annotations(
+        &self,
+        archive: Option<String>,
+    ) -> crate::Result<Vec<Annotation>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/list-annotations")?)
+            .query(&ListAnnotationsArgs { archive })
+            .send()
+            .await?;
+        let result: CSResult<Vec<Annotation>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 预演服务端配置的保留策略, 查看降采样可以节省的空间.
+    pub async fn retention_report(&self) -> crate::Result<RetentionReport> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/retention-report")?)
+            .send()
+            .await?;
+        let result: CSResult<RetentionReport> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 获取时间范围内按照 `grouping` 分组的用电量分布.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn get_usage_stats(
+        &self,
+        time_span: TimeSpan,
+        grouping: Grouping,
+        include_archives: bool,
+    ) -> crate::Result<UsageStats> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-usage-stats")?)
+            .query(&GetUsageStatsArgs {
+                start_time: time_span.start_time,
+                end_time: time_span.end_time,
+                span: None,
+                grouping,
+                include_archives,
+            })
+            .send()
+            .await?;
+        let result: CSResult<UsageStats> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 获取电量耗尽的预测.
+    pub async fn get_forecast(&self) -> crate::Result<Forecast> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-forecast")?)
+            .send()
+            .await?;
+        let result: CSResult<Forecast> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 计算到 `target_time` 为止需要充值的度数和费用.
+    ///
+    /// `margin` 为在预计用电量之上额外预留的比例, 如 0.1 表示 10%, 为 None 时使用服务端的默认值.
+    pub async fn plan_recharge(
+        &self,
+        target_time: DateTime<FixedOffset>,
+        margin: Option<f32>,
+    ) -> crate::Result<RechargePlan> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/plan-recharge")?)
+            .query(&PlanRechargeArgs {
+                target_time,
+                margin,
+            })
+            .send()
+            .await?;
+        let result: CSResult<RechargePlan> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 统计时间范围内的费用和碳排放, 并按月汇总.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn get_costs(
+        &self,
+        time_span: TimeSpan,
+        include_archives: bool,
+    ) -> crate::Result<CostReport> {
+        self.request_costs(&SpanArgs {
+            start_time: time_span.start_time,
+            end_time: time_span.end_time,
+            include_archives,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// 统计 archive 的费用和碳排放, 并按月汇总.
+    pub async fn get_archive_costs(&self, archive_name: String) -> crate::Result<CostReport> {
+        self.request_costs(&SpanArgs {
+            archive: Some(archive_name),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn request_costs(&self, args: &SpanArgs) -> crate::Result<CostReport> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-costs")?)
+            .query(args)
+            .send()
+            .await?;
+        let result: CSResult<CostReport> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 将时间范围内的用电量划分为基础负载和主动用电.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn get_baseline(
+        &self,
+        time_span: TimeSpan,
+        include_archives: bool,
+    ) -> crate::Result<BaselineSplit> {
+        self.request_baseline(&SpanArgs {
+            start_time: time_span.start_time,
+            end_time: time_span.end_time,
+            include_archives,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// 将 archive 的用电量划分为基础负载和主动用电.
+    pub async fn get_archive_baseline(&self, archive_name: String) -> crate::Result<BaselineSplit> {
+        self.request_baseline(&SpanArgs {
+            archive: Some(archive_name),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// 标注电器的使用时间段, 即在当前记录的时间线上添加带有电器标签的标注.
+    pub async fn tag_appliance(
+        &self,
+        name: impl AsRef<str>,
+        time_span: TimeSpan,
+        text: impl Into<String>,
+    ) -> crate::Result<Annotation> {
+        self.add_annotation(
+            None,
+            time_span,
+            text,
+            vec![format!("{APPLIANCE_TAG_PREFIX}{}", name.as_ref())],
+        )
+        .await
+    }
+
+    /// 根据标注的电器使用时间段估计时间范围内各个电器的用电量和费用.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn get_appliances(
+        &self,
+        time_span: TimeSpan,
+        include_archives: bool,
+    ) -> crate::Result<ApplianceReport> {
+        self.request_appliances(&SpanArgs {
+            start_time: time_span.start_time,
+            end_time: time_span.end_time,
+            include_archives,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// 估计某个 archive 中各个电器的用电量和费用.
+    pub async fn get_archive_appliances(&self, archive: String) -> crate::Result<ApplianceReport> {
+        self.request_appliances(&SpanArgs {
+            archive: Some(archive),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn request_appliances(&self, args: &SpanArgs) -> crate::Result<ApplianceReport> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-appliances")?)
+            .query(args)
+            .send()
+            .await?;
+        let result: CSResult<ApplianceReport> = resp.json().await?;
+        Ok(result?)
+    }
+
+    async fn request_baseline(&self, args: &SpanArgs) -> crate::Result<BaselineSplit> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-baseline")?)
+            .query(args)
+            .send()
+            .await?;
+        let result: CSResult<BaselineSplit> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 对比两段时间的用电情况.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn compare_spans(
+        &self,
+        first: TimeSpan,
+        second: TimeSpan,
+        include_archives: bool,
+    ) -> crate::Result<Comparison> {
+        self.request_comparison(&CompareArgs {
+            first_start_time: first.start_time,
+            first_end_time: first.end_time,
+            second_start_time: second.start_time,
+            second_end_time: second.end_time,
+            include_archives,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// 对比两个 archive 的用电情况.
+    pub async fn compare_archives(
+        &self,
+        first_archive: String,
+        second_archive: String,
+    ) -> crate::Result<Comparison> {
+        self.request_comparison(&CompareArgs {
+            first_archive: Some(first_archive),
+            second_archive: Some(second_archive),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn request_comparison(&self, args: &CompareArgs) -> crate::Result<Comparison> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/compare")?)
+            .query(args)
+            .send()
+            .await?;
+        let result: CSResult<Comparison> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 按照服务端时区和校历解析时间范围表达式, 如 `last:7d`, `week:this`, `semester:last`,
+    /// 得到的时间范围可以直接用于其他查询.
+    pub async fn resolve_span(&self, expr: impl AsRef<str>) -> crate::Result<TimeSpan> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/resolve-span")?)
+            .query(&ResolveSpanArgs {
+                expr: expr.as_ref().to_string(),
+            })
+            .send()
+            .await?;
+        let result: CSResult<TimeSpan> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 获取服务端配置的校历.
+    pub async fn get_calendar(&self) -> crate::Result<Vec<AcademicPeriod>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-calendar")?)
+            .send()
+            .await?;
+        let result: CSResult<Vec<AcademicPeriod>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 按照校历中与时间范围重叠的各个时间段 (可以只统计某一种类) 统计用电情况.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn get_academic_stats(
+        &self,
+        time_span: TimeSpan,
+        kind: Option<PeriodKind>,
+        include_archives: bool,
+    ) -> crate::Result<Vec<AcademicStats>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-academic-stats")?)
+            .query(&GetAcademicStatsArgs {
+                start_time: time_span.start_time,
+                end_time: time_span.end_time,
+                span: None,
+                kind,
+                include_archives,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Vec<AcademicStats>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 对比两个时间范围表达式对应的时间段, 如 `semester:2026-spring` 和 `semester:2026-fall`.
+    ///
+    /// `include_archives` 为 true 时同时统计与时间范围重叠的 archive.
+    pub async fn compare_exprs(
+        &self,
+        first: impl AsRef<str>,
+        second: impl AsRef<str>,
+        include_archives: bool,
+    ) -> crate::Result<Comparison> {
+        self.request_comparison(&CompareArgs {
+            first_span: Some(first.as_ref().to_string()),
+            second_span: Some(second.as_ref().to_string()),
+            include_archives,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// 添加室友, 编号由服务端分配.
+    pub async fn add_roommate(
+        &self,
+        name: impl Into<String>,
+        weight: Option<f32>,
+        move_in: Option<DateTime<FixedOffset>>,
+        move_out: Option<DateTime<FixedOffset>>,
+        away: Vec<TimeSpan>,
+    ) -> crate::Result<Roommate> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/add-roommate")?)
+            .json(&AddRoommateArgs {
+                name: name.into(),
+                weight,
+                move_in,
+                move_out,
+                away,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Roommate> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 使用 `roommate` 的内容更新同编号的室友.
+    pub async fn update_roommate(&self, roommate: &Roommate) -> crate::Result<Roommate> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/update-roommate")?)
+            .json(roommate)
+            .send()
+            .await?;
+        let result: CSResult<Roommate> = resp.json().await?;
+        Ok(result?)
+    }
+
+    pub async fn delete_roommate(&self, id: u64) -> crate::Result<()> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/delete-roommate")?)
+            .json(&DeleteRoommateArgs { id })
+            .send()
+            .await?;
+        let result: CSResult<()> = resp.json().await?;
+        Ok(result?)
+    }
+
+    pub async fn list_roommates(&self) -> crate::Result<Vec<Roommate>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/list-roommates")?)
+            .send()
+            .await?;
+        let result: CSResult<Vec<Roommate>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 按照室友在宿舍的时间和权重分摊时间范围内的用电量和费用.
+    pub async fn get_settlement(
+        &self,
+        time_span: TimeSpan,
+        include_archives: bool,
+    ) -> crate::Result<Settlement> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-settlement")?)
+            .query(&GetSettlementArgs {
+                span: SpanArgs {
+                    start_time: time_span.start_time,
+                    end_time: time_span.end_time,
+                    include_archives,
+                    ..Default::default()
+                },
+                csv: false,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Settlement> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 分摊 archive 的用电量和费用.
+    pub async fn get_archive_settlement(&self, archive_name: String) -> crate::Result<Settlement> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-settlement")?)
+            .query(&GetSettlementArgs {
+                span: SpanArgs {
+                    archive: Some(archive_name),
+                    ..Default::default()
+                },
+                csv: false,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Settlement> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 以 csv 的形式导出分摊表, `archive` 为 Some 时分摊 archive, 忽略 `time_span`.
+    pub async fn export_settlement_csv(
+        &self,
+        time_span: TimeSpan,
+        archive: Option<String>,
+        include_archives: bool,
+    ) -> crate::Result<String> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-settlement")?)
+            .query(&GetSettlementArgs {
+                span: SpanArgs {
+                    start_time: time_span.start_time,
+                    end_time: time_span.end_time,
+                    span: None,
+                    archive,
+                    include_archives,
+                },
+                csv: true,
+            })
+            .send()
+            .await?;
+        match resp.status() {
+            StatusCode::OK => Ok(resp.text().await?),
+            _ => Err(Error::CS(resp.json().await?)),
+        }
+    }
+
+    /// 获取与时间范围重叠的用电异常事件, `min_severity` 为 Some 时只返回不低于此严重程度的异常.
+    pub async fn get_anomalies(
+        &self,
+        time_span: TimeSpan,
+        min_severity: Option<Severity>,
+    ) -> crate::Result<Vec<AnomalyEvent>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-anomalies")?)
+            .query(&GetAnomaliesArgs {
+                start_time: time_span.start_time,
+                end_time: time_span.end_time,
+                span: None,
+                min_severity,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Vec<AnomalyEvent>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 设置离开时间段, `limit` 为 None 时使用服务端配置的默认用电上限.
+    pub async fn set_away(
+        &self,
+        start_time: DateTime<FixedOffset>,
+        end_time: DateTime<FixedOffset>,
+        limit: Option<f32>,
+        text: Option<String>,
+    ) -> crate::Result<AwayPeriod> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/set-away")?)
+            .json(&SetAwayArgs {
+                start_time,
+                end_time,
+                limit,
+                text,
+            })
+            .send()
+            .await?;
+        let result: CSResult<AwayPeriod> = resp.json().await?;
+        Ok(result?)
+    }
+
+    pub async fn cancel_away(&self, id: u64) -> crate::Result<()> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/cancel-away")?)
+            .json(&CancelAwayArgs { id })
+            .send()
+            .await?;
+        let result: CSResult<()> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 列出离开时间段及其用电报告.
+    pub async fn list_away(&self) -> crate::Result<Vec<AwayPeriod>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/list-away")?)
+            .send()
+            .await?;
+        let result: CSResult<Vec<AwayPeriod>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 设置按周或者按月的预算, 已经有相同周期和单位的预算时修改其数额.
+    ///
+    /// 以元为单位的预算需要服务端配置电价.
+    pub async fn set_budget(
+        &self,
+        period: BudgetPeriod,
+        unit: BudgetUnit,
+        amount: f32,
+    ) -> crate::Result<Budget> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/set-budget")?)
+            .json(&SetBudgetArgs {
+                period,
+                unit,
+                amount,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Budget> = resp.json().await?;
+        Ok(result?)
+    }
+
+    pub async fn delete_budget(&self, id: u64) -> crate::Result<()> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/delete-budget")?)
+            .json(&DeleteBudgetArgs { id })
+            .send()
+            .await?;
+        let result: CSResult<()> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 获取各个预算在当前周期的执行情况, 包括已用比例和按照当前速度预测的周期结束时的总量.
+    pub async fn get_budgets(&self) -> crate::Result<Vec<BudgetProgress>> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-budgets")?)
+            .send()
+            .await?;
+        let result: CSResult<Vec<BudgetProgress>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 发送一条测试告警到服务端配置的所有告警渠道, 返回各渠道的发送结果.
+    pub async fn test_alert(&self) -> crate::Result<Vec<AlertDelivery>> {
+        let resp = self
+            .client
+            .post(self.server_base.join("/test-alert")?)
+            .send()
+            .await?;
+        let result: CSResult<Vec<AlertDelivery>> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 按照当前的记录生成用电摘要.
+    pub async fn get_digest(&self, schedule: DigestSchedule) -> crate::Result<Digest> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-digest")?)
+            .query(&GetDigestArgs {
+                schedule,
+                format: DigestFormat::Json,
+            })
+            .send()
+            .await?;
+        let result: CSResult<Digest> = resp.json().await?;
+        Ok(result?)
+    }
+
+    /// 获取渲染为纯文本或者 HTML 的用电摘要, `format` 为 [`DigestFormat::Json`] 时返回 JSON 文本.
+    pub async fn render_digest(
+        &self,
+        schedule: DigestSchedule,
+        format: DigestFormat,
+    ) -> crate::Result<String> {
+        let resp = self
+            .client
+            .get(self.server_base.join("/get-digest")?)
+            .query(&GetDigestArgs { schedule, format })
+            .send()
+            .await?;
+        match resp.status() {
+            StatusCode::OK => Ok(resp.text().await?),
+            _ => Err(Error::CS(resp.json().await?)),
+        }
+    }
+
+    /// 订阅服务端事件, `last_event_id` 为上次收到的最后一个事件编号.
+    ///
+    /// 连接断开后自动重新连接, 并从最后收到的事件之后续接. 错过的事件无法补发时收到 [`ServerEvent::Resync`][crate::server::ServerEvent::Resync],
+    /// 此时应当重新获取完整的状态. 出错时流中返回错误, 之后继续重试, 流本身不会结束.
+    ///
+    /// # Errors
+    /// - [`Error::Reqwest`][]: see [`reqwest::RequestBuilder::send`], [`reqwest::Response::chunk`].
+    /// - [`Error::SerdeJson`][]: 无法解析的事件.
+    /// - [`Error::UrlParse`][]: 服务端地址无效时, 流只返回这一个错误.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<String>,
+    ) -> impl Stream<Item = crate::Result<EventEnvelope>> + use<> {
+        let url = match self.server_base.join("/subscribe") {
+            Ok(url) => url,
+            Err(e) => return stream::once(async move { Err(e.into()) }).left_stream(),
+        };
+        let subscription = Subscription {
+            client: self.client.clone(),
+            url,
+            last_event_id,
+            resp: None,
+            buf: Vec::new(),
+            pending: VecDeque::new(),
+            retry: false,
+        };
+        stream::unfold(subscription, |mut subscription| async move {
+            let item = subscription.next().await;
+            Some((item, subscription))
+        })
+        .right_stream()
+    }
+
     pub fn set_server_base(&mut self, server_base: Url) {
         self.server_base = server_base;
     }
@@ -536,13 +1533,64 @@ impl GuardClient {
 mod tests {
     use chromiumoxide::BrowserConfig;
 
+    use axum::{Router, http::HeaderMap, routing::get};
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+
     use crate::{
         CSError, Error,
-        client::{BrowserExecutor, Client, GuardClient},
+        client::{BrowserExecutor, Client, GuardClient, parse_sse_block},
         config::RoomConfig,
         rooms::{Area, Building, District, Districts, Floor, Room, RoomInfo},
+        server::ServerEvent,
     };
 
+    #[test]
+    fn sse_block() {
+        assert_eq!(
+            parse_sse_block("id: 1-2\ndata: {\"kind\":\n:comment\ndata:\"resync\"}\n"),
+            Some((
+                Some("1-2".to_string()),
+                "{\"kind\":\n\"resync\"}".to_string()
+            ))
+        );
+        assert_eq!(parse_sse_block(":\n"), None);
+    }
+
+    #[tokio::test]
+    async fn subscribe_resumes_after_reconnect() {
+        // 每次连接只发送一个事件就断开, 第二次连接时应当带上第一个事件的编号.
+        let app = Router::new().route(
+            "/subscribe",
+            get(|headers: HeaderMap| async move {
+                match headers.get("Last-Event-ID") {
+                    None => "id: 7-1\ndata: {\"kind\":\"login-restored\"}\n\n".to_string(),
+                    Some(id) => format!(
+                        ":\r\n\r\nid: 7-2\r\ndata: {{\"kind\":\"login-lost\",\"error\":\"{}\"}}\r\n\r\n",
+                        id.to_str().unwrap()
+                    ),
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = Client::new(format!("http://127.0.0.1:{port}").parse().unwrap());
+        let mut events = Box::pin(client.subscribe(None));
+        let first = events.next().await.unwrap().unwrap();
+        assert_eq!(first.id, "7-1");
+        assert_eq!(first.event, ServerEvent::LoginRestored);
+        let second = events.next().await.unwrap().unwrap();
+        assert_eq!(second.id, "7-2");
+        assert_eq!(
+            second.event,
+            ServerEvent::LoginLost {
+                error: "7-1".to_string()
+            }
+        );
+    }
+
     #[tokio::test]
     async fn list_archives() {
         let client = Client::new("http://localhost:20531".parse().unwrap());
@@ -1,5 +1,10 @@
-use crate::{CSError, error::Error};
-use serde::{Deserialize, Serialize};
+use crate::{
+    CSError,
+    analytics::{Accounting, Severity},
+    error::Error,
+};
+use chrono::{NaiveDate, Weekday};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
     io,
     net::SocketAddr,
@@ -21,6 +26,292 @@ pub(crate) struct ServerTlsConfig {
     pub(crate) root_ca: PathBuf,
 }
 
+/// 降采样之后保留的时间分辨率.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Resolution {
+    Hourly,
+    Daily,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct RetentionConfig {
+    /// 原始记录保留的天数, 更早的记录会被降采样.
+    pub(crate) keep_raw_days: u32,
+    pub(crate) resolution: Resolution,
+    /// 后台执行保留策略的间隔 (小时).
+    #[serde(default = "default_retention_interval_hours")]
+    pub(crate) interval_hours: u64,
+}
+
+fn default_retention_interval_hours() -> u64 {
+    24
+}
+
+/// 分时电价的一个时段, 按照服务端时区的整点划分.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct TariffWindow {
+    /// 开始的整点 (包含).
+    #[serde(deserialize_with = "deserialize_hour")]
+    pub(crate) start: u32,
+    /// 结束的整点 (不包含), 不大于 `start` 时表示跨越午夜, 结束于午夜时为 0.
+    #[serde(deserialize_with = "deserialize_hour")]
+    pub(crate) end: u32,
+    pub(crate) price: f32,
+}
+
+/// 电价配置.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct TariffConfig {
+    /// 每度电的价格 (元), 配置了分时电价时为各时段之外的价格.
+    pub(crate) price: f32,
+    /// 分时电价 (峰谷电价) 时段, 重叠时后面的时段优先.
+    #[serde(default)]
+    pub(crate) windows: Vec<TariffWindow>,
+}
+
+impl TariffConfig {
+    /// 每天各个小时的电价.
+    pub(crate) fn hourly_prices(&self) -> [f32; 24] {
+        let mut prices = [self.price; 24];
+        for window in &self.windows {
+            let mut hour = window.start;
+            loop {
+                prices[hour as usize] = window.price;
+                hour = (hour + 1) % 24;
+                if hour == window.end {
+                    break;
+                }
+            }
+        }
+        prices
+    }
+}
+
+/// 校历中时间段的种类.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeriodKind {
+    /// 学期.
+    #[default]
+    Semester,
+    /// 假期, 如寒假, 暑假.
+    Break,
+    /// 考试周.
+    Exam,
+}
+
+/// 校历中的一个时间段, 起止日期都包含在内, 按照服务端时区划分.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AcademicPeriod {
+    /// 如 `2026-fall`, `2027-winter-break`, 需要唯一.
+    pub name: String,
+    #[serde(default)]
+    pub kind: PeriodKind,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+/// 告警规则, 条件满足时告警, 同一规则在冷却时间内不会重复告警.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum AlertRule {
+    /// 剩余度数低于阈值 (度).
+    LowDegree { threshold: f32 },
+    /// 检测到充值, 每次充值告警一次, 不受冷却时间限制.
+    Recharge,
+    /// 服务端的 ECNU 登录失效, 需要重新登录.
+    SessionExpired,
+    /// 查询电量连续失败 (不包括登录失效) 达到次数.
+    UpstreamFailure {
+        #[serde(default = "default_upstream_failures")]
+        failures: u32,
+    },
+    /// 最近一段时间的平均功率超过阈值 (度/小时).
+    ConsumptionJump {
+        rate: f32,
+        #[serde(default = "default_jump_window_mins")]
+        window_mins: u32,
+    },
+    /// 检测到严重程度不低于 `min_severity` (默认不限) 的用电异常, 每个异常告警一次, 不受冷却时间限制.
+    Anomaly {
+        #[serde(default)]
+        min_severity: Option<Severity>,
+    },
+    /// 离开期间的用电量超过上限, 以及离开时间段结束, 各告警一次, 不受冷却时间限制.
+    Away,
+    /// 预计会超出预算, 以及已经超出预算, 每个周期各告警一次, 不受冷却时间限制.
+    Budget,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct AlertRuleConfig {
+    #[serde(flatten)]
+    pub(crate) rule: AlertRule,
+    /// 同一规则两次告警之间的最短间隔 (分钟).
+    #[serde(default = "default_alert_cooldown_mins")]
+    pub(crate) cooldown_mins: u32,
+}
+
+/// SMTP 连接的加密方式.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum SmtpSecurity {
+    /// 不加密, 只应该用于本机或者内网的邮件服务器.
+    None,
+    /// 明文连接后通过 STARTTLS 升级, 默认端口 587.
+    StartTls,
+    /// 直接使用 TLS 连接, 默认端口 465.
+    #[default]
+    Tls,
+}
+
+/// 告警的发送渠道.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub(crate) enum AlertChannelConfig {
+    /// 以 JSON 的形式 POST 告警.
+    Webhook { url: String },
+    /// 通过 SMTP 发送邮件.
+    Smtp {
+        host: String,
+        /// 为 None 时使用加密方式对应的默认端口.
+        #[serde(default)]
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    /// Bark, ServerChan 等推送地址, 其中的 `{title}` 和 `{body}` 会被替换为 url 编码后的标题和内容, 然后 GET 此地址.
+    Push { url: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct AlertConfig {
+    #[serde(default)]
+    pub(crate) rules: Vec<AlertRuleConfig>,
+    #[serde(default)]
+    pub(crate) channels: Vec<AlertChannelConfig>,
+}
+
+/// 用电摘要的周期.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DigestSchedule {
+    /// 每天发送, 统计前一天.
+    #[default]
+    Daily,
+    /// 每周发送, 统计最近 7 天.
+    Weekly,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct DigestConfig {
+    #[serde(default)]
+    pub(crate) schedule: DigestSchedule,
+    /// 发送的整点 (0 ~ 23), 按照服务端时区.
+    #[serde(default = "default_digest_hour", deserialize_with = "deserialize_hour")]
+    pub(crate) hour: u32,
+    /// 每周摘要的发送日, 如 `mon`.
+    #[serde(default = "default_digest_weekday")]
+    pub(crate) weekday: Weekday,
+    /// 发送渠道, 与告警渠道的配置相同.
+    #[serde(default)]
+    pub(crate) channels: Vec<AlertChannelConfig>,
+}
+
+/// MQTT 连接的 tls 配置.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub(crate) struct MqttTlsConfig {
+    /// 为 None 时使用内置的公共根证书.
+    #[serde(default)]
+    pub(crate) root_ca: Option<PathBuf>,
+    /// 客户端证书和密钥, 只在 broker 要求客户端证书时需要.
+    #[serde(default)]
+    pub(crate) client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub(crate) client_key: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct MqttConfig {
+    pub(crate) host: String,
+    /// 为 None 时使用默认端口, 不启用 tls 时为 1883, 否则为 8883.
+    #[serde(default)]
+    pub(crate) port: Option<u16>,
+    #[serde(default)]
+    pub(crate) username: Option<String>,
+    #[serde(default)]
+    pub(crate) password: Option<String>,
+    #[serde(default = "default_mqtt_client_id")]
+    pub(crate) client_id: String,
+    /// 状态主题的前缀, 如 `epu/degree`.
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub(crate) topic_prefix: String,
+    /// 是否发布 Home Assistant 自动发现配置.
+    #[serde(default = "default_mqtt_discovery")]
+    pub(crate) discovery: bool,
+    /// Home Assistant 自动发现的主题前缀.
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub(crate) discovery_prefix: String,
+    /// 为 Some 时通过 tls 连接 broker.
+    #[serde(default)]
+    pub(crate) tls: Option<MqttTlsConfig>,
+}
+
+fn default_mqtt_client_id() -> String {
+    "epu-server".to_string()
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "epu".to_string()
+}
+
+fn default_mqtt_discovery() -> bool {
+    true
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_digest_hour() -> u32 {
+    8
+}
+
+/// 整点, 超出 0 ~ 23 时拒绝加载配置.
+fn deserialize_hour<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let hour = u32::deserialize(deserializer)?;
+    if hour < 24 {
+        Ok(hour)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "hour must be within 0..=23, got {hour}"
+        )))
+    }
+}
+
+fn default_digest_weekday() -> Weekday {
+    Weekday::Mon
+}
+
+fn default_upstream_failures() -> u32 {
+    30
+}
+
+fn default_jump_window_mins() -> u32 {
+    60
+}
+
+fn default_alert_cooldown_mins() -> u32 {
+    360
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct ServerConfig {
     // 一旦为 Some, 自动启用 tls.
@@ -28,6 +319,30 @@ pub(crate) struct ServerConfig {
     pub(crate) tls_config: Option<ServerTlsConfig>,
     #[serde(default = "default_bind_address", rename = "bind")]
     pub(crate) bind_address: SocketAddr,
+    /// 为 None 时原始记录永久保留.
+    #[serde(default)]
+    pub(crate) retention: Option<RetentionConfig>,
+    /// 为 None 时不估算费用.
+    #[serde(default)]
+    pub(crate) tariff: Option<TariffConfig>,
+    /// 电网排放因子 (kg CO2/度), 为 None 时不估算碳排放.
+    #[serde(default)]
+    pub(crate) emission_factor: Option<f32>,
+    /// 离开模式默认允许超出基础负载的用电量 (度).
+    #[serde(default = "default_away_limit")]
+    pub(crate) away_limit: f32,
+    /// 校历, 用于 `semester:`, `break:` 和 `exam:` 时间范围表达式, 以及按照校历时间段统计.
+    #[serde(default)]
+    pub(crate) calendar: Vec<AcademicPeriod>,
+    /// 为 None 时不告警.
+    #[serde(default)]
+    pub(crate) alerts: Option<AlertConfig>,
+    /// 为 None 时不定期发送用电摘要, 仍然可以通过接口获取.
+    #[serde(default)]
+    pub(crate) digest: Option<DigestConfig>,
+    /// 为 None 时不连接 MQTT broker.
+    #[serde(default)]
+    pub(crate) mqtt: Option<MqttConfig>,
 }
 
 impl Default for ServerConfig {
@@ -36,11 +351,22 @@ impl Default for ServerConfig {
     }
 }
 
+fn default_away_limit() -> f32 {
+    1.0
+}
+
 fn default_bind_address() -> SocketAddr {
     "0.0.0.0:20531".parse().unwrap()
 }
 
 impl ServerConfig {
+    pub(crate) fn accounting(&self) -> Accounting {
+        Accounting {
+            hourly_prices: self.tariff.as_ref().map(TariffConfig::hourly_prices),
+            emission_factor: self.emission_factor,
+        }
+    }
+
     pub(crate) async fn from_toml_file(
         file: impl AsRef<Path>,
         create_new: bool,
@@ -149,3 +475,59 @@ pub(crate) const SERVER_CONFIG_FILENAME: &str = "server.toml";
 pub(crate) const DELETED_DIRNAME: &str = "deleted";
 pub(crate) const ROOMS_DIRNAME: &str = "rooms";
 pub(crate) const ROOM_UNKNOWN_DIRNAME: &str = "unknown";
+pub(crate) const CORRECTIONS_FILENAME: &str = "corrections.jsonl";
+pub(crate) const ANNOTATIONS_FILENAME: &str = "annotations.json";
+pub(crate) const ROOMMATES_FILENAME: &str = "roommates.json";
+pub(crate) const ANOMALIES_FILENAME: &str = "anomalies.json";
+pub(crate) const AWAY_FILENAME: &str = "away.json";
+pub(crate) const BUDGETS_FILENAME: &str = "budgets.json";
+pub(crate) const LAYOUT_FILENAME: &str = "layout.toml";
+pub(crate) const BACKUPS_DIRNAME: &str = "backups";
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{ServerConfig, TariffWindow};
+
+    #[test]
+    fn time_of_use_prices() {
+        let config: ServerConfig = toml::from_str(
+            r"
+            emission_factor = 0.57
+            [tariff]
+            price = 0.6
+            [[tariff.windows]]
+            start = 22
+            end = 6
+            price = 0.3
+            ",
+        )
+        .unwrap();
+        let prices = config.tariff.as_ref().unwrap().hourly_prices();
+        assert!(prices[..6].iter().all(|&p| (p - 0.3).abs() < f32::EPSILON));
+        assert!(
+            prices[6..22]
+                .iter()
+                .all(|&p| (p - 0.6).abs() < f32::EPSILON)
+        );
+        assert!(prices[22..].iter().all(|&p| (p - 0.3).abs() < f32::EPSILON));
+        assert_eq!(config.accounting().emission_factor, Some(0.57));
+    }
+
+    #[test]
+    fn digest_hour_range() {
+        let parse = |hour: u32| toml::from_str::<ServerConfig>(&format!("[digest]\nhour = {hour}"));
+        assert_eq!(parse(23).unwrap().digest.unwrap().hour, 23);
+        assert!(parse(24).is_err());
+        assert!(parse(30).is_err());
+    }
+
+    #[test]
+    fn tariff_window_hours() {
+        let window = |start: u32, end: u32| {
+            toml::from_str::<TariffWindow>(&format!("start = {start}\nend = {end}\nprice = 0.3"))
+        };
+        assert!(window(22, 0).is_ok());
+        assert!(window(25, 6).is_err());
+        assert!(window(22, 24).is_err());
+    }
+}
@@ -42,6 +42,14 @@ pub enum Error {
     Utf8(#[from] FromUtf8Error),
     #[error(transparent)]
     Log(#[from] tracing_appender::rolling::InitError),
+    #[error(transparent)]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error(transparent)]
+    Mail(#[from] lettre::error::Error),
+    #[error(transparent)]
+    MailAddress(#[from] lettre::address::AddressError),
+    #[error("data dir layout version {0} is newer than the supported version {1}, please upgrade")]
+    LayoutTooNew(u32, u32),
 }
 
 /// Client-Server error
@@ -92,6 +100,52 @@ pub enum CSError {
     InvalidCookies,
     #[error("server failed to send request")]
     ServerRequestError,
+    #[error("writing records failed")]
+    WriteRecords,
+    #[error("record not found at the given time")]
+    RecordNotFound,
+    #[error("a record already exists at the given time")]
+    DuplicatedRecord,
+    #[error("no correction to undo")]
+    NothingToUndo,
+    #[error("no records given to delete")]
+    NoRecordsToDelete,
+    #[error("reading or writing correction journal failed")]
+    CorrectionJournal,
+    #[error("annotation not found")]
+    AnnotationNotFound,
+    #[error("reading or writing annotations failed")]
+    AnnotationStore,
+    #[error("retention policy is not configured")]
+    RetentionDisabled,
+    #[error("not enough records to analyze")]
+    InsufficientRecords,
+    #[error("target time must be later than the last record")]
+    InvalidTargetTime,
+    #[error("roommate not found")]
+    RoommateNotFound,
+    #[error("reading or writing roommates failed")]
+    RoommateStore,
+    #[error("reading or writing anomalies failed")]
+    AnomalyStore,
+    #[error("away period not found")]
+    AwayNotFound,
+    #[error("reading or writing away periods failed")]
+    AwayStore,
+    #[error("invalid away period")]
+    InvalidAwayPeriod,
+    #[error("invalid time span expression: {0}")]
+    InvalidSpanExpr(String),
+    #[error("budget not found")]
+    BudgetNotFound,
+    #[error("reading or writing budgets failed")]
+    BudgetStore,
+    #[error("invalid budget, or money budget without a tariff")]
+    InvalidBudget,
+    #[error("alerting is not configured")]
+    AlertsDisabled,
+    #[error("unknown grafana target")]
+    UnknownGrafanaTarget,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -8,6 +8,7 @@ use std::{
 };
 use tokio::{fs::File, io::AsyncRead};
 
+pub mod analytics;
 pub mod client;
 pub mod config;
 pub mod error;
@@ -0,0 +1,794 @@
+//! 服务端告警.
+//!
+//! 在 `server.toml` 的 `[alerts]` 中配置告警规则和发送渠道, 服务端每分钟根据最新的记录和后台查询的状态检查一次规则,
+//! 触发的告警发送到所有渠道. 同一规则两次告警之间至少间隔 `cooldown_mins` 分钟, 冷却时间只保存在内存中,
+//! 服务端重启后重新计算.
+//!
+//! 用电异常, 离开模式和预算的规则不参与定时检查, 而是在 [`EventHub`] 收到对应的事件时立即告警.
+//!
+//! [`EventHub`]: crate::server::event::EventHub
+//!
+//! 渠道包括 JSON webhook, SMTP 邮件, 以及 Bark, ServerChan 等通过 GET 请求推送的地址.
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Local, TimeDelta};
+use lettre::message::MultiPart;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+use crate::analytics::{self, Severity};
+use crate::config::{AlertChannelConfig, AlertRule, AlertRuleConfig, SmtpSecurity};
+use crate::server::{
+    AppState, AwayPeriod, BudgetPeriod, BudgetProgress, BudgetUnit, QueryStatus, ServerEvent,
+};
+use crate::{CSError, TimeSpan};
+
+/// 检查间隔.
+const CHECK_INTERVAL: Duration = Duration::from_mins(1);
+
+/// 发送告警的超时时间.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// 触发告警的规则, 如 `low-degree`, 测试告警为 `test`.
+    pub rule: String,
+    pub title: String,
+    pub message: String,
+    pub time: DateTime<FixedOffset>,
+}
+
+/// 告警发送到某个渠道的结果.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AlertDelivery {
+    /// 渠道在配置中的序号, 从 0 开始.
+    pub index: usize,
+    /// 渠道的类型, 如 `webhook`.
+    pub kind: String,
+    /// 为 None 时发送成功.
+    pub error: Option<String>,
+}
+
+impl AlertRule {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertRule::LowDegree { .. } => "low-degree",
+            AlertRule::Recharge => "recharge",
+            AlertRule::SessionExpired => "session-expired",
+            AlertRule::UpstreamFailure { .. } => "upstream-failure",
+            AlertRule::ConsumptionJump { .. } => "consumption-jump",
+            AlertRule::Anomaly { .. } => "anomaly",
+            AlertRule::Away => "away",
+            AlertRule::Budget => "budget",
+        }
+    }
+}
+
+impl AlertChannelConfig {
+    fn kind(&self) -> &'static str {
+        match self {
+            AlertChannelConfig::Webhook { .. } => "webhook",
+            AlertChannelConfig::Smtp { .. } => "smtp",
+            AlertChannelConfig::Push { .. } => "push",
+        }
+    }
+}
+
+/// 规则检查的状态, 在两次检查之间保留.
+#[derive(Debug, Default)]
+struct RuleState {
+    /// 各条规则最近一次告警的时间, 与配置中的规则一一对应.
+    last_fired: Vec<Option<DateTime<FixedOffset>>>,
+    /// 已经告警 (或者启动时已经存在) 的最后一次充值的时间.
+    last_recharge: Option<Option<DateTime<FixedOffset>>>,
+}
+
+/// 检查规则, 返回触发的告警, 并更新 `state` 中的冷却时间.
+fn check_rules(
+    rules: &[AlertRuleConfig],
+    state: &mut RuleState,
+    records: &[(DateTime<FixedOffset>, f32)],
+    status: &QueryStatus,
+    now: DateTime<FixedOffset>,
+) -> Vec<Alert> {
+    state.last_fired.resize(rules.len(), None);
+    let last_recharge = analytics::recharges(records).last().cloned();
+    // 第一次检查时已经存在的充值不告警.
+    let seen_recharge = *state
+        .last_recharge
+        .get_or_insert(last_recharge.as_ref().map(|r| r.time));
+    let mut alerts = Vec::new();
+    for (rule_config, last_fired) in rules.iter().zip(&mut state.last_fired) {
+        let rule = &rule_config.rule;
+        if !matches!(rule, AlertRule::Recharge)
+            && last_fired
+                .is_some_and(|t| now - t < TimeDelta::minutes(rule_config.cooldown_mins.into()))
+        {
+            continue;
+        }
+        let fired = match rule {
+            AlertRule::LowDegree { threshold } => records
+                .last()
+                .filter(|(_, degree)| degree < threshold)
+                .map(|(time, degree)| {
+                    (
+                        "宿舍电量不足".to_string(),
+                        format!(
+                            "当前剩余电量: {degree:.2} 度, 低于 {threshold:.2} 度, 记录时间: {}",
+                            time.format("%Y-%m-%d %H:%M")
+                        ),
+                    )
+                }),
+            AlertRule::Recharge => last_recharge
+                .as_ref()
+                .filter(|r| seen_recharge.is_none_or(|t| r.time > t))
+                .map(|r| {
+                    (
+                        "宿舍电费已充值".to_string(),
+                        format!(
+                            "充值 {:.2} 度, 充值后剩余电量: {:.2} 度, 记录时间: {}",
+                            r.amount,
+                            r.after,
+                            r.time.format("%Y-%m-%d %H:%M")
+                        ),
+                    )
+                }),
+            AlertRule::SessionExpired => status.logged_out.then(|| {
+                (
+                    "ECNU 登录已失效".to_string(),
+                    "服务端无法查询电量, 请重新登录".to_string(),
+                )
+            }),
+            AlertRule::UpstreamFailure { failures } => (status.consecutive_failures
+                >= *failures)
+                .then(|| {
+                    let last_success = status.last_success.map_or_else(
+                        || "无".to_string(),
+                        |t| t.format("%Y-%m-%d %H:%M").to_string(),
+                    );
+                    (
+                        "电量查询连续失败".to_string(),
+                        format!(
+                            "已连续失败 {} 次, 最近一次成功: {last_success}, 错误: {}",
+                            status.consecutive_failures,
+                            status.last_error.as_deref().unwrap_or("未知")
+                        ),
+                    )
+                }),
+            AlertRule::ConsumptionJump { rate, window_mins } => {
+                jump_rate(records, *window_mins)
+                    .filter(|r| r > rate)
+                    .map(|r| {
+                        (
+                            "宿舍用电量突增".to_string(),
+                            format!(
+                                "最近 {window_mins} 分钟的平均功率为 {r:.2} 度/小时, 超过 {rate:.2} 度/小时"
+                            ),
+                        )
+                    })
+            }
+            // 由事件触发, 见 [`event_alerts`].
+            AlertRule::Anomaly { .. } | AlertRule::Away | AlertRule::Budget => None,
+        };
+        if let Some((title, message)) = fired {
+            *last_fired = Some(now);
+            alerts.push(Alert {
+                rule: rule.name().to_string(),
+                title,
+                message,
+                time: now,
+            });
+        }
+    }
+    state.last_recharge = Some(last_recharge.map(|r| r.time).or(seen_recharge));
+    alerts
+}
+
+/// 事件对应的告警. 这些事件在产生时已经去重, 因此不受冷却时间限制.
+fn event_alerts(
+    rules: &[AlertRuleConfig],
+    event: &ServerEvent,
+    now: DateTime<FixedOffset>,
+) -> Vec<Alert> {
+    let format_time = |t: &DateTime<FixedOffset>| t.format("%Y-%m-%d %H:%M").to_string();
+    let away_report = |period: &AwayPeriod| {
+        period
+            .report
+            .as_ref()
+            .map_or((0.0, 0.0, 0.0), |r| (r.consumption, r.expected, r.excess))
+    };
+    let budget_text = |progress: &BudgetProgress| {
+        let period = match progress.budget.period {
+            BudgetPeriod::Weekly => "本周",
+            BudgetPeriod::Monthly => "本月",
+        };
+        let unit = match progress.budget.unit {
+            BudgetUnit::Kwh => "度",
+            BudgetUnit::Money => "元",
+        };
+        let mut text = format!(
+            "{period}预算 {:.2} {unit}, 已用 {:.2} {unit}",
+            progress.budget.amount, progress.spent
+        );
+        if let Some(projected) = progress.projected {
+            text.push_str(&format!(
+                ", 按照当前速度预计周期结束时达到 {projected:.2} {unit}"
+            ));
+        }
+        text
+    };
+    rules
+        .iter()
+        .filter_map(|rule_config| {
+            let rule = &rule_config.rule;
+            let (title, message) = match (rule, event) {
+                (AlertRule::Anomaly { min_severity }, ServerEvent::Anomaly(e))
+                    if min_severity.is_none_or(|s| e.anomaly.severity >= s) =>
+                {
+                    let severity = match e.anomaly.severity {
+                        Severity::Low => "低",
+                        Severity::Medium => "中",
+                        Severity::High => "高",
+                    };
+                    (
+                        "宿舍用电异常",
+                        format!(
+                            "{} ~ {} 用电 {:.2} 度, 按照用电习惯预期 {:.2} 度, 严重程度: {severity}",
+                            format_time(&e.anomaly.start_time),
+                            format_time(&e.anomaly.end_time),
+                            e.anomaly.consumption,
+                            e.anomaly.expected
+                        ),
+                    )
+                }
+                (AlertRule::Away, ServerEvent::AwayLimitExceeded(period)) => (
+                    "离开期间用电超过上限",
+                    format!(
+                        "{} 离开以来超出基础负载的用电量为 {:.2} 度, 超过上限 {:.2} 度",
+                        format_time(&period.start_time),
+                        away_report(period).2,
+                        period.limit
+                    ),
+                ),
+                (AlertRule::Away, ServerEvent::AwayEnded(period)) => {
+                    let (consumption, expected, excess) = away_report(period);
+                    (
+                        "离开时间段结束",
+                        format!(
+                            "{} ~ {} 共用电 {consumption:.2} 度, 按照基础负载预期 {expected:.2} 度, 超出 {excess:.2} 度",
+                            format_time(&period.start_time),
+                            format_time(&period.end_time)
+                        ),
+                    )
+                }
+                (AlertRule::Budget, ServerEvent::BudgetOverPace(progress)) => {
+                    ("宿舍用电预计超出预算", budget_text(progress))
+                }
+                (AlertRule::Budget, ServerEvent::BudgetExceeded(progress)) => {
+                    ("宿舍用电已超出预算", budget_text(progress))
+                }
+                _ => return None,
+            };
+            Some(Alert {
+                rule: rule.name().to_string(),
+                title: title.to_string(),
+                message,
+                time: now,
+            })
+        })
+        .collect()
+}
+
+/// 最后一条记录之前 `window_mins` 分钟内的平均功率 (度/小时), 记录不足一个窗口时为 None.
+fn jump_rate(records: &[(DateTime<FixedOffset>, f32)], window_mins: u32) -> Option<f32> {
+    let window = TimeDelta::minutes(window_mins.into());
+    let (first, _) = records.first()?;
+    let (last, _) = records.last()?;
+    if window <= TimeDelta::zero() || *last - window < *first {
+        return None;
+    }
+    let time_span = TimeSpan::new(Some(*last - window), Some(*last));
+    #[allow(clippy::cast_precision_loss)]
+    let hours = window.num_seconds() as f32 / 3600.0;
+    Some(analytics::consumption(records, &time_span) / hours)
+}
+
+/// url 编码, 保留 RFC 3986 中的非保留字符.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// 发送到渠道的通知, 告警和用电摘要共用.
+pub(crate) struct Notice<'a, T> {
+    pub(crate) title: &'a str,
+    /// 纯文本内容, 用于推送和邮件.
+    pub(crate) text: &'a str,
+    /// 邮件的 HTML 内容, 为 None 时邮件只包含纯文本.
+    pub(crate) html: Option<&'a str>,
+    /// webhook POST 的 JSON 内容.
+    pub(crate) payload: &'a T,
+}
+
+impl Alert {
+    fn notice(&self) -> Notice<'_, Self> {
+        Notice {
+            title: &self.title,
+            text: &self.message,
+            html: None,
+            payload: self,
+        }
+    }
+}
+
+async fn deliver<T: Serialize>(
+    client: &reqwest::Client,
+    channel: &AlertChannelConfig,
+    notice: &Notice<'_, T>,
+) -> crate::Result<()> {
+    match channel {
+        AlertChannelConfig::Webhook { url } => {
+            client
+                .post(url)
+                .json(notice.payload)
+                .timeout(DELIVERY_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        AlertChannelConfig::Push { url } => {
+            let url = url
+                .replace("{title}", &percent_encode(notice.title))
+                .replace("{body}", &percent_encode(notice.text));
+            client
+                .get(url)
+                .timeout(DELIVERY_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        AlertChannelConfig::Smtp {
+            host,
+            port,
+            security,
+            username,
+            password,
+            from,
+            to,
+        } => {
+            let builder = match security {
+                SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+                    .port(port.unwrap_or(25)),
+                SmtpSecurity::StartTls => {
+                    AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+                        .port(port.unwrap_or(587))
+                }
+                SmtpSecurity::Tls => {
+                    AsyncSmtpTransport::<Tokio1Executor>::relay(host)?.port(port.unwrap_or(465))
+                }
+            };
+            let builder = match (username, password) {
+                (Some(username), Some(password)) => {
+                    builder.credentials(Credentials::new(username.clone(), password.clone()))
+                }
+                _ => builder,
+            };
+            let transport = builder.timeout(Some(DELIVERY_TIMEOUT)).build();
+            let mut message = Message::builder().from(from.parse()?).subject(notice.title);
+            for to in to {
+                message = message.to(to.parse()?);
+            }
+            let message = match notice.html {
+                Some(html) => message.multipart(MultiPart::alternative_plain_html(
+                    notice.text.to_string(),
+                    html.to_string(),
+                ))?,
+                None => message
+                    .header(ContentType::TEXT_PLAIN)
+                    .body(notice.text.to_string())?,
+            };
+            transport.send(message).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 把通知发送到所有渠道, 返回各渠道的发送结果.
+pub(crate) async fn deliver_all<T: Serialize>(
+    client: &reqwest::Client,
+    channels: &[AlertChannelConfig],
+    notice: &Notice<'_, T>,
+) -> Vec<AlertDelivery> {
+    let mut deliveries = Vec::with_capacity(channels.len());
+    for (index, channel) in channels.iter().enumerate() {
+        let error = deliver(client, channel, notice).await.err().map(|e| {
+            error!(target: "delivery", "{} #{index}: {e:?}", channel.kind());
+            e.to_string()
+        });
+        deliveries.push(AlertDelivery {
+            index,
+            kind: channel.kind().to_string(),
+            error,
+        });
+    }
+    deliveries
+}
+
+/// 发送一条测试告警到所有渠道.
+pub(crate) async fn test(state: &AppState) -> Result<Vec<AlertDelivery>, CSError> {
+    let config = state
+        .server_config
+        .alerts
+        .as_ref()
+        .ok_or(CSError::AlertsDisabled)?;
+    let alert = Alert {
+        rule: "test".to_string(),
+        title: "测试告警".to_string(),
+        message: "这是一条测试告警, 收到此消息说明告警渠道配置正确".to_string(),
+        time: Local::now().fixed_offset(),
+    };
+    Ok(deliver_all(&reqwest::Client::new(), &config.channels, &alert.notice()).await)
+}
+
+pub(crate) async fn alert_loop(state: Arc<AppState>) -> ! {
+    let config = state
+        .server_config
+        .alerts
+        .clone()
+        .expect("alert loop requires alert config");
+    let client = reqwest::Client::new();
+    let mut rule_state = RuleState::default();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut rx = state.events.subscribe();
+    loop {
+        let alerts = tokio::select! {
+            _ = interval.tick() => {
+                let records = match state.recorder.write().await.read_records().await {
+                    Ok(mut records) => {
+                        records.sort();
+                        records
+                    }
+                    Err(e) => {
+                        error!(target: "reading records", "{e:?}");
+                        continue;
+                    }
+                };
+                let status = state.query_status.read().await.clone();
+                check_rules(
+                    &config.rules,
+                    &mut rule_state,
+                    &records,
+                    &status,
+                    Local::now().fixed_offset(),
+                )
+            }
+            event = rx.recv() => match event {
+                Ok(envelope) => {
+                    event_alerts(&config.rules, &envelope.event, Local::now().fixed_offset())
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("alert: {n} events skipped");
+                    continue;
+                }
+                Err(RecvError::Closed) => unreachable!("event sender lives as long as app state"),
+            },
+        };
+        for alert in alerts {
+            warn!("alert: {}: {}", alert.title, alert.message);
+            let deliveries = deliver_all(&client, &config.channels, &alert.notice()).await;
+            let delivered = deliveries.iter().filter(|d| d.error.is_none()).count();
+            info!(
+                "alert delivered to {delivered}/{} channels",
+                deliveries.len()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::Router;
+    use axum::extract::{Query, State};
+    use axum::routing::{get, post};
+    use chrono::{FixedOffset, TimeDelta, TimeZone};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::analytics::{Anomaly, Severity};
+    use crate::config::{AlertChannelConfig, AlertRule, AlertRuleConfig, SmtpSecurity};
+    use crate::server::alert::{
+        Alert, RuleState, check_rules, deliver_all, event_alerts, percent_encode,
+    };
+    use crate::server::{
+        AnomalyEvent, AwayPeriod, AwayReport, Budget, BudgetPeriod, BudgetProgress, BudgetStatus,
+        BudgetUnit, QueryStatus, ServerEvent,
+    };
+
+    #[test]
+    fn rules_and_cooldown() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t0 = tz.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        let mut records: Vec<_> = (0..=4)
+            .map(|h| (t0 + TimeDelta::hours(h), 10.0 - 0.5 * h as f32))
+            .collect();
+        let rules = vec![
+            AlertRuleConfig {
+                rule: AlertRule::LowDegree { threshold: 9.0 },
+                cooldown_mins: 60,
+            },
+            AlertRuleConfig {
+                rule: AlertRule::ConsumptionJump {
+                    rate: 1.0,
+                    window_mins: 60,
+                },
+                cooldown_mins: 60,
+            },
+            AlertRuleConfig {
+                rule: AlertRule::UpstreamFailure { failures: 3 },
+                cooldown_mins: 60,
+            },
+            AlertRuleConfig {
+                rule: AlertRule::Recharge,
+                cooldown_mins: 60,
+            },
+        ];
+        let mut state = RuleState::default();
+        let status = QueryStatus {
+            consecutive_failures: 3,
+            ..QueryStatus::default()
+        };
+        let now = t0 + TimeDelta::hours(4);
+        let names = |alerts: Vec<Alert>| alerts.into_iter().map(|a| a.rule).collect::<Vec<_>>();
+        assert_eq!(
+            names(check_rules(&rules, &mut state, &records, &status, now)),
+            ["low-degree", "upstream-failure"]
+        );
+        // 冷却时间内不重复告警.
+        let now = now + TimeDelta::minutes(30);
+        assert!(check_rules(&rules, &mut state, &records, &status, now).is_empty());
+
+        // 用电突增, 然后充值.
+        records.push((t0 + TimeDelta::hours(5), 6.0));
+        records.push((t0 + TimeDelta::hours(6), 20.0));
+        let now = now + TimeDelta::minutes(20);
+        assert_eq!(
+            names(check_rules(
+                &rules,
+                &mut state,
+                &records,
+                &QueryStatus::default(),
+                now
+            )),
+            ["recharge"]
+        );
+        records.push((t0 + TimeDelta::hours(6) + TimeDelta::minutes(30), 18.0));
+        assert_eq!(
+            names(check_rules(
+                &rules,
+                &mut state,
+                &records,
+                &QueryStatus::default(),
+                now
+            )),
+            ["consumption-jump"]
+        );
+    }
+
+    #[test]
+    fn event_rules() {
+        let t0 = FixedOffset::east_opt(8 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
+            .unwrap();
+        let rule = |rule| AlertRuleConfig {
+            rule,
+            cooldown_mins: 60,
+        };
+        let rules = vec![
+            rule(AlertRule::Anomaly {
+                min_severity: Some(Severity::Medium),
+            }),
+            rule(AlertRule::Away),
+            rule(AlertRule::Budget),
+            rule(AlertRule::LowDegree { threshold: 9.0 }),
+        ];
+        let anomaly = |severity| {
+            ServerEvent::Anomaly(AnomalyEvent {
+                id: 1,
+                anomaly: Anomaly {
+                    start_time: t0,
+                    end_time: t0 + TimeDelta::hours(1),
+                    consumption: 1.5,
+                    expected: 0.1,
+                    peak_rate: 1.5,
+                    severity,
+                },
+                detected_at: t0,
+            })
+        };
+        let period = AwayPeriod {
+            id: 1,
+            start_time: t0,
+            end_time: t0 + TimeDelta::days(1),
+            limit: 1.0,
+            alerted: true,
+            report: Some(AwayReport {
+                until: t0 + TimeDelta::days(1),
+                consumption: 3.0,
+                baseline_rate: Some(0.05),
+                expected: 1.2,
+                excess: 1.8,
+                finished: true,
+            }),
+        };
+        let progress = BudgetProgress {
+            budget: Budget {
+                id: 1,
+                period: BudgetPeriod::Weekly,
+                unit: BudgetUnit::Kwh,
+                amount: 20.0,
+            },
+            start_time: t0,
+            end_time: t0 + TimeDelta::days(7),
+            until: t0 + TimeDelta::days(2),
+            consumption: 8.0,
+            cost: None,
+            spent: 8.0,
+            fraction: 0.4,
+            elapsed: 2.0 / 7.0,
+            projected: Some(28.0),
+            status: BudgetStatus::OverPace,
+        };
+        let fired = |event| {
+            event_alerts(&rules, &event, t0)
+                .into_iter()
+                .map(|a| (a.rule, a.message))
+                .collect::<Vec<_>>()
+        };
+
+        // 严重程度低于 min_severity 时不告警.
+        assert!(fired(anomaly(Severity::Low)).is_empty());
+        assert_eq!(fired(anomaly(Severity::High))[0].0, "anomaly");
+        let away = fired(ServerEvent::AwayEnded(period.clone()));
+        assert_eq!(away.len(), 1);
+        assert!(away[0].1.contains("共用电 3.00 度"), "{}", away[0].1);
+        assert_eq!(fired(ServerEvent::AwayLimitExceeded(period))[0].0, "away");
+        assert_eq!(
+            fired(ServerEvent::BudgetOverPace(progress)),
+            [(
+                "budget".to_string(),
+                "本周预算 20.00 度, 已用 8.00 度, 按照当前速度预计周期结束时达到 28.00 度"
+                    .to_string()
+            )]
+        );
+        // 不对应任何规则的事件.
+        assert!(fired(ServerEvent::LoginRestored).is_empty());
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(percent_encode("a b/c~"), "a%20b%2Fc~");
+        assert_eq!(percent_encode("电"), "%E7%94%B5");
+    }
+
+    /// 本地的 SMTP 替身, 返回收到的 DATA.
+    async fn smtp_stand_in(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut data = String::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let command = line.to_ascii_uppercase();
+            if command.starts_with("DATA") {
+                write.write_all(b"354 go ahead\r\n").await.unwrap();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    if line == "." {
+                        break;
+                    }
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                write.write_all(b"250 queued\r\n").await.unwrap();
+            } else if command.starts_with("QUIT") {
+                write.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                write.write_all(b"250 ok\r\n").await.unwrap();
+            }
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn deliver_to_local_stand_ins() {
+        type Received = Arc<Mutex<Vec<String>>>;
+        let received: Received = Arc::default();
+        let app = Router::new()
+            .route(
+                "/webhook",
+                post(
+                    |State(received): State<Received>, axum::Json(alert): axum::Json<Alert>| async move {
+                        received.lock().unwrap().push(format!("webhook {}", alert.title));
+                    },
+                ),
+            )
+            .route(
+                "/push",
+                get(
+                    |State(received): State<Received>,
+                     Query(query): Query<std::collections::HashMap<String, String>>| async move {
+                        received
+                            .lock()
+                            .unwrap()
+                            .push(format!("push {} {}", query["title"], query["body"]));
+                    },
+                ),
+            )
+            .route("/broken", post(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR }))
+            .with_state(Arc::clone(&received));
+        let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let http_addr = http.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(http, app).await.unwrap() });
+        let smtp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let smtp_port = smtp.local_addr().unwrap().port();
+        let smtp_data = tokio::spawn(smtp_stand_in(smtp));
+
+        let channels = vec![
+            AlertChannelConfig::Webhook {
+                url: format!("http://{http_addr}/webhook"),
+            },
+            AlertChannelConfig::Push {
+                url: format!("http://{http_addr}/push?title={{title}}&body={{body}}"),
+            },
+            AlertChannelConfig::Smtp {
+                host: "127.0.0.1".to_string(),
+                port: Some(smtp_port),
+                security: SmtpSecurity::None,
+                username: None,
+                password: None,
+                from: "alert@example.com".to_string(),
+                to: vec!["me@example.com".to_string()],
+            },
+            AlertChannelConfig::Webhook {
+                url: format!("http://{http_addr}/broken"),
+            },
+        ];
+        let alert = Alert {
+            rule: "test".to_string(),
+            title: "宿舍电量不足".to_string(),
+            message: "剩余 1.00 度 & 更少".to_string(),
+            time: FixedOffset::east_opt(8 * 3600)
+                .unwrap()
+                .with_ymd_and_hms(2025, 3, 1, 0, 0, 0)
+                .unwrap(),
+        };
+        let deliveries = deliver_all(&reqwest::Client::new(), &channels, &alert.notice()).await;
+        let errors: Vec<_> = deliveries.iter().map(|d| d.error.is_some()).collect();
+        assert_eq!(errors, [false, false, false, true]);
+        assert_eq!(
+            *received.lock().unwrap(),
+            [
+                "webhook 宿舍电量不足",
+                "push 宿舍电量不足 剩余 1.00 度 & 更少"
+            ]
+        );
+        let data = smtp_data.await.unwrap();
+        assert!(data.contains("To: me@example.com"));
+        assert!(data.contains("Subject:"));
+    }
+}
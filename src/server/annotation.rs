@@ -0,0 +1,184 @@
+//! 时间线标注, 如 "国庆回家", "换了新空调".
+//!
+//! 当前记录的标注保存在房间目录下的 `annotations.json` 中, 被归档的标注保存在
+//! `archives/<archive_name>.annotations.json` 中, 与 archive 一同移动/删除.
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ANNOTATIONS_FILENAME, ARCHIVE_DIRNAME, is_sanitized_filename};
+use crate::server::store;
+use crate::{CSError, Records, TimeSpan};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// 在同一房间内唯一, 被归档后保持不变.
+    pub id: u64,
+    pub text: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 标注的时间范围, 起止都为 None 时表示不限定时间.
+    pub time_span: TimeSpan,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+/// 附带重叠标注的记录.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AnnotatedRecords {
+    pub records: Records,
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct AnnotationStore {
+    /// 下一个标注的编号, 只保存在房间目录的标注文件中.
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+/// archive 对应的标注文件路径.
+pub(crate) fn archive_annotations_file(archive_dir: &Path, archive_name: &str) -> PathBuf {
+    archive_dir.join(format!("{archive_name}.annotations.json"))
+}
+
+/// 标注文件的路径, `archive` 为 None 时表示当前记录的标注.
+fn store_path(room_dir: &Path, archive: Option<&str>) -> Result<PathBuf, CSError> {
+    match archive {
+        None => Ok(room_dir.join(ANNOTATIONS_FILENAME)),
+        Some(name) if !is_sanitized_filename(name) => Err(CSError::InvalidArchiveName),
+        Some(name) => {
+            let archive_dir = room_dir.join(ARCHIVE_DIRNAME);
+            if archive_dir.join(format!("{name}.toml")).exists() {
+                Ok(archive_annotations_file(&archive_dir, name))
+            } else {
+                Err(CSError::ArchiveNotFound)
+            }
+        }
+    }
+}
+
+async fn load_store(path: &Path) -> Result<AnnotationStore, CSError> {
+    store::load(path, CSError::AnnotationStore).await
+}
+
+async fn save_store(path: &Path, store: &AnnotationStore) -> Result<(), CSError> {
+    store::save(path, store, CSError::AnnotationStore).await
+}
+
+/// 列出标注, `archive` 为 None 时列出当前记录的标注.
+pub(crate) async fn list(
+    room_dir: &Path,
+    archive: Option<&str>,
+) -> Result<Vec<Annotation>, CSError> {
+    let path = store_path(room_dir, archive)?;
+    Ok(load_store(&path).await?.annotations)
+}
+
+/// 列出与时间范围重叠的标注, `archive` 为 None 时从当前记录的标注中查找.
+pub(crate) async fn overlapping(
+    room_dir: &Path,
+    archive: Option<&str>,
+    time_span: &TimeSpan,
+) -> Result<Vec<Annotation>, CSError> {
+    let mut annotations = list(room_dir, archive).await?;
+    annotations.retain(|a| a.time_span.overlaps(time_span));
+    Ok(annotations)
+}
+
+/// 添加标注, 编号由房间目录下的标注文件统一分配.
+pub(crate) async fn add(
+    room_dir: &Path,
+    archive: Option<&str>,
+    text: String,
+    tags: Vec<String>,
+    time_span: TimeSpan,
+    created_at: DateTime<FixedOffset>,
+) -> Result<Annotation, CSError> {
+    let path = store_path(room_dir, archive)?;
+    let room_path = store_path(room_dir, None)?;
+    let mut room_store = load_store(&room_path).await?;
+    let id = store::next_id(&mut room_store.next_id);
+    let annotation = Annotation {
+        id,
+        text,
+        tags,
+        time_span,
+        created_at,
+    };
+    if path == room_path {
+        room_store.annotations.push(annotation.clone());
+    } else {
+        let mut store = load_store(&path).await?;
+        store.annotations.push(annotation.clone());
+        save_store(&path, &store).await?;
+    }
+    save_store(&room_path, &room_store).await?;
+    Ok(annotation)
+}
+
+/// 修改标注的内容, 编号和创建时间保持不变.
+pub(crate) async fn update(
+    room_dir: &Path,
+    archive: Option<&str>,
+    id: u64,
+    text: String,
+    tags: Vec<String>,
+    time_span: TimeSpan,
+) -> Result<Annotation, CSError> {
+    let path = store_path(room_dir, archive)?;
+    let mut store = load_store(&path).await?;
+    let annotation = store
+        .annotations
+        .iter_mut()
+        .find(|a| a.id == id)
+        .ok_or(CSError::AnnotationNotFound)?;
+    annotation.text = text;
+    annotation.tags = tags;
+    annotation.time_span = time_span;
+    let annotation = annotation.clone();
+    save_store(&path, &store).await?;
+    Ok(annotation)
+}
+
+pub(crate) async fn delete(room_dir: &Path, archive: Option<&str>, id: u64) -> Result<(), CSError> {
+    let path = store_path(room_dir, archive)?;
+    let mut store = load_store(&path).await?;
+    let len = store.annotations.len();
+    store.annotations.retain(|a| a.id != id);
+    if store.annotations.len() == len {
+        return Err(CSError::AnnotationNotFound);
+    }
+    save_store(&path, &store).await
+}
+
+/// 将完全处于 `time_span` 之内的标注移动到 archive 的标注文件中.
+pub(crate) async fn move_into_archive(
+    room_dir: &Path,
+    archive_name: &str,
+    time_span: &TimeSpan,
+) -> Result<(), CSError> {
+    let room_path = room_dir.join(ANNOTATIONS_FILENAME);
+    let archive_path = archive_annotations_file(&room_dir.join(ARCHIVE_DIRNAME), archive_name);
+    let mut room_store = load_store(&room_path).await?;
+    let (moved, retained) = room_store
+        .annotations
+        .into_iter()
+        .partition::<Vec<_>, _>(|a| {
+            // 不限定时间的标注属于整个房间, 不随 archive 移动.
+            a.time_span.start_time.is_some()
+                && a.time_span.end_time.is_some()
+                && time_span.contains_span(&a.time_span)
+        });
+    room_store.annotations = retained;
+    if moved.is_empty() {
+        return Ok(());
+    }
+    let mut archive_store = load_store(&archive_path).await?;
+    archive_store.annotations.extend(moved);
+    save_store(&archive_path, &archive_store).await?;
+    save_store(&room_path, &room_store).await?;
+    Ok(())
+}
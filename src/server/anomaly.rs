@@ -0,0 +1,213 @@
+//! 用电异常检测.
+//!
+//! 服务端定期根据最近 [`PROFILE_HISTORY`] 的记录学习用电习惯, 检测上次检测之后的完整小时,
+//! 发现的异常作为事件保存在房间目录下的 `anomalies.json` 中. 与上一个事件相接的异常会合并到上一个事件中.
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, DurationRound, FixedOffset, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::analytics::{Anomaly, PROFILE_HISTORY, Severity, detect_anomalies, usage_profile};
+use crate::config::ANOMALIES_FILENAME;
+use crate::server::{AppState, ServerEvent, store};
+use crate::{CSError, TimeSpan};
+
+/// 第一次检测时向前检测的时长.
+const FIRST_CHECK_WINDOW: TimeDelta = TimeDelta::days(1);
+
+/// 检测间隔.
+const CHECK_INTERVAL: Duration = Duration::from_mins(10);
+
+/// 保存下来的一次异常.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AnomalyEvent {
+    pub id: u64,
+    #[serde(flatten)]
+    pub anomaly: Anomaly,
+    /// 第一次检测到的时间.
+    pub detected_at: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct AnomalyStore {
+    #[serde(default)]
+    next_id: u64,
+    /// 已经检测过的时间, 下一次从这里开始检测.
+    #[serde(default)]
+    checked_until: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    events: Vec<AnomalyEvent>,
+}
+
+async fn load_store(room_dir: &Path) -> Result<AnomalyStore, CSError> {
+    store::load(&room_dir.join(ANOMALIES_FILENAME), CSError::AnomalyStore).await
+}
+
+async fn save_store(room_dir: &Path, store: &AnomalyStore) -> Result<(), CSError> {
+    store::save(
+        &room_dir.join(ANOMALIES_FILENAME),
+        store,
+        CSError::AnomalyStore,
+    )
+    .await
+}
+
+/// 与时间范围重叠, 且严重程度不低于 `min_severity` 的异常事件.
+pub(crate) async fn list(
+    room_dir: &Path,
+    time_span: &TimeSpan,
+    min_severity: Option<Severity>,
+) -> Result<Vec<AnomalyEvent>, CSError> {
+    Ok(load_store(room_dir)
+        .await?
+        .events
+        .into_iter()
+        .filter(|e| {
+            time_span.overlaps(&TimeSpan::new(
+                Some(e.anomaly.start_time),
+                Some(e.anomaly.end_time),
+            ))
+        })
+        .filter(|e| min_severity.is_none_or(|s| e.anomaly.severity >= s))
+        .collect())
+}
+
+/// 将新检测到的异常合并到事件中, 返回新建的或者严重程度升高的事件.
+fn merge(store: &mut AnomalyStore, found: Vec<Anomaly>) -> Vec<AnomalyEvent> {
+    let now = Local::now().fixed_offset();
+    let mut notify: Vec<AnomalyEvent> = Vec::new();
+    for anomaly in found {
+        match store.events.last_mut() {
+            Some(last) if last.anomaly.end_time >= anomaly.start_time => {
+                let escalated = anomaly.severity > last.anomaly.severity;
+                let prev = &mut last.anomaly;
+                prev.end_time = prev.end_time.max(anomaly.end_time);
+                prev.consumption += anomaly.consumption;
+                prev.expected += anomaly.expected;
+                prev.peak_rate = prev.peak_rate.max(anomaly.peak_rate);
+                prev.severity = prev.severity.max(anomaly.severity);
+                if escalated {
+                    notify.retain(|e| e.id != last.id);
+                    notify.push(last.clone());
+                }
+            }
+            _ => {
+                let event = AnomalyEvent {
+                    id: store::next_id(&mut store.next_id),
+                    anomaly,
+                    detected_at: now,
+                };
+                store.events.push(event.clone());
+                notify.push(event);
+            }
+        }
+    }
+    notify
+}
+
+/// 检测上次检测之后的完整小时.
+async fn check(state: &AppState) -> Result<Vec<AnomalyEvent>, CSError> {
+    let mut records = state
+        .recorder
+        .write()
+        .await
+        .read_records()
+        .await
+        .map_err(|e| {
+            error!(target: "reading records", "{e:?}");
+            CSError::ReadRecords
+        })?;
+    records.sort();
+    let Some(&(last_time, _)) = records.last() else {
+        return Ok(Vec::new());
+    };
+    // 只检测最后一条记录之前的完整小时.
+    let until = last_time
+        .with_timezone(&Local)
+        .duration_trunc(TimeDelta::hours(1))
+        .map_or(last_time, |t| t.fixed_offset());
+
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.anomalies_lock.lock().await;
+    let mut store = load_store(&room_dir).await?;
+    let from = store
+        .checked_until
+        .unwrap_or(until - FIRST_CHECK_WINDOW)
+        .max(until - PROFILE_HISTORY);
+    if from >= until {
+        return Ok(Vec::new());
+    }
+    let Some(profile) = usage_profile(&records, from) else {
+        return Ok(Vec::new());
+    };
+    let found = detect_anomalies(&records, &profile, &TimeSpan::new(Some(from), Some(until)));
+    let notify = merge(&mut store, found);
+    store.checked_until = Some(until);
+    save_store(&room_dir, &store).await?;
+    Ok(notify)
+}
+
+pub(crate) async fn anomaly_loop(state: Arc<AppState>) -> ! {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match check(&state).await {
+            Ok(events) => {
+                for event in events {
+                    warn!(
+                        "anomaly: {:?} {} ~ {}, {:.2} kWh (expected {:.2} kWh)",
+                        event.anomaly.severity,
+                        event.anomaly.start_time,
+                        event.anomaly.end_time,
+                        event.anomaly.consumption,
+                        event.anomaly.expected
+                    );
+                    state.events.send(ServerEvent::Anomaly(event));
+                }
+            }
+            Err(e) => error!("anomaly check: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::analytics::{Anomaly, Severity};
+    use crate::server::anomaly::{AnomalyStore, merge};
+
+    #[test]
+    fn merge_adjacent_anomalies() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |h| offset.with_ymd_and_hms(2026, 3, 2, h, 0, 0).unwrap();
+        let anomaly = |h, severity| Anomaly {
+            start_time: t(h),
+            end_time: t(h + 1),
+            consumption: 1.5,
+            expected: 0.1,
+            peak_rate: 1.5,
+            severity,
+        };
+        let mut store = AnomalyStore::default();
+        let notify = merge(&mut store, vec![anomaly(1, Severity::Low)]);
+        assert_eq!(notify.len(), 1);
+        assert_eq!(notify[0].id, 1);
+
+        // 与上一个事件相接, 严重程度不变时不通知.
+        assert!(merge(&mut store, vec![anomaly(2, Severity::Low)]).is_empty());
+        let notify = merge(
+            &mut store,
+            vec![anomaly(3, Severity::High), anomaly(6, Severity::Medium)],
+        );
+        assert_eq!(notify.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(store.events.len(), 2);
+        let first = &store.events[0].anomaly;
+        assert_eq!((first.start_time, first.end_time), (t(1), t(4)));
+        assert!((first.consumption - 4.5).abs() < 1e-4);
+        assert_eq!(first.severity, Severity::High);
+    }
+}
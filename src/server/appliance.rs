@@ -0,0 +1,24 @@
+//! 电器用电归属.
+//!
+//! 电器的使用时间段以带有 `appliance:<名称>` 标签的标注保存在时间线上, 如 22:00 ~ 07:00 带有 `appliance:空调`
+//! 标签的标注, 一个标注可以带有多个电器标签. 各个电器的用电量估计参见 [`analytics::attribute_appliances`].
+//!
+//! [`analytics::attribute_appliances`]: crate::analytics::attribute_appliances
+use crate::TimeSpan;
+use crate::server::Annotation;
+
+/// 电器标签的前缀, 前缀之后为电器名称.
+pub const APPLIANCE_TAG_PREFIX: &str = "appliance:";
+
+/// 标注中的电器使用时间段 (电器名称, 时间范围).
+pub(crate) fn intervals(annotations: &[Annotation]) -> Vec<(String, TimeSpan)> {
+    annotations
+        .iter()
+        .flat_map(|a| {
+            a.tags.iter().filter_map(|tag| {
+                let name = tag.strip_prefix(APPLIANCE_TAG_PREFIX)?.trim();
+                (!name.is_empty()).then(|| (name.to_string(), a.time_span.clone()))
+            })
+        })
+        .collect()
+}
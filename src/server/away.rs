@@ -0,0 +1,301 @@
+//! 离开模式: 假期等所有人都不在宿舍的时间段.
+//!
+//! 离开时间段作为带有 [`AWAY_TAG`] 标签的标注保存在时间线上, 用电上限, 是否已经提醒和用电报告等状态
+//! 保存在房间目录下的 `away.json` 中, 以标注的编号关联.
+//!
+//! 离开期间应该只有基础负载的用电, 服务端定期检查离开期间的用电量, 超出基础负载的部分超过用电上限时提醒一次,
+//! 离开时间段结束后给出最终的用电报告.
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::analytics::{self, QUANTUM};
+use crate::config::AWAY_FILENAME;
+use crate::server::{AppState, ServerEvent, annotation, store};
+use crate::{CSError, Records, TimeSpan};
+
+/// 离开时间段的标注使用的标签.
+pub const AWAY_TAG: &str = "away";
+
+/// 离开时间段的标注没有给出文本时使用的文本.
+const DEFAULT_AWAY_TEXT: &str = "离开宿舍";
+
+/// 估计基础负载时使用离开之前这么长时间内的记录.
+const BASELINE_HISTORY: TimeDelta = TimeDelta::days(14);
+
+/// 检查间隔.
+const CHECK_INTERVAL: Duration = Duration::from_mins(10);
+
+/// 离开期间的用电情况.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AwayReport {
+    /// 统计截止的时间, 离开时间段结束后为结束时间.
+    pub until: DateTime<FixedOffset>,
+    /// 离开期间的用电量 (度).
+    pub consumption: f32,
+    /// 离开前的基础负载功率 (度/小时), 记录不足以估计时为 None.
+    pub baseline_rate: Option<f32>,
+    /// 按照基础负载预期的用电量 (度).
+    pub expected: f32,
+    /// 超出预期的用电量 (度).
+    pub excess: f32,
+    /// 离开时间段是否已经结束.
+    pub finished: bool,
+}
+
+/// 一个离开时间段.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AwayPeriod {
+    /// 对应的标注的编号.
+    pub id: u64,
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+    /// 允许超出基础负载的用电量 (度).
+    pub limit: f32,
+    /// 是否已经提醒过用电超过上限.
+    #[serde(default)]
+    pub alerted: bool,
+    /// 离开时间段开始之后才有.
+    #[serde(default)]
+    pub report: Option<AwayReport>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct AwayStore {
+    #[serde(default)]
+    periods: Vec<AwayPeriod>,
+}
+
+async fn load_store(room_dir: &Path) -> Result<AwayStore, CSError> {
+    store::load(&room_dir.join(AWAY_FILENAME), CSError::AwayStore).await
+}
+
+async fn save_store(room_dir: &Path, store: &AwayStore) -> Result<(), CSError> {
+    store::save(&room_dir.join(AWAY_FILENAME), store, CSError::AwayStore).await
+}
+
+/// 调用者需要持有 `annotations_lock`.
+pub(crate) async fn list(room_dir: &Path) -> Result<Vec<AwayPeriod>, CSError> {
+    Ok(load_store(room_dir).await?.periods)
+}
+
+/// 添加离开时间段, 同时在当前记录的时间线上添加标注, 调用者需要持有 `annotations_lock`.
+pub(crate) async fn set(
+    room_dir: &Path,
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+    limit: f32,
+    text: Option<String>,
+) -> Result<AwayPeriod, CSError> {
+    if end_time <= start_time || limit < 0.0 {
+        return Err(CSError::InvalidAwayPeriod);
+    }
+    let mut store = load_store(room_dir).await?;
+    let annotation = annotation::add(
+        room_dir,
+        None,
+        text.unwrap_or_else(|| DEFAULT_AWAY_TEXT.to_string()),
+        vec![AWAY_TAG.to_string()],
+        TimeSpan::new(Some(start_time), Some(end_time)),
+        Local::now().fixed_offset(),
+    )
+    .await?;
+    let period = AwayPeriod {
+        id: annotation.id,
+        start_time,
+        end_time,
+        limit,
+        alerted: false,
+        report: None,
+    };
+    store.periods.push(period.clone());
+    if let Err(e) = save_store(room_dir, &store).await {
+        // 删除已经添加的标注, 避免留下没有对应离开时间段的标注.
+        annotation::delete(room_dir, None, annotation.id).await.ok();
+        return Err(e);
+    }
+    Ok(period)
+}
+
+/// 取消离开时间段并删除对应的标注, 调用者需要持有 `annotations_lock`.
+pub(crate) async fn cancel(room_dir: &Path, id: u64) -> Result<(), CSError> {
+    let mut store = load_store(room_dir).await?;
+    let len = store.periods.len();
+    store.periods.retain(|p| p.id != id);
+    if store.periods.len() == len {
+        return Err(CSError::AwayNotFound);
+    }
+    save_store(room_dir, &store).await?;
+    // 标注可能已经随 archive 移走或者被手动删除了.
+    match annotation::delete(room_dir, None, id).await {
+        Ok(()) | Err(CSError::AnnotationNotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// 统计离开时间段到 `now` 为止的用电情况, `records` 需要按照时间排序.
+fn report(
+    period: &AwayPeriod,
+    records: &[(DateTime<FixedOffset>, f32)],
+    now: DateTime<FixedOffset>,
+) -> AwayReport {
+    let until = now.min(period.end_time);
+    let span = TimeSpan::new(Some(period.start_time), Some(until));
+    let consumption = analytics::consumption(records, &span);
+    let baseline_rate = analytics::baseline_rate(
+        records,
+        &TimeSpan::new(
+            Some(period.start_time - BASELINE_HISTORY),
+            Some(period.start_time),
+        ),
+    );
+    #[allow(clippy::cast_precision_loss)]
+    let hours = (until - period.start_time).num_seconds().max(0) as f32 / 3600.0;
+    let expected = baseline_rate.unwrap_or(0.0) * hours;
+    AwayReport {
+        until,
+        consumption,
+        baseline_rate,
+        expected,
+        excess: (consumption - expected).max(0.0),
+        finished: now >= period.end_time,
+    }
+}
+
+/// 已经开始, 且还没有给出最终报告.
+fn is_active(period: &AwayPeriod, now: DateTime<FixedOffset>) -> bool {
+    period.start_time <= now && !period.report.as_ref().is_some_and(|r| r.finished)
+}
+
+/// 更新需要更新的离开时间段, 返回需要通知的事件.
+fn refresh(
+    periods: &mut [AwayPeriod],
+    records: &[(DateTime<FixedOffset>, f32)],
+    now: DateTime<FixedOffset>,
+) -> Vec<ServerEvent> {
+    let mut events = Vec::new();
+    for period in periods {
+        if !is_active(period, now) {
+            continue;
+        }
+        let report = report(period, records, now);
+        let finished = report.finished;
+        // 用电量以量化单位变化, 留出半个单位避免浮点误差.
+        let exceeded = report.excess > period.limit + QUANTUM / 2.0;
+        period.report = Some(report);
+        if exceeded && !period.alerted {
+            period.alerted = true;
+            events.push(ServerEvent::AwayLimitExceeded(period.clone()));
+        }
+        if finished {
+            events.push(ServerEvent::AwayEnded(period.clone()));
+        }
+    }
+    events
+}
+
+async fn check(state: &AppState) -> Result<Vec<ServerEvent>, CSError> {
+    let room_dir = state.room_dir.read().await.clone();
+    let now = Local::now().fixed_offset();
+    {
+        let _lock = state.annotations_lock.lock().await;
+        if !load_store(&room_dir)
+            .await?
+            .periods
+            .iter()
+            .any(|p| is_active(p, now))
+        {
+            return Ok(Vec::new());
+        }
+    }
+    // 持有 annotations_lock 时不能获取 recorder 的锁, 见 [`AppState`].
+    let mut records: Records = state
+        .recorder
+        .write()
+        .await
+        .read_records()
+        .await
+        .map_err(|e| {
+            error!(target: "reading records", "{e:?}");
+            CSError::ReadRecords
+        })?;
+    records.sort();
+    let _lock = state.annotations_lock.lock().await;
+    let mut store = load_store(&room_dir).await?;
+    let events = refresh(&mut store.periods, &records, now);
+    save_store(&room_dir, &store).await?;
+    Ok(events)
+}
+
+pub(crate) async fn away_loop(state: Arc<AppState>) -> ! {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match check(&state).await {
+            Ok(events) => {
+                for event in events {
+                    match &event {
+                        ServerEvent::AwayLimitExceeded(period) => warn!(
+                            "away: consumption exceeded the limit ({:.2} kWh) since {}: {:?}",
+                            period.limit, period.start_time, period.report
+                        ),
+                        ServerEvent::AwayEnded(period) => info!(
+                            "away: period {} ~ {} ended: {:?}",
+                            period.start_time, period.end_time, period.report
+                        ),
+                        _ => {}
+                    }
+                    state.events.send(event);
+                }
+            }
+            Err(e) => error!("away check: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::Records;
+    use crate::server::ServerEvent;
+    use crate::server::away::{AwayPeriod, refresh};
+
+    #[tokio::test]
+    async fn alert_once_and_report_at_end() {
+        let records =
+            Records::from_csv(Cursor::new(include_str!("../../assets/records-sample.csv")))
+                .await
+                .unwrap();
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |d, h| offset.with_ymd_and_hms(2026, 3, d, h, 0, 0).unwrap();
+        let period = |start, end| AwayPeriod {
+            id: 1,
+            start_time: start,
+            end_time: end,
+            limit: 1.0,
+            alerted: false,
+            report: None,
+        };
+        // 晚上有人在用电 (约 0.9 度/小时), 很快超过上限.
+        let mut periods = vec![period(t(2, 18), t(3, 0)), period(t(4, 0), t(5, 0))];
+        let events = refresh(&mut periods, &records, t(2, 22));
+        assert!(matches!(events.as_slice(), [ServerEvent::AwayLimitExceeded(p)] if p.alerted));
+        let report = periods[0].report.as_ref().unwrap();
+        assert!(!report.finished);
+        assert!(report.excess > 1.0 && report.baseline_rate.is_some());
+        assert!(periods[1].report.is_none());
+
+        let events = refresh(&mut periods, &records, t(3, 1));
+        assert!(matches!(events.as_slice(), [ServerEvent::AwayEnded(_)]));
+        let report = periods[0].report.as_ref().unwrap();
+        assert!(report.finished && report.until == t(3, 0));
+        assert!(refresh(&mut periods[..1], &records, t(3, 2)).is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+//! 用电预算.
+//!
+//! 每个房间可以设置按周或者按月的预算, 以度或者元为单位, 同一周期和单位只有一个预算,
+//! 保存在房间目录下的 `budgets.json` 中. 周期按照服务端时区划分, 周从周一开始.
+//!
+//! 服务端定期统计当前周期到最后一条记录为止的用电情况, 按照当前周期的平均用电速度预测周期结束时的总量.
+//! 每个周期内, 预测会超出预算和已经超出预算时各提醒一次.
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Local, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::analytics::{self, Accounting};
+use crate::config::BUDGETS_FILENAME;
+use crate::server::{AppState, ServerEvent, store};
+use crate::{CSError, TimeSpan};
+
+/// 周期开始后至少经过这么长时间才预测周期结束时的总量, 避免刚开始时的用电速度波动太大.
+const MIN_PACE_ELAPSED: TimeDelta = TimeDelta::hours(12);
+
+/// 检查间隔.
+const CHECK_INTERVAL: Duration = Duration::from_mins(10);
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetPeriod {
+    Weekly,
+    Monthly,
+}
+
+impl BudgetPeriod {
+    /// 包含 `now` 的周期, 按照 `now` 的时区划分.
+    fn current<Tz: TimeZone>(self, now: &DateTime<Tz>) -> Option<TimeSpan> {
+        let expr = match self {
+            BudgetPeriod::Weekly => "week:this",
+            BudgetPeriod::Monthly => "month:this",
+        };
+        TimeSpan::resolve(expr, now, &[]).ok()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetUnit {
+    /// 度.
+    Kwh,
+    /// 元, 需要服务端配置电价.
+    Money,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Budget {
+    pub id: u64,
+    pub period: BudgetPeriod,
+    pub unit: BudgetUnit,
+    pub amount: f32,
+}
+
+/// 预算的执行状态.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetStatus {
+    /// 按照当前速度不会超出预算 (或者还无法预测).
+    OnTrack,
+    /// 按照当前速度会在周期结束前超出预算.
+    OverPace,
+    /// 已经超出预算.
+    Exceeded,
+}
+
+/// 预算在当前周期的执行情况.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct BudgetProgress {
+    pub budget: Budget,
+    /// 当前周期, 两端都包含.
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+    /// 统计截止的时间, 即周期内最后一条记录的时间, 周期内没有记录时为周期开始时间.
+    pub until: DateTime<FixedOffset>,
+    /// 周期内的用电量 (度).
+    pub consumption: f32,
+    /// 周期内的费用 (元), 没有配置电价时为 None.
+    pub cost: Option<f32>,
+    /// 以预算的单位计的已用量.
+    pub spent: f32,
+    /// 已用量占预算的比例, 可能大于 1.
+    pub fraction: f32,
+    /// 周期已经过去的比例.
+    pub elapsed: f32,
+    /// 按照当前速度预测的周期结束时的总量, 周期开始不久时为 None.
+    pub projected: Option<f32>,
+    pub status: BudgetStatus,
+}
+
+/// 某个预算在某个周期内已经提醒过的最高状态.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct BudgetAlert {
+    budget_id: u64,
+    period_start: DateTime<FixedOffset>,
+    status: BudgetStatus,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct BudgetStore {
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    budgets: Vec<Budget>,
+    #[serde(default)]
+    alerts: Vec<BudgetAlert>,
+}
+
+async fn load_store(room_dir: &Path) -> Result<BudgetStore, CSError> {
+    store::load(&room_dir.join(BUDGETS_FILENAME), CSError::BudgetStore).await
+}
+
+async fn save_store(room_dir: &Path, store: &BudgetStore) -> Result<(), CSError> {
+    store::save(
+        &room_dir.join(BUDGETS_FILENAME),
+        store,
+        CSError::BudgetStore,
+    )
+    .await
+}
+
+/// 调用者需要持有 `budgets_lock`.
+pub(crate) async fn list(room_dir: &Path) -> Result<Vec<Budget>, CSError> {
+    Ok(load_store(room_dir).await?.budgets)
+}
+
+/// 设置预算, 已经有相同周期和单位的预算时修改其数额, 调用者需要持有 `budgets_lock`.
+pub(crate) async fn set(
+    room_dir: &Path,
+    period: BudgetPeriod,
+    unit: BudgetUnit,
+    amount: f32,
+    accounting: &Accounting,
+) -> Result<Budget, CSError> {
+    if !(amount.is_finite() && amount > 0.0)
+        || (unit == BudgetUnit::Money && accounting.hourly_prices.is_none())
+    {
+        return Err(CSError::InvalidBudget);
+    }
+    let mut store = load_store(room_dir).await?;
+    let budget = match store
+        .budgets
+        .iter_mut()
+        .find(|b| b.period == period && b.unit == unit)
+    {
+        Some(budget) => {
+            budget.amount = amount;
+            budget.clone()
+        }
+        None => {
+            let budget = Budget {
+                id: store::next_id(&mut store.next_id),
+                period,
+                unit,
+                amount,
+            };
+            store.budgets.push(budget.clone());
+            budget
+        }
+    };
+    // 数额变化后重新判断是否需要提醒.
+    store.alerts.retain(|a| a.budget_id != budget.id);
+    save_store(room_dir, &store).await?;
+    Ok(budget)
+}
+
+/// 调用者需要持有 `budgets_lock`.
+pub(crate) async fn delete(room_dir: &Path, id: u64) -> Result<(), CSError> {
+    let mut store = load_store(room_dir).await?;
+    let len = store.budgets.len();
+    store.budgets.retain(|b| b.id != id);
+    if store.budgets.len() == len {
+        return Err(CSError::BudgetNotFound);
+    }
+    store.alerts.retain(|a| a.budget_id != id);
+    save_store(room_dir, &store).await
+}
+
+/// 统计预算在包含 `now` 的周期内的执行情况, `records` 需要按照时间排序.
+///
+/// 以元为单位的预算在没有配置电价时无法统计, 返回 None.
+pub(crate) fn progress<Tz: TimeZone>(
+    budget: &Budget,
+    records: &[(DateTime<FixedOffset>, f32)],
+    now: &DateTime<Tz>,
+    accounting: &Accounting,
+) -> Option<BudgetProgress> {
+    let period = budget.period.current(now)?;
+    let (start_time, end_time) = (period.start_time?, period.end_time?);
+    let until = analytics::within(records, &period)
+        .last()
+        .map_or(start_time, |x| x.0);
+    let report = analytics::cost_report(
+        records,
+        &TimeSpan::new(Some(start_time), Some(until)),
+        accounting,
+    );
+    let spent = match budget.unit {
+        BudgetUnit::Kwh => report.consumption,
+        BudgetUnit::Money => report.cost?,
+    };
+    #[allow(clippy::cast_precision_loss)]
+    let elapsed =
+        (until - start_time).num_seconds() as f32 / (end_time - start_time).num_seconds() as f32;
+    let projected = (until - start_time >= MIN_PACE_ELAPSED).then(|| spent / elapsed);
+    let status = if spent > budget.amount {
+        BudgetStatus::Exceeded
+    } else if projected.is_some_and(|p| p > budget.amount) {
+        BudgetStatus::OverPace
+    } else {
+        BudgetStatus::OnTrack
+    };
+    Some(BudgetProgress {
+        budget: budget.clone(),
+        start_time,
+        end_time,
+        until,
+        consumption: report.consumption,
+        cost: report.cost,
+        spent,
+        fraction: spent / budget.amount,
+        elapsed,
+        projected,
+        status,
+    })
+}
+
+/// 记录提醒过的状态, 返回状态在本周期内第一次升高的预算的提醒.
+fn alerts(store: &mut BudgetStore, progresses: Vec<BudgetProgress>) -> Vec<ServerEvent> {
+    let mut events = Vec::new();
+    for progress in progresses {
+        let id = progress.budget.id;
+        // 只保留当前周期的提醒状态.
+        store
+            .alerts
+            .retain(|a| a.budget_id != id || a.period_start == progress.start_time);
+        let alerted = store
+            .alerts
+            .iter()
+            .find(|a| a.budget_id == id)
+            .map_or(BudgetStatus::OnTrack, |a| a.status);
+        if progress.status <= alerted {
+            continue;
+        }
+        store.alerts.retain(|a| a.budget_id != id);
+        store.alerts.push(BudgetAlert {
+            budget_id: id,
+            period_start: progress.start_time,
+            status: progress.status,
+        });
+        events.push(match progress.status {
+            BudgetStatus::Exceeded => ServerEvent::BudgetExceeded(progress),
+            _ => ServerEvent::BudgetOverPace(progress),
+        });
+    }
+    events
+}
+
+async fn check(state: &AppState) -> Result<Vec<ServerEvent>, CSError> {
+    let room_dir = state.room_dir.read().await.clone();
+    {
+        let _lock = state.budgets_lock.lock().await;
+        if load_store(&room_dir).await?.budgets.is_empty() {
+            return Ok(Vec::new());
+        }
+    }
+    // 持有 budgets_lock 时不能获取 recorder 的锁, 见 [`AppState`].
+    let mut records = state
+        .recorder
+        .write()
+        .await
+        .read_records()
+        .await
+        .map_err(|e| {
+            error!(target: "reading records", "{e:?}");
+            CSError::ReadRecords
+        })?;
+    records.sort();
+    let _lock = state.budgets_lock.lock().await;
+    let mut store = load_store(&room_dir).await?;
+    let now = Local::now();
+    let progresses = store
+        .budgets
+        .iter()
+        .filter_map(|b| progress(b, &records, &now, &state.accounting))
+        .collect();
+    let events = alerts(&mut store, progresses);
+    if !events.is_empty() {
+        save_store(&room_dir, &store).await?;
+    }
+    Ok(events)
+}
+
+pub(crate) async fn budget_loop(state: Arc<AppState>) -> ! {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match check(&state).await {
+            Ok(events) => {
+                for event in events {
+                    let (ServerEvent::BudgetOverPace(progress)
+                    | ServerEvent::BudgetExceeded(progress)) = &event
+                    else {
+                        continue;
+                    };
+                    warn!(
+                        "budget: {:?} {:?} budget {:.2} {:?}, spent {:.2}, projected {:?}",
+                        progress.status,
+                        progress.budget.period,
+                        progress.budget.amount,
+                        progress.budget.unit,
+                        progress.spent,
+                        progress.projected
+                    );
+                    state.events.send(event);
+                }
+            }
+            Err(e) => error!("budget check: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::Records;
+    use crate::analytics::Accounting;
+    use crate::server::ServerEvent;
+    use crate::server::budget::{
+        Budget, BudgetPeriod, BudgetStatus, BudgetStore, BudgetUnit, alerts, progress,
+    };
+
+    #[tokio::test]
+    async fn pace_and_alerts() {
+        let records =
+            Records::from_csv(Cursor::new(include_str!("../../assets/records-sample.csv")))
+                .await
+                .unwrap();
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let now = offset.with_ymd_and_hms(2026, 3, 4, 12, 0, 0).unwrap();
+        let accounting = Accounting {
+            hourly_prices: None,
+            emission_factor: None,
+        };
+        let budget = |id, amount| Budget {
+            id,
+            period: BudgetPeriod::Weekly,
+            unit: BudgetUnit::Kwh,
+            amount,
+        };
+        let generous = progress(&budget(1, 1000.0), &records, &now, &accounting).unwrap();
+        assert_eq!(
+            generous.start_time,
+            offset.with_ymd_and_hms(2026, 3, 2, 0, 0, 0).unwrap()
+        );
+        assert!(generous.consumption > 0.0 && generous.elapsed > 0.0 && generous.elapsed < 1.0);
+        let projected = generous.projected.unwrap();
+        assert!(projected > generous.spent);
+        assert_eq!(generous.status, BudgetStatus::OnTrack);
+
+        // 预测会超出, 但还没有超出.
+        let tight = progress(
+            &budget(2, (generous.spent + projected) / 2.0),
+            &records,
+            &now,
+            &accounting,
+        )
+        .unwrap();
+        assert_eq!(tight.status, BudgetStatus::OverPace);
+        let exceeded = progress(
+            &budget(3, generous.spent / 2.0),
+            &records,
+            &now,
+            &accounting,
+        )
+        .unwrap();
+        assert_eq!(exceeded.status, BudgetStatus::Exceeded);
+
+        // 以元为单位的预算需要电价.
+        let money = Budget {
+            unit: BudgetUnit::Money,
+            ..budget(4, 10.0)
+        };
+        assert!(progress(&money, &records, &now, &accounting).is_none());
+
+        let mut store = BudgetStore::default();
+        let events = alerts(
+            &mut store,
+            vec![generous.clone(), tight.clone(), exceeded.clone()],
+        );
+        assert!(matches!(
+            events.as_slice(),
+            [ServerEvent::BudgetOverPace(p), ServerEvent::BudgetExceeded(q)] if p.budget.id == 2 && q.budget.id == 3
+        ));
+        // 同一周期内不重复提醒, 状态升高时再提醒.
+        assert!(alerts(&mut store, vec![tight.clone(), exceeded]).is_empty());
+        let escalated = progress(
+            &budget(2, generous.spent / 2.0),
+            &records,
+            &now,
+            &accounting,
+        )
+        .unwrap();
+        assert!(matches!(
+            alerts(&mut store, vec![escalated]).as_slice(),
+            [ServerEvent::BudgetExceeded(_)]
+        ));
+    }
+}
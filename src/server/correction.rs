@@ -0,0 +1,321 @@
+//! 记录修正: 手动插入读数, 删除/修改错误的读数, 以及撤销.
+//!
+//! 每一次修正都会被追加到房间目录下的修正日志 (`corrections.jsonl`) 中, 日志只追加不修改,
+//! 撤销操作本身也作为一条日志记录, 以便审计.
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, Local};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::error;
+
+use crate::analytics::Accounting;
+use crate::config::{ARCHIVE_DIRNAME, CORRECTIONS_FILENAME};
+use crate::server::{Recorder, load_archive, rewrite_archive};
+use crate::{CSError, Records};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum CorrectionOp {
+    /// 手动插入的读数, `source` 标明读数来源, 如 "meter".
+    Insert { source: String },
+    /// 删除读数.
+    Delete,
+    /// 修改读数的度数.
+    Correct,
+    /// 撤销日志中编号为 `id` 的修正.
+    Undo { id: u64 },
+}
+
+/// 修正日志中的一条记录.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CorrectionEntry {
+    pub id: u64,
+    /// 修正发生的时间.
+    pub created_at: DateTime<FixedOffset>,
+    /// 被修正的 archive, 为 None 时表示当前记录 (records.csv).
+    pub archive: Option<String>,
+    pub op: CorrectionOp,
+    /// 被移除的记录.
+    pub removed: Records,
+    /// 被添加的记录.
+    pub added: Records,
+}
+
+/// 从记录中移除 `removed` 中时间点对应的记录, 并加入 `added`, 结果按照时间排序.
+fn apply(records: &mut Records, removed: &Records, added: &Records) {
+    let removed: HashSet<_> = removed.iter().map(|x| x.0).collect();
+    records.retain(|x| !removed.contains(&x.0));
+    records.extend(added.iter().copied());
+    records.sort();
+}
+
+pub(crate) async fn read_journal(room_dir: &Path) -> crate::Result<Vec<CorrectionEntry>> {
+    let content = match fs::read_to_string(room_dir.join(CORRECTIONS_FILENAME)).await {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => Err(e)?,
+    };
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        entries.push(serde_json::from_str(line)?);
+    }
+    Ok(entries)
+}
+
+async fn append_journal(room_dir: &Path, entry: &CorrectionEntry) -> crate::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(room_dir.join(CORRECTIONS_FILENAME))
+        .await?
+        .write_all(line.as_bytes())
+        .await?;
+    Ok(())
+}
+
+async fn load_target(
+    recorder: &Recorder,
+    room_dir: &Path,
+    archive: Option<&str>,
+) -> Result<Records, CSError> {
+    match archive {
+        Some(name) => load_archive(&room_dir.join(ARCHIVE_DIRNAME), name).await,
+        None => recorder.read_records().await.map_err(|e| {
+            error!(target: "reading records", "{e:?}");
+            CSError::ReadRecords
+        }),
+    }
+}
+
+async fn store_target(
+    recorder: &mut Recorder,
+    room_dir: &Path,
+    archive: Option<&str>,
+    records: Records,
+    accounting: &Accounting,
+) -> Result<(), CSError> {
+    match archive {
+        Some(name) => rewrite_archive(&room_dir.join(ARCHIVE_DIRNAME), name, &records, accounting)
+            .await
+            .map(|_| ()),
+        None => recorder.replace(records).await.map_err(|e| {
+            error!(target: "writing records", "{e:?}");
+            CSError::WriteRecords
+        }),
+    }
+}
+
+/// 对当前记录或者 archive 执行一次修正并记入日志.
+///
+/// `plan` 根据目标中现有的记录给出需要移除和添加的记录.
+///
+/// 调用方需要持有 recorder 的写锁, 以保证修正和日志的写入是串行的.
+/// 修正 archive 时会使用 `accounting` 重新计算其元信息.
+pub(crate) async fn correct(
+    recorder: &mut Recorder,
+    room_dir: &Path,
+    accounting: &Accounting,
+    archive: Option<String>,
+    op: CorrectionOp,
+    plan: impl FnOnce(&Records) -> Result<(Records, Records), CSError>,
+) -> Result<CorrectionEntry, CSError> {
+    let journal = read_journal(room_dir).await.map_err(|e| {
+        error!(target: "reading correction journal", "{e:?}");
+        CSError::CorrectionJournal
+    })?;
+    let original = load_target(recorder, room_dir, archive.as_deref()).await?;
+    let (removed, added) = plan(&original)?;
+    let mut records = original.clone();
+    apply(&mut records, &removed, &added);
+    store_target(recorder, room_dir, archive.as_deref(), records, accounting).await?;
+
+    let entry = CorrectionEntry {
+        id: journal.last().map_or(1, |e| e.id + 1),
+        created_at: Local::now().fixed_offset(),
+        archive,
+        op,
+        removed,
+        added,
+    };
+    if let Err(e) = append_journal(room_dir, &entry).await {
+        error!(target: "writing correction journal", "{e:?}");
+        // 日志写入失败时回滚修正, 保证每一次修正都有据可查.
+        store_target(
+            recorder,
+            room_dir,
+            entry.archive.as_deref(),
+            original,
+            accounting,
+        )
+        .await
+        .ok();
+        return Err(CSError::CorrectionJournal);
+    }
+    Ok(entry)
+}
+
+/// 找到最后一条还没有被撤销的修正.
+///
+/// 目标 archive 已经被删除 (`archive_exists` 返回 false) 的修正无法撤销, 会被跳过.
+fn last_undoable(
+    journal: &[CorrectionEntry],
+    archive_exists: impl Fn(&str) -> bool,
+) -> Option<&CorrectionEntry> {
+    let undone: HashSet<u64> = journal
+        .iter()
+        .filter_map(|e| match e.op {
+            CorrectionOp::Undo { id } => Some(id),
+            _ => None,
+        })
+        .collect();
+    journal
+        .iter()
+        .rev()
+        .filter(|e| !matches!(e.op, CorrectionOp::Undo { .. }) && !undone.contains(&e.id))
+        .find(|e| e.archive.as_deref().is_none_or(&archive_exists))
+}
+
+/// 撤销 `target` 需要移除和添加的记录.
+///
+/// 修正之后记录可能已经被归档, 只撤销仍然能对应上的部分; 被移除的时间点之后又有了记录
+/// (例如重新导入) 时不再恢复, 避免重复的时间点.
+fn undo_plan(target: &CorrectionEntry, records: &Records) -> (Records, Records) {
+    let present: HashSet<_> = records.iter().map(|x| x.0).collect();
+    let removed: Vec<_> = target
+        .added
+        .iter()
+        .filter(|x| present.contains(&x.0))
+        .copied()
+        .collect();
+    let removing: HashSet<_> = removed.iter().map(|x| x.0).collect();
+    let added = target
+        .removed
+        .iter()
+        .filter(|x| !present.contains(&x.0) || removing.contains(&x.0))
+        .copied()
+        .collect();
+    (Records(removed), Records(added))
+}
+
+/// 撤销最后一条修正.
+pub(crate) async fn undo_last(
+    recorder: &mut Recorder,
+    room_dir: &Path,
+    accounting: &Accounting,
+) -> Result<CorrectionEntry, CSError> {
+    let journal = read_journal(room_dir).await.map_err(|e| {
+        error!(target: "reading correction journal", "{e:?}");
+        CSError::CorrectionJournal
+    })?;
+    let archive_dir = room_dir.join(ARCHIVE_DIRNAME);
+    let target = last_undoable(&journal, |name| {
+        archive_dir.join(format!("{name}.csv")).exists()
+    })
+    .cloned()
+    .ok_or(CSError::NothingToUndo)?;
+    correct(
+        recorder,
+        room_dir,
+        accounting,
+        target.archive.clone(),
+        CorrectionOp::Undo { id: target.id },
+        |records| Ok(undo_plan(&target, records)),
+    )
+    .await
+}
+
+/// 找到 `time` 时间点对应的记录.
+pub(crate) fn find_record(
+    records: &Records,
+    time: &DateTime<FixedOffset>,
+) -> Result<(DateTime<FixedOffset>, f32), CSError> {
+    records
+        .iter()
+        .find(|x| x.0 == *time)
+        .copied()
+        .ok_or(CSError::RecordNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, Local, TimeZone};
+
+    use crate::Records;
+    use crate::server::correction::{
+        CorrectionEntry, CorrectionOp, apply, last_undoable, undo_plan,
+    };
+
+    #[test]
+    fn apply_correction() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |h| offset.with_ymd_and_hms(2026, 1, 24, h, 0, 0).unwrap();
+        let mut records = Records(vec![(t(1), 10.0), (t(2), 99.0), (t(4), 9.0)]);
+        apply(
+            &mut records,
+            &Records(vec![(t(2), 99.0)]),
+            &Records(vec![(t(2), 9.8), (t(3), 9.5)]),
+        );
+        assert_eq!(
+            records.0,
+            vec![(t(1), 10.0), (t(2), 9.8), (t(3), 9.5), (t(4), 9.0)]
+        );
+    }
+
+    #[test]
+    fn undo_skips_undone_entries() {
+        let entry = |id, op| CorrectionEntry {
+            id,
+            created_at: Local::now().fixed_offset(),
+            archive: None,
+            op,
+            removed: Records(Vec::new()),
+            added: Records(Vec::new()),
+        };
+        let journal = vec![
+            entry(1, CorrectionOp::Delete),
+            entry(2, CorrectionOp::Correct),
+            entry(3, CorrectionOp::Undo { id: 2 }),
+        ];
+        assert_eq!(last_undoable(&journal, |_| true).map(|e| e.id), Some(1));
+        let journal = vec![
+            entry(1, CorrectionOp::Delete),
+            entry(2, CorrectionOp::Undo { id: 1 }),
+        ];
+        assert!(last_undoable(&journal, |_| true).is_none());
+
+        // 目标 archive 已经被删除的修正被跳过.
+        let mut journal = vec![
+            entry(1, CorrectionOp::Delete),
+            entry(2, CorrectionOp::Delete),
+        ];
+        journal[1].archive = Some("deleted".to_string());
+        assert_eq!(
+            last_undoable(&journal, |name| name != "deleted").map(|e| e.id),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn undo_keeps_timestamps_unique() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |h| offset.with_ymd_and_hms(2026, 1, 24, h, 0, 0).unwrap();
+        let target = CorrectionEntry {
+            id: 1,
+            created_at: Local::now().fixed_offset(),
+            archive: None,
+            op: CorrectionOp::Correct,
+            removed: Records(vec![(t(1), 99.0), (t(2), 10.0), (t(3), 9.0)]),
+            added: Records(vec![(t(1), 9.9)]),
+        };
+        // t(2) 删除之后又被重新导入.
+        let records = Records(vec![(t(1), 9.9), (t(2), 10.0)]);
+        let (removed, added) = undo_plan(&target, &records);
+        assert_eq!(removed.0, vec![(t(1), 9.9)]);
+        assert_eq!(added.0, vec![(t(1), 99.0), (t(3), 9.0)]);
+    }
+}
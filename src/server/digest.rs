@@ -0,0 +1,470 @@
+//! 用电摘要.
+//!
+//! 摘要包括前一天的用电量, 本周与上周同期的对比, 剩余度数, 预计耗尽时间, 以及摘要时间范围内的充值和查询中断,
+//! 渲染为纯文本和 HTML 两种格式. 在 `server.toml` 的 `[digest]` 中配置后, 服务端每天 (或每周) 在指定的整点
+//! 把摘要发送到配置的渠道, 渠道与告警渠道相同, 邮件同时包含纯文本和 HTML, webhook 则 POST 摘要的 JSON.
+//! 无论是否配置, 都可以通过 `/get-digest` 接口获取.
+use std::fmt::Write;
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, TimeDelta, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::analytics::{self, Accounting, Forecast, Recharge};
+use crate::config::{DigestConfig, DigestSchedule};
+use crate::server::alert::{self, Notice};
+use crate::server::{AppState, Outage};
+use crate::{CSError, TimeSpan};
+
+/// 短于此时长且已经恢复的查询中断不计入摘要, 避免偶尔一次查询失败带来的干扰.
+const MIN_OUTAGE: TimeDelta = TimeDelta::minutes(5);
+
+/// 摘要的输出格式.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DigestFormat {
+    #[default]
+    Json,
+    Text,
+    Html,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub schedule: DigestSchedule,
+    pub generated_at: DateTime<FixedOffset>,
+    /// 摘要的时间范围: 每日摘要为前一天, 每周摘要为最近 7 天, 充值和查询中断按照此范围统计.
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+    /// 前一天的用电量 (度).
+    pub yesterday: f32,
+    /// 前一天的电费, 没有配置电价时为 None.
+    pub yesterday_cost: Option<f32>,
+    /// 本周 (从周一开始) 到目前为止的用电量.
+    pub this_week: f32,
+    /// 上周同期, 即上周一到上周的此刻的用电量.
+    pub last_week_to_date: f32,
+    /// 上周全周的用电量.
+    pub last_week: f32,
+    /// 本周相对上周同期的变化比例, 如 -0.1 表示减少 10%, 上周同期没有用电时为 None.
+    pub week_change: Option<f32>,
+    /// 最后一条记录的剩余度数.
+    pub degree: Option<f32>,
+    pub degree_time: Option<DateTime<FixedOffset>>,
+    pub forecast: Option<Forecast>,
+    pub recharges: Vec<Recharge>,
+    pub outages: Vec<Outage>,
+}
+
+/// 根据记录生成摘要, `records` 需要已经按照时间排序.
+pub(crate) fn build<Tz: TimeZone>(
+    schedule: DigestSchedule,
+    records: &[(DateTime<FixedOffset>, f32)],
+    outages: &[Outage],
+    now: &DateTime<Tz>,
+    accounting: &Accounting,
+) -> Result<Digest, CSError> {
+    let resolve = |expr| TimeSpan::resolve(expr, now, &[]);
+    let window = resolve(match schedule {
+        DigestSchedule::Daily => "yesterday",
+        DigestSchedule::Weekly => "last:7d",
+    })?;
+    let (Some(start_time), Some(end_time)) = (window.start_time, window.end_time) else {
+        unreachable!("resolved spans are bounded");
+    };
+    let yesterday = analytics::cost_report(records, &resolve("yesterday")?, accounting);
+    let consumption =
+        |span: &TimeSpan| analytics::cost_report(records, span, accounting).consumption;
+    let this_week = resolve("week:this")?;
+    let last_week = resolve("week:last")?;
+    let elapsed = now.fixed_offset() - this_week.start_time.unwrap_or(start_time);
+    let last_week_to_date = TimeSpan::new(
+        last_week.start_time,
+        last_week.start_time.map(|t| t + elapsed),
+    );
+    let (this_week, last_week_to_date, last_week) = (
+        consumption(&this_week),
+        consumption(&last_week_to_date),
+        consumption(&last_week),
+    );
+    let in_window = analytics::within(records, &window);
+    Ok(Digest {
+        schedule,
+        generated_at: now.fixed_offset(),
+        start_time,
+        end_time,
+        yesterday: yesterday.consumption,
+        yesterday_cost: yesterday.cost,
+        this_week,
+        last_week_to_date,
+        last_week,
+        week_change: (last_week_to_date > 0.0).then(|| this_week / last_week_to_date - 1.0),
+        degree: records.last().map(|x| x.1),
+        degree_time: records.last().map(|x| x.0),
+        forecast: analytics::forecast(records),
+        recharges: analytics::recharges(in_window),
+        outages: outages
+            .iter()
+            .filter(|o| {
+                o.start_time <= end_time
+                    && o.end_time
+                        .is_none_or(|end| end >= start_time && end - o.start_time >= MIN_OUTAGE)
+            })
+            .cloned()
+            .collect(),
+    })
+}
+
+fn format_time(time: &DateTime<FixedOffset>) -> String {
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Digest {
+    #[must_use]
+    pub fn title(&self) -> String {
+        match self.schedule {
+            DigestSchedule::Daily => {
+                format!("宿舍每日用电摘要 ({})", self.start_time.format("%Y-%m-%d"))
+            }
+            DigestSchedule::Weekly => format!(
+                "宿舍每周用电摘要 ({} ~ {})",
+                self.start_time.format("%Y-%m-%d"),
+                self.end_time.format("%Y-%m-%d")
+            ),
+        }
+    }
+
+    /// 摘要的各项 (名称, 内容).
+    fn rows(&self) -> Vec<(&'static str, String)> {
+        let mut rows = Vec::new();
+        let cost = self
+            .yesterday_cost
+            .map(|c| format!(" (约 {c:.2} 元)"))
+            .unwrap_or_default();
+        rows.push(("昨日用电", format!("{:.2} 度{cost}", self.yesterday)));
+        let change = self
+            .week_change
+            .map(|c| format!(" ({:+.1}%)", c * 100.0))
+            .unwrap_or_default();
+        rows.push((
+            "本周用电",
+            format!(
+                "{:.2} 度, 上周同期 {:.2} 度{change}, 上周全周 {:.2} 度",
+                self.this_week, self.last_week_to_date, self.last_week
+            ),
+        ));
+        if let (Some(degree), Some(time)) = (self.degree, &self.degree_time) {
+            rows.push((
+                "剩余电量",
+                format!("{degree:.2} 度 ({})", format_time(time)),
+            ));
+        }
+        let depletion = match &self.forecast {
+            Some(Forecast {
+                depletion_time: Some(t),
+                earliest,
+                latest,
+                ..
+            }) => {
+                let range = match (earliest, latest) {
+                    (Some(e), Some(l)) => format!(" ({} ~ {})", format_time(e), format_time(l)),
+                    _ => String::new(),
+                };
+                format!("{}{range}", format_time(t))
+            }
+            Some(_) => "近期不会耗尽".to_string(),
+            None => "记录不足, 无法预测".to_string(),
+        };
+        rows.push(("预计耗尽", depletion));
+        rows
+    }
+
+    fn recharge_lines(&self) -> Vec<String> {
+        self.recharges
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} 充值 {:.2} 度, 充值后 {:.2} 度",
+                    format_time(&r.time),
+                    r.amount,
+                    r.after
+                )
+            })
+            .collect()
+    }
+
+    fn outage_lines(&self) -> Vec<String> {
+        self.outages
+            .iter()
+            .map(|o| {
+                let end = o
+                    .end_time
+                    .as_ref()
+                    .map_or_else(|| "未恢复".to_string(), format_time);
+                let reason = if o.logged_out {
+                    "登录失效".to_string()
+                } else {
+                    o.error.clone()
+                };
+                format!("{} ~ {end}: {reason}", format_time(&o.start_time))
+            })
+            .collect()
+    }
+
+    /// 纯文本格式的摘要.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n\n", self.title());
+        for (name, value) in self.rows() {
+            writeln!(text, "{name}: {value}").unwrap();
+        }
+        for (name, lines) in [
+            ("充值", self.recharge_lines()),
+            ("查询中断", self.outage_lines()),
+        ] {
+            if lines.is_empty() {
+                writeln!(text, "{name}: 无").unwrap();
+            } else {
+                writeln!(text, "{name}:").unwrap();
+                for line in lines {
+                    writeln!(text, "  - {line}").unwrap();
+                }
+            }
+        }
+        text
+    }
+
+    /// HTML 格式的摘要, 用于邮件.
+    #[must_use]
+    pub fn to_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h2>{0}</h2>\n<table>\n",
+            escape_html(&self.title())
+        );
+        for (name, value) in self.rows() {
+            writeln!(
+                html,
+                "<tr><th align=\"left\">{name}</th><td>{}</td></tr>",
+                escape_html(&value)
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n");
+        for (name, lines) in [
+            ("充值", self.recharge_lines()),
+            ("查询中断", self.outage_lines()),
+        ] {
+            writeln!(html, "<h3>{name}</h3>").unwrap();
+            if lines.is_empty() {
+                html.push_str("<p>无</p>\n");
+            } else {
+                html.push_str("<ul>\n");
+                for line in lines {
+                    writeln!(html, "<li>{}</li>", escape_html(&line)).unwrap();
+                }
+                html.push_str("</ul>\n");
+            }
+        }
+        html.push_str("</body></html>\n");
+        html
+    }
+}
+
+/// 根据当前的记录和查询状态生成摘要.
+pub(crate) async fn generate(
+    state: &AppState,
+    schedule: DigestSchedule,
+) -> Result<Digest, CSError> {
+    let mut records = state
+        .recorder
+        .write()
+        .await
+        .read_records()
+        .await
+        .map_err(|e| {
+            error!(target: "reading records", "{e:?}");
+            CSError::ReadRecords
+        })?;
+    records.sort();
+    let outages: Vec<_> = state
+        .query_status
+        .read()
+        .await
+        .outages
+        .iter()
+        .cloned()
+        .collect();
+    build(
+        schedule,
+        &records,
+        &outages,
+        &Local::now(),
+        &state.accounting,
+    )
+}
+
+/// `now` 之后下一次发送摘要的时间.
+fn next_run<Tz: TimeZone>(config: &DigestConfig, now: &DateTime<Tz>) -> DateTime<Tz> {
+    let today = now.date_naive();
+    (0..=8)
+        .filter_map(|i| {
+            let date = today + TimeDelta::days(i);
+            if config.schedule == DigestSchedule::Weekly && date.weekday() != config.weekday {
+                return None;
+            }
+            let time = date.and_hms_opt(config.hour, 0, 0)?;
+            now.timezone().from_local_datetime(&time).earliest()
+        })
+        .find(|t| t > now)
+        .expect("a run time within 8 days")
+}
+
+pub(crate) async fn digest_loop(state: Arc<AppState>) -> ! {
+    let config = state
+        .server_config
+        .digest
+        .clone()
+        .expect("digest loop requires digest config");
+    let client = reqwest::Client::new();
+    loop {
+        let now = Local::now();
+        let next = next_run(&config, &now);
+        info!("next digest at {next}");
+        tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+        let digest = match generate(&state, config.schedule).await {
+            Ok(x) => x,
+            Err(e) => {
+                error!("generating digest: {e:?}");
+                continue;
+            }
+        };
+        let (title, text, html) = (digest.title(), digest.to_text(), digest.to_html());
+        let payload = json!({
+            "title": title,
+            "text": text,
+            "html": html,
+            "digest": digest,
+        });
+        let notice = Notice {
+            title: &title,
+            text: &text,
+            html: Some(&html),
+            payload: &payload,
+        };
+        let deliveries = alert::deliver_all(&client, &config.channels, &notice).await;
+        let delivered = deliveries.iter().filter(|d| d.error.is_none()).count();
+        info!(
+            "digest delivered to {delivered}/{} channels",
+            deliveries.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeDelta, TimeZone, Weekday};
+
+    use crate::analytics::Accounting;
+    use crate::config::{DigestConfig, DigestSchedule};
+    use crate::server::Outage;
+    use crate::server::digest::{build, next_run};
+
+    #[test]
+    fn build_and_render() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        // 2026-10-05 为周一, 每小时用电 0.1 度, 10-12 12:00 充值.
+        let t0 = tz.with_ymd_and_hms(2026, 10, 5, 0, 0, 0).unwrap();
+        let mut records = Vec::new();
+        let mut degree = 100.0;
+        for h in 0..(13 * 24 + 8) {
+            if h == 7 * 24 + 12 {
+                degree += 50.0;
+            }
+            records.push((t0 + TimeDelta::hours(h), degree));
+            degree -= 0.1;
+        }
+        let now = tz.with_ymd_and_hms(2026, 10, 18, 8, 0, 0).unwrap();
+        let outages = vec![
+            Outage {
+                start_time: tz.with_ymd_and_hms(2026, 10, 17, 3, 0, 0).unwrap(),
+                end_time: Some(tz.with_ymd_and_hms(2026, 10, 17, 3, 40, 0).unwrap()),
+                logged_out: true,
+                error: "not logged in".to_string(),
+            },
+            Outage {
+                start_time: tz.with_ymd_and_hms(2026, 10, 17, 5, 0, 0).unwrap(),
+                end_time: Some(tz.with_ymd_and_hms(2026, 10, 17, 5, 0, 10).unwrap()),
+                logged_out: false,
+                error: "timeout".to_string(),
+            },
+        ];
+        let accounting = Accounting {
+            hourly_prices: Some([0.6; 24]),
+            emission_factor: None,
+        };
+        let daily = build(DigestSchedule::Daily, &records, &outages, &now, &accounting).unwrap();
+        assert!((daily.yesterday - 2.4).abs() < 0.05);
+        assert!((daily.yesterday_cost.unwrap() - daily.yesterday * 0.6).abs() < 0.01);
+        assert!(daily.recharges.is_empty());
+        // 短暂的失败不计入.
+        assert_eq!(daily.outages, outages[..1]);
+        assert!(daily.week_change.unwrap().abs() < 0.05);
+        let text = daily.to_text();
+        assert!(text.starts_with("宿舍每日用电摘要 (2026-10-17)"));
+        assert!(text.contains("登录失效"));
+        assert!(daily.to_html().contains("<th align=\"left\">昨日用电</th>"));
+
+        let weekly = build(
+            DigestSchedule::Weekly,
+            &records,
+            &outages,
+            &now,
+            &accounting,
+        )
+        .unwrap();
+        assert_eq!(weekly.recharges.len(), 1);
+        assert!(weekly.to_text().contains("充值 49.90 度"));
+    }
+
+    #[test]
+    fn schedule() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        // 周日.
+        let now = tz.with_ymd_and_hms(2026, 10, 18, 9, 30, 0).unwrap();
+        let mut config = DigestConfig {
+            schedule: DigestSchedule::Daily,
+            hour: 8,
+            weekday: Weekday::Mon,
+            channels: Vec::new(),
+        };
+        assert_eq!(
+            next_run(&config, &now),
+            tz.with_ymd_and_hms(2026, 10, 19, 8, 0, 0).unwrap()
+        );
+        config.hour = 10;
+        assert_eq!(
+            next_run(&config, &now),
+            tz.with_ymd_and_hms(2026, 10, 18, 10, 0, 0).unwrap()
+        );
+        config.schedule = DigestSchedule::Weekly;
+        config.weekday = Weekday::Sun;
+        assert_eq!(
+            next_run(&config, &now),
+            tz.with_ymd_and_hms(2026, 10, 18, 10, 0, 0).unwrap()
+        );
+        config.hour = 8;
+        assert_eq!(
+            next_run(&config, &now),
+            tz.with_ymd_and_hms(2026, 10, 25, 8, 0, 0).unwrap()
+        );
+    }
+}
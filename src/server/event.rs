@@ -0,0 +1,236 @@
+//! 服务端事件.
+//!
+//! 后台查询记录新的读数, 检测到充值以及 ECNU 登录状态变化, 或者通过接口切换房间, 创建 archive 时, 以及后台检测到用电异常, 离开期间用电超限和预算超支时,
+//! 通过 [`AppState`] 中的 [`EventHub`] 发送事件, 供 `/subscribe` 和 MQTT 等推送渠道使用.
+//!
+//! 每个事件带有编号 `<服务端启动时间戳>-<序号>`, 服务端保留最近 [`HISTORY_LEN`] 个事件, 订阅时给出最后收到的事件编号即可补发之后的事件.
+//! 服务端重启或者错过的事件已经不在历史中时, 无法补发, 改为发送一个 [`ServerEvent::Resync`].
+//!
+//! [`AppState`]: crate::server::AppState
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, FixedOffset};
+use futures::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::analytics::Recharge;
+use crate::config::RoomConfig;
+use crate::server::{AnomalyEvent, ArchiveMeta, AwayPeriod, BudgetProgress};
+
+/// 保留用于补发的事件数.
+const HISTORY_LEN: usize = 256;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ServerEvent {
+    /// 记录了新的读数.
+    Reading {
+        time: DateTime<FixedOffset>,
+        degree: f32,
+    },
+    /// 新的读数相对上一条是充值.
+    Recharge(Recharge),
+    /// 服务端的 ECNU 登录失效.
+    LoginLost { error: String },
+    /// 启动后或者登录失效之后第一次查询成功.
+    LoginRestored,
+    /// 切换了宿舍房间, 清除房间时为空的房间配置.
+    RoomChanged(RoomConfig),
+    /// 创建 (或者导入) 了 archive.
+    ArchiveCreated(ArchiveMeta),
+    /// 检测到新的用电异常, 或者已有异常的严重程度升高.
+    Anomaly(AnomalyEvent),
+    /// 离开期间超出基础负载的用电量超过了上限.
+    AwayLimitExceeded(AwayPeriod),
+    /// 离开时间段结束, 附带最终的用电报告.
+    AwayEnded(AwayPeriod),
+    /// 按照当前速度预算会在周期结束前用完.
+    BudgetOverPace(BudgetProgress),
+    /// 已经超出预算.
+    BudgetExceeded(BudgetProgress),
+    /// 订阅者可能错过了事件, 应当重新获取完整的状态.
+    Resync,
+}
+
+/// 带有编号的事件.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EventEnvelope {
+    pub id: String,
+    pub event: ServerEvent,
+}
+
+#[derive(Debug)]
+pub(crate) struct EventHub {
+    /// 服务端启动的时间戳, 作为事件编号的前缀.
+    epoch: i64,
+    tx: broadcast::Sender<EventEnvelope>,
+    /// 最后一个事件的序号以及最近的事件, 发送和订阅在同一个锁内进行, 保证补发和之后收到的事件不重不漏.
+    history: Mutex<(u64, VecDeque<(u64, EventEnvelope)>)>,
+}
+
+impl EventHub {
+    pub(crate) fn new(epoch: i64) -> Self {
+        Self {
+            epoch,
+            tx: broadcast::channel(64).0,
+            history: Mutex::new((0, VecDeque::with_capacity(HISTORY_LEN))),
+        }
+    }
+
+    /// 发送事件, 没有订阅者时只保存在历史中.
+    pub(crate) fn send(&self, event: ServerEvent) {
+        let mut history = self.history.lock().unwrap();
+        let (seq, events) = &mut *history;
+        *seq += 1;
+        let envelope = EventEnvelope {
+            id: format!("{}-{seq}", self.epoch),
+            event,
+        };
+        if events.len() == HISTORY_LEN {
+            events.pop_front();
+        }
+        events.push_back((*seq, envelope.clone()));
+        self.tx.send(envelope).ok();
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+
+    /// 编号为最后一个事件的 [`ServerEvent::Resync`].
+    fn resync(&self) -> EventEnvelope {
+        let seq = self.history.lock().unwrap().0;
+        EventEnvelope {
+            id: format!("{}-{seq}", self.epoch),
+            event: ServerEvent::Resync,
+        }
+    }
+
+    /// 订阅 `last_id` 之后的事件, 返回需要补发的事件和接收之后事件的接收端.
+    fn subscribe_after(
+        &self,
+        last_id: Option<&str>,
+    ) -> (Vec<EventEnvelope>, broadcast::Receiver<EventEnvelope>) {
+        let history = self.history.lock().unwrap();
+        let rx = self.tx.subscribe();
+        let Some(last_id) = last_id else {
+            return (Vec::new(), rx);
+        };
+        let (seq, events) = &*history;
+        let last = last_id
+            .split_once('-')
+            .and_then(|(epoch, seq)| Some((epoch.parse::<i64>().ok()?, seq.parse::<u64>().ok()?)))
+            .filter(|&(epoch, _)| epoch == self.epoch)
+            .map(|(_, last)| last);
+        let oldest = events.front().map_or(seq + 1, |(s, _)| *s);
+        let replay = match last {
+            Some(last) if last <= *seq && last + 1 >= oldest => events
+                .iter()
+                .filter(|(s, _)| *s > last)
+                .map(|(_, e)| e.clone())
+                .collect(),
+            _ => vec![EventEnvelope {
+                id: format!("{}-{seq}", self.epoch),
+                event: ServerEvent::Resync,
+            }],
+        };
+        (replay, rx)
+    }
+
+    /// `last_id` 之后的事件流, 先补发错过的事件, 接收端积压过多时发送 [`ServerEvent::Resync`].
+    pub(crate) fn stream(
+        self: &std::sync::Arc<Self>,
+        last_id: Option<&str>,
+    ) -> impl Stream<Item = EventEnvelope> + use<> {
+        let (replay, rx) = self.subscribe_after(last_id);
+        let live = stream::unfold(
+            (rx, std::sync::Arc::clone(self)),
+            |(mut rx, hub)| async move {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => hub.resync(),
+                    Err(RecvError::Closed) => return None,
+                };
+                Some((event, (rx, hub)))
+            },
+        );
+        stream::iter(replay).chain(live)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{FixedOffset, TimeZone};
+    use futures::StreamExt;
+    use serde_json::json;
+
+    use crate::analytics::{Anomaly, Severity};
+    use crate::server::AnomalyEvent;
+    use crate::server::event::{EventHub, HISTORY_LEN, ServerEvent};
+
+    #[tokio::test]
+    async fn resume_after_last_id() {
+        let hub = Arc::new(EventHub::new(100));
+        hub.send(ServerEvent::LoginRestored);
+        hub.send(ServerEvent::LoginLost {
+            error: "expired".to_string(),
+        });
+
+        let ids = |events: &[crate::server::EventEnvelope]| {
+            events.iter().map(|e| e.id.clone()).collect::<Vec<_>>()
+        };
+        let (replay, _) = hub.subscribe_after(None);
+        assert!(replay.is_empty());
+        let (replay, _) = hub.subscribe_after(Some("100-1"));
+        assert_eq!(ids(&replay), ["100-2"]);
+        let (replay, _) = hub.subscribe_after(Some("100-2"));
+        assert!(replay.is_empty());
+        // 服务端重启前的编号.
+        let (replay, _) = hub.subscribe_after(Some("99-7"));
+        assert_eq!(replay[0].event, ServerEvent::Resync);
+        assert_eq!(replay[0].id, "100-2");
+
+        let mut stream = Box::pin(hub.stream(Some("100-1")));
+        hub.send(ServerEvent::LoginRestored);
+        assert_eq!(stream.next().await.unwrap().id, "100-2");
+        assert_eq!(stream.next().await.unwrap().id, "100-3");
+
+        // 错过的事件已经不在历史中.
+        for _ in 0..HISTORY_LEN {
+            hub.send(ServerEvent::LoginRestored);
+        }
+        let (replay, _) = hub.subscribe_after(Some("100-2"));
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].event, ServerEvent::Resync);
+        let (replay, _) = hub.subscribe_after(Some("100-3"));
+        assert_eq!(replay.len(), HISTORY_LEN);
+    }
+
+    #[test]
+    fn anomaly_event_json() {
+        let t = FixedOffset::east_opt(8 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2026, 3, 2, 1, 0, 0)
+            .unwrap();
+        let event = ServerEvent::Anomaly(AnomalyEvent {
+            id: 3,
+            anomaly: Anomaly {
+                start_time: t,
+                end_time: t,
+                consumption: 1.5,
+                expected: 0.25,
+                peak_rate: 1.5,
+                severity: Severity::High,
+            },
+            detected_at: t,
+        });
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["kind"], json!("anomaly"));
+        assert_eq!(value["severity"], json!("high"));
+        assert_eq!(serde_json::from_value::<ServerEvent>(value).unwrap(), event);
+    }
+}
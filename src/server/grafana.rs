@@ -0,0 +1,277 @@
+//! Grafana JSON 数据源.
+//!
+//! 按照 Grafana JSON 数据源插件的约定实现 `/grafana` 下的 `search`, `query` 和 `annotations` 接口,
+//! 在 Grafana 中添加 JSON 数据源并把地址设为 `https://<server>/grafana` 即可直接绘制记录和 archive 的曲线.
+//!
+//! 可用的查询目标:
+//! - `records`: 当前记录.
+//! - `all`: 当前记录以及与查询范围重叠的 archive 中的记录.
+//! - `archive:<archive 名称>`: 一个 archive 中的记录.
+//!
+//! 数据点多于 Grafana 请求的 `maxDataPoints` 时在服务端降采样, 标注为查询范围内的充值.
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::{self, Recharge};
+use crate::config::ARCHIVE_DIRNAME;
+use crate::server::{AppState, collect_records, load_archive, read_archive_metas};
+use crate::{CSError, TimeSpan};
+
+type Record = (DateTime<FixedOffset>, f32);
+
+/// 标注查询未指定目标时使用的目标.
+const DEFAULT_ANNOTATION_TARGET: &str = "all";
+
+const ARCHIVE_TARGET_PREFIX: &str = "archive:";
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GrafanaRange {
+    pub(crate) from: DateTime<FixedOffset>,
+    pub(crate) to: DateTime<FixedOffset>,
+}
+
+impl GrafanaRange {
+    fn time_span(&self) -> TimeSpan {
+        TimeSpan::new(Some(self.from), Some(self.to))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct GrafanaSearch {
+    /// 查询目标需要包含的文本.
+    #[serde(default)]
+    pub(crate) target: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GrafanaTarget {
+    #[serde(default)]
+    pub(crate) target: String,
+    #[serde(default)]
+    pub(crate) hide: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GrafanaQuery {
+    pub(crate) range: GrafanaRange,
+    pub(crate) max_data_points: Option<usize>,
+    pub(crate) targets: Vec<GrafanaTarget>,
+}
+
+/// 一条时间序列, 数据点为 `[度数, 毫秒时间戳]`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct GrafanaSeries {
+    pub(crate) target: String,
+    pub(crate) datapoints: Vec<(f64, i64)>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct GrafanaAnnotationQuery {
+    /// 标注所用的查询目标, 为空时为 [`DEFAULT_ANNOTATION_TARGET`].
+    #[serde(default)]
+    pub(crate) query: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GrafanaAnnotations {
+    pub(crate) range: GrafanaRange,
+    #[serde(default)]
+    pub(crate) annotation: GrafanaAnnotationQuery,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct GrafanaAnnotation {
+    /// 毫秒时间戳.
+    pub(crate) time: i64,
+    pub(crate) title: String,
+    pub(crate) text: String,
+    pub(crate) tags: Vec<String>,
+}
+
+impl From<&Recharge> for GrafanaAnnotation {
+    fn from(recharge: &Recharge) -> Self {
+        Self {
+            time: recharge.time.timestamp_millis(),
+            title: format!("充值 {:.2} 度", recharge.amount),
+            text: format!("{:.2} → {:.2}", recharge.before, recharge.after),
+            tags: vec!["recharge".to_string()],
+        }
+    }
+}
+
+/// 降采样到不超过 `max_points` 个数据点, `records` 需要已经排好序.
+///
+/// 把时间范围等分为 `max_points / 2` 段, 每段保留度数最低和最高的记录, 因此充值带来的跳变和每段内的用电量都能保留下来.
+pub(crate) fn downsample(records: &[Record], max_points: usize) -> Vec<Record> {
+    let buckets = max_points / 2;
+    if records.len() <= max_points || buckets == 0 {
+        return records.iter().take(max_points.max(1)).copied().collect();
+    }
+    let (first, last) = (records[0].0, records[records.len() - 1].0);
+    let width = (last - first).num_milliseconds().max(1);
+    let bucket_of = |time: &DateTime<FixedOffset>| {
+        let offset = (*time - first).num_milliseconds();
+        let buckets = i64::try_from(buckets).unwrap_or(i64::MAX);
+        (offset.saturating_mul(buckets) / width).min(buckets - 1)
+    };
+    let mut sampled = Vec::with_capacity(buckets * 2);
+    for bucket in records.chunk_by(|a, b| bucket_of(&a.0) == bucket_of(&b.0)) {
+        let min = bucket
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.1.total_cmp(&b.1.1))
+            .map(|(i, _)| i)
+            .unwrap();
+        let max = bucket
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.1.total_cmp(&b.1.1))
+            .map(|(i, _)| i)
+            .unwrap();
+        sampled.push(bucket[min.min(max)]);
+        if min != max {
+            sampled.push(bucket[min.max(max)]);
+        }
+    }
+    sampled
+}
+
+/// 所有可用的查询目标.
+pub(crate) async fn search(
+    state: &AppState,
+    search: &GrafanaSearch,
+) -> Result<Vec<String>, CSError> {
+    let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
+    let mut targets = vec!["records".to_string(), "all".to_string()];
+    targets.extend(
+        read_archive_metas(&archive_dir)
+            .await?
+            .into_iter()
+            .map(|meta| format!("{ARCHIVE_TARGET_PREFIX}{}", meta.archive_name)),
+    );
+    targets.retain(|t| t.contains(&search.target));
+    Ok(targets)
+}
+
+/// 查询目标的记录, 已经排好序, 但不按照时间范围过滤.
+async fn load_target(
+    state: &AppState,
+    target: &str,
+    time_span: &TimeSpan,
+) -> Result<Vec<Record>, CSError> {
+    let mut records = match target {
+        "records" => collect_records(state, time_span, false).await?,
+        "all" => collect_records(state, time_span, true).await?,
+        _ => {
+            let Some(archive_name) = target.strip_prefix(ARCHIVE_TARGET_PREFIX) else {
+                return Err(CSError::UnknownGrafanaTarget);
+            };
+            let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
+            load_archive(&archive_dir, archive_name).await?
+        }
+    };
+    records.sort();
+    Ok(records.0)
+}
+
+/// 查询各个目标在时间范围内的 (降采样后的) 时间序列, 忽略隐藏和空的目标.
+pub(crate) async fn query(
+    state: &AppState,
+    query: &GrafanaQuery,
+) -> Result<Vec<GrafanaSeries>, CSError> {
+    let time_span = query.range.time_span();
+    let mut series = Vec::new();
+    for target in &query.targets {
+        if target.hide || target.target.is_empty() {
+            continue;
+        }
+        let records = load_target(state, &target.target, &time_span).await?;
+        let records = analytics::within(&records, &time_span);
+        let records = match query.max_data_points {
+            Some(max_points) => downsample(records, max_points),
+            None => records.to_vec(),
+        };
+        series.push(GrafanaSeries {
+            target: target.target.clone(),
+            datapoints: records
+                .iter()
+                // 读数精确到 0.01 度, 避免 f32 转换带来的多余位数.
+                .map(|(time, degree)| {
+                    (
+                        (f64::from(*degree) * 100.0).round() / 100.0,
+                        time.timestamp_millis(),
+                    )
+                })
+                .collect(),
+        });
+    }
+    Ok(series)
+}
+
+/// 时间范围内的充值.
+pub(crate) async fn annotations(
+    state: &AppState,
+    annotations: &GrafanaAnnotations,
+) -> Result<Vec<GrafanaAnnotation>, CSError> {
+    let time_span = annotations.range.time_span();
+    let target = match annotations.annotation.query.trim() {
+        "" => DEFAULT_ANNOTATION_TARGET,
+        target => target,
+    };
+    let records = load_target(state, target, &time_span).await?;
+    Ok(analytics::recharges(&records)
+        .iter()
+        .filter(|recharge| time_span.contains(&recharge.time))
+        .map(GrafanaAnnotation::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeDelta, TimeZone};
+
+    use crate::server::grafana::{GrafanaQuery, downsample};
+
+    #[test]
+    fn downsample_keeps_extremes() {
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let start = tz.with_ymd_and_hms(2026, 9, 1, 0, 0, 0).unwrap();
+        // 每 10 分钟消耗 0.1 度, 第 500 条记录时充值 50 度.
+        let records: Vec<_> = (0..1000)
+            .map(|i| {
+                let degree = 100.0 - 0.1 * i as f32 + if i >= 500 { 50.0 } else { 0.0 };
+                (start + TimeDelta::minutes(10 * i64::from(i)), degree)
+            })
+            .collect();
+
+        assert_eq!(downsample(&records[..10], 100), &records[..10]);
+        let sampled = downsample(&records, 100);
+        assert!(sampled.len() <= 100);
+        assert!(sampled.is_sorted_by_key(|r| r.0));
+        assert_eq!(sampled[0], records[0]);
+        assert_eq!(sampled[sampled.len() - 1], records[999]);
+        // 充值前后的两条记录都被保留.
+        assert!(sampled.contains(&records[499]));
+        assert!(sampled.contains(&records[500]));
+
+        assert_eq!(downsample(&records, 1), &records[..1]);
+    }
+
+    #[test]
+    fn parse_query() {
+        let query: GrafanaQuery = serde_json::from_str(
+            r#"{
+                "range": {"from": "2026-09-01T00:00:00.000Z", "to": "2026-09-02T00:00:00.000Z", "raw": {"from": "now-1d", "to": "now"}},
+                "intervalMs": 60000,
+                "maxDataPoints": 500,
+                "targets": [{"target": "records", "refId": "A"}, {"target": "archive:2026", "refId": "B", "hide": true}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(query.max_data_points, Some(500));
+        assert_eq!(query.targets.len(), 2);
+        assert!(query.targets[1].hide);
+        assert_eq!(query.range.to.timestamp(), 1_788_307_200);
+    }
+}
@@ -0,0 +1,322 @@
+//! 从外部 csv 导入电量记录, 例如 python 版本留下的数据或者其他工具导出的读数.
+//!
+//! 解析是宽松的:
+//! - 可以有表头, 表头中包含 `time` / `date` / `时间` 和 `degree` / `kwh` / `电量` 的列会被识别;
+//! - 每一行可以使用逗号或制表符分隔;
+//! - 时间支持 RFC 3339, `2026-01-24 14:35:32` 等常见格式 (无时区时按照服务端时区解释), 以及 unix 时间戳 (10 位的秒或 13 位的毫秒);
+//! - 空行和 `#` 开头的行会被跳过, 其余无法解析的行会记录在 [`ImportReport::issues`] 中.
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::Accounting;
+use crate::server::{ArchiveMeta, Recorder, save_archive};
+use crate::{CSError, Records};
+
+/// 导入方式.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportMode {
+    /// 合并到当前房间的 records.csv 中.
+    #[default]
+    Merge,
+    /// 直接作为一个新的 archive 导入.
+    Archive,
+}
+
+/// 导入时某一行的问题.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImportIssue {
+    /// 行号 (从 1 开始).
+    pub line: usize,
+    pub content: String,
+    pub reason: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ImportReport {
+    /// 文件中成功解析的记录数.
+    pub parsed: usize,
+    /// 实际写入的记录数.
+    pub imported: usize,
+    /// 因为时间点重复而被丢弃的记录数.
+    pub duplicates: usize,
+    /// 以 [`ImportMode::Archive`] 导入时创建的 archive.
+    pub archive: Option<ArchiveMeta>,
+    pub issues: Vec<ImportIssue>,
+}
+
+const TIME_HEADERS: &[&str] = &["time", "date", "时间"];
+const DEGREE_HEADERS: &[&str] = &["degree", "kwh", "电量", "value"];
+
+/// 没有时区信息的时间格式, 按照服务端时区解释.
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M",
+];
+
+/// 带时区信息的时间格式 (RFC 3339 以外).
+const OFFSET_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S%.f%:z", "%Y-%m-%d %H:%M:%S%.f%z"];
+
+fn unquote(field: &str) -> &str {
+    let field = field.trim();
+    field
+        .strip_prefix('"')
+        .and_then(|f| f.strip_suffix('"'))
+        .unwrap_or(field)
+        .trim()
+}
+
+/// 解析一个时间字段, 无法识别时返回 None.
+pub(crate) fn parse_timestamp(field: &str) -> Option<DateTime<FixedOffset>> {
+    let field = unquote(field);
+    if let Ok(t) = DateTime::parse_from_rfc3339(field) {
+        return Some(t);
+    }
+    for fmt in OFFSET_FORMATS {
+        if let Ok(t) = DateTime::parse_from_str(field, fmt) {
+            return Some(t);
+        }
+    }
+    for fmt in NAIVE_FORMATS {
+        if let Ok(t) = NaiveDateTime::parse_from_str(field, fmt) {
+            return Local
+                .from_local_datetime(&t)
+                .earliest()
+                .map(|t| t.fixed_offset());
+        }
+    }
+    // unix 时间戳, 只接受 10 位的秒和 13 位的毫秒, 避免把行号或者度数当作时间.
+    if !field.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let ts: i64 = field.parse().ok()?;
+    let millis = match field.len() {
+        10 => ts * 1000,
+        13 => ts,
+        _ => return None,
+    };
+    DateTime::from_timestamp_millis(millis).map(|t| t.with_timezone(&Local).fixed_offset())
+}
+
+fn split_fields(line: &str) -> Vec<&str> {
+    if line.contains('\t') {
+        line.split('\t').collect()
+    } else {
+        line.split(',').collect()
+    }
+}
+
+/// 根据表头确定时间和电量所在的列, 如果这一行不像表头, 返回 None.
+fn header_columns(fields: &[&str]) -> Option<(usize, usize)> {
+    if fields
+        .iter()
+        .any(|f| parse_timestamp(f).is_some() || unquote(f).parse::<f32>().is_ok())
+    {
+        return None;
+    }
+    let find = |names: &[&str]| {
+        fields.iter().position(|f| {
+            let f = unquote(f).to_lowercase();
+            names.iter().any(|n| f.contains(n))
+        })
+    };
+    let time_col = find(TIME_HEADERS).unwrap_or(0);
+    let degree_col = find(DEGREE_HEADERS).unwrap_or(if time_col == 0 { 1 } else { 0 });
+    Some((time_col, degree_col))
+}
+
+/// 宽松地解析 csv 内容, 返回按照文件顺序排列的记录以及无法解析的行.
+pub(crate) fn parse_lenient(content: &str) -> (Records, Vec<ImportIssue>) {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let mut records = Vec::new();
+    let mut issues = Vec::new();
+    let mut columns = (0, 1);
+    let mut first_row = true;
+
+    for (idx, line) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let fields = split_fields(trimmed);
+        if std::mem::take(&mut first_row)
+            && let Some(cols) = header_columns(&fields)
+        {
+            columns = cols;
+            continue;
+        }
+        let issue = |reason: &str| ImportIssue {
+            line: line_no,
+            content: line.to_string(),
+            reason: reason.to_string(),
+        };
+        let (Some(time), Some(degree)) = (fields.get(columns.0), fields.get(columns.1)) else {
+            issues.push(issue("missing column"));
+            continue;
+        };
+        let Some(time) = parse_timestamp(time) else {
+            issues.push(issue("unrecognized timestamp"));
+            continue;
+        };
+        let degree = match unquote(degree).parse::<f32>() {
+            Ok(d) if d.is_finite() => d,
+            _ => {
+                issues.push(issue("invalid degree"));
+                continue;
+            }
+        };
+        records.push((time, degree));
+    }
+    (Records(records), issues)
+}
+
+/// 将 `imported` 中时间点未在 `existing` 出现过的记录合并进去, 结果按照时间排序.
+///
+/// 返回合并后的记录和被丢弃的重复记录数.
+pub(crate) fn merge_records(existing: Records, imported: Records) -> (Records, usize) {
+    let mut seen: HashSet<DateTime<FixedOffset>> = existing.iter().map(|x| x.0).collect();
+    let mut merged = existing;
+    let mut duplicates = 0;
+    for rec in imported.0 {
+        if seen.insert(rec.0) {
+            merged.push(rec);
+        } else {
+            duplicates += 1;
+        }
+    }
+    merged.sort();
+    (merged, duplicates)
+}
+
+/// 将 csv 内容合并到 recorder 对应的记录中.
+pub(crate) async fn import_into_recorder(
+    recorder: &mut Recorder,
+    content: &str,
+) -> crate::Result<ImportReport> {
+    let (parsed, issues) = parse_lenient(content);
+    let parsed_num = parsed.len();
+    let existing = recorder.read_records().await?;
+    let existing_num = existing.len();
+    let (merged, duplicates) = merge_records(existing, parsed);
+    let imported = merged.len() - existing_num;
+    if imported > 0 {
+        recorder.replace(merged).await?;
+    }
+    Ok(ImportReport {
+        parsed: parsed_num,
+        imported,
+        duplicates,
+        archive: None,
+        issues,
+    })
+}
+
+/// 将 csv 内容作为新的 archive 导入.
+pub(crate) async fn import_as_archive(
+    archive_dir: &Path,
+    archive_name: Option<String>,
+    content: &str,
+    accounting: &Accounting,
+) -> Result<ImportReport, CSError> {
+    let (parsed, issues) = parse_lenient(content);
+    let parsed_num = parsed.len();
+    let (records, duplicates) = merge_records(Records(Vec::new()), parsed);
+    let archive_meta = save_archive(archive_dir, archive_name, &records, accounting).await?;
+    Ok(ImportReport {
+        parsed: parsed_num,
+        imported: records.len(),
+        duplicates,
+        archive: Some(archive_meta),
+        issues,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone};
+
+    use crate::Records;
+    use crate::server::import::{merge_records, parse_lenient, parse_timestamp};
+
+    #[test]
+    fn lenient_parsing() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let (records, issues) = parse_lenient(
+            "\
+time\tdegree
+2026-01-24T14:35:32+08:00\t33.43
+
+# comment
+2026-01-24 15:00:00+08:00\t33.00
+1769236532,\"33.43\"
+not-a-time,1.0
+2026-01-24T16:00:00+08:00,abc
+2026-01-24T17:00:00+08:00
+",
+        );
+        assert_eq!(
+            records.0,
+            vec![
+                (
+                    offset.with_ymd_and_hms(2026, 1, 24, 14, 35, 32).unwrap(),
+                    33.43
+                ),
+                (
+                    offset.with_ymd_and_hms(2026, 1, 24, 15, 0, 0).unwrap(),
+                    33.00
+                ),
+                (
+                    offset.with_ymd_and_hms(2026, 1, 24, 14, 35, 32).unwrap(),
+                    33.43
+                ),
+            ]
+        );
+        assert_eq!(
+            issues.iter().map(|i| i.line).collect::<Vec<_>>(),
+            vec![7, 8, 9]
+        );
+    }
+
+    #[test]
+    fn timestamp_digits() {
+        // 10 位为秒, 13 位为毫秒.
+        assert_eq!(
+            parse_timestamp("1769236532").map(|t| t.timestamp()),
+            Some(1_769_236_532)
+        );
+        assert_eq!(
+            parse_timestamp("1769236532123").map(|t| t.timestamp_millis()),
+            Some(1_769_236_532_123)
+        );
+        // 行号, 度数等其他长度的数字不是时间.
+        for field in ["33", "33.43", "999999999999", "1769236532.5", ""] {
+            assert!(parse_timestamp(field).is_none(), "{field}");
+        }
+    }
+
+    #[test]
+    fn header_column_order() {
+        let (records, issues) = parse_lenient("电量,时间\n10.5,2026-01-24T14:35:32+08:00\n");
+        assert!(issues.is_empty());
+        assert_eq!(records.len(), 1);
+        assert!((records[0].1 - 10.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn merge_drops_duplicates() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |h| offset.with_ymd_and_hms(2026, 1, 24, h, 0, 0).unwrap();
+        let existing = Records(vec![(t(1), 10.0), (t(3), 9.0)]);
+        let imported = Records(vec![(t(3), 8.0), (t(2), 9.5), (t(2), 9.4)]);
+        let (merged, duplicates) = merge_records(existing, imported);
+        assert_eq!(duplicates, 2);
+        assert_eq!(merged.0, vec![(t(1), 10.0), (t(2), 9.5), (t(3), 9.0)]);
+    }
+}
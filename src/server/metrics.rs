@@ -0,0 +1,275 @@
+//! Prometheus 指标.
+//!
+//! `/metrics` 以 Prometheus 的文本格式导出当前剩余度数, 后台查询的次数, 耗时和错误, 登录状态, 记录和 archive 的数量,
+//! 以及 tls 证书的过期时间. 此接口与其他接口一样受 mTLS 保护, 启用 tls 时 Prometheus 需要配置客户端证书.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::pem::PemObject;
+use tokio::fs;
+use tracing::error;
+
+use crate::config::ARCHIVE_DIRNAME;
+use crate::error::Error;
+use crate::server::{AppState, read_archive_metas};
+
+/// 查询耗时直方图的桶上界 (秒).
+const LATENCY_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// 启动以来后台查询电量的统计.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct QueryMetrics {
+    queries: u64,
+    /// 按照错误种类统计的失败次数.
+    errors: BTreeMap<&'static str, u64>,
+    /// 耗时不超过各个桶上界的查询次数, 与 [`LATENCY_BUCKETS`] 一一对应.
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+/// 查询错误的种类, 用作指标的标签.
+fn error_kind(e: &Error) -> &'static str {
+    match e {
+        Error::Ecnu(_) => "not_logged_in",
+        Error::NoDegree => "no_degree",
+        Error::Reqwest(e) if e.is_timeout() => "timeout",
+        Error::Reqwest(e) if e.is_connect() => "connect",
+        Error::Reqwest(e) if e.is_decode() => "decode",
+        Error::Reqwest(_) => "http",
+        _ => "other",
+    }
+}
+
+impl QueryMetrics {
+    pub(crate) fn observe(&mut self, latency: Duration, error: Option<&Error>) {
+        self.queries += 1;
+        if let Some(e) = error {
+            *self.errors.entry(error_kind(e)).or_default() += 1;
+        }
+        let seconds = latency.as_secs_f64();
+        self.latency_sum += seconds;
+        for (bucket, count) in LATENCY_BUCKETS.iter().zip(&mut self.latency_buckets) {
+            if seconds <= *bucket {
+                *count += 1;
+            }
+        }
+    }
+}
+
+/// 以 Prometheus 文本格式写入一个指标, `samples` 为 (标签, 值).
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            writeln!(out, "{name} {value}").unwrap();
+        } else {
+            writeln!(out, "{name}{{{labels}}} {value}").unwrap();
+        }
+    }
+}
+
+/// 证书文件中第一个证书的过期时间 (unix 时间戳).
+async fn certificate_expiry(path: &Path) -> Option<i64> {
+    let content = fs::read(path)
+        .await
+        .map_err(|e| error!(target: "reading certificate", "{}: {e:?}", path.display()))
+        .ok()?;
+    let cert = CertificateDer::pem_slice_iter(&content).next()?.ok()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&cert)
+        .map_err(|e| error!(target: "parsing certificate", "{}: {e:?}", path.display()))
+        .ok()?;
+    Some(cert.validity().not_after.timestamp())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn render_query_metrics(out: &mut String, metrics: &QueryMetrics) {
+    write_metric(
+        out,
+        "epu_upstream_queries_total",
+        "counter",
+        "Number of degree queries sent to ECNU.",
+        &[(String::new(), metrics.queries as f64)],
+    );
+    let errors: Vec<_> = metrics
+        .errors
+        .iter()
+        .map(|(kind, count)| (format!("kind=\"{kind}\""), *count as f64))
+        .collect();
+    write_metric(
+        out,
+        "epu_upstream_errors_total",
+        "counter",
+        "Number of failed degree queries by error kind.",
+        &errors,
+    );
+    let mut latency: Vec<_> = LATENCY_BUCKETS
+        .iter()
+        .zip(&metrics.latency_buckets)
+        .map(|(bucket, count)| (format!("le=\"{bucket}\""), *count as f64))
+        .collect();
+    latency.push(("le=\"+Inf\"".to_string(), metrics.queries as f64));
+    writeln!(
+        out,
+        "# HELP epu_upstream_query_duration_seconds Latency of degree queries sent to ECNU.\n\
+         # TYPE epu_upstream_query_duration_seconds histogram"
+    )
+    .unwrap();
+    for (labels, value) in latency {
+        writeln!(
+            out,
+            "epu_upstream_query_duration_seconds_bucket{{{labels}}} {value}"
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "epu_upstream_query_duration_seconds_sum {}",
+        metrics.latency_sum
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "epu_upstream_query_duration_seconds_count {}",
+        metrics.queries
+    )
+    .unwrap();
+}
+
+/// 生成 Prometheus 文本格式的指标.
+#[allow(clippy::cast_precision_loss)]
+pub(crate) async fn render(state: &AppState) -> String {
+    let status = state.query_status.read().await.clone();
+    let mut out = String::new();
+    if let Some(degree) = status.degree {
+        write_metric(
+            &mut out,
+            "epu_degree",
+            "gauge",
+            "Remaining degree (kWh) from the last successful query.",
+            // 读数精确到 0.01 度, 避免 f32 转换带来的多余位数.
+            &[(String::new(), (f64::from(degree) * 100.0).round() / 100.0)],
+        );
+    }
+    if let Some(last_success) = status.last_success {
+        write_metric(
+            &mut out,
+            "epu_last_success_timestamp_seconds",
+            "gauge",
+            "Unix time of the last successful degree query.",
+            &[(String::new(), last_success.timestamp() as f64)],
+        );
+    }
+    write_metric(
+        &mut out,
+        "epu_logged_in",
+        "gauge",
+        "Whether the server side ECNU login is valid (1) or not (0).",
+        &[(String::new(), f64::from(u8::from(!status.logged_out)))],
+    );
+    write_metric(
+        &mut out,
+        "epu_consecutive_failures",
+        "gauge",
+        "Number of consecutive failed degree queries, excluding logged out ones.",
+        &[(String::new(), status.consecutive_failures.into())],
+    );
+    render_query_metrics(&mut out, &status.metrics);
+
+    match state.recorder.write().await.read_records().await {
+        Ok(records) => write_metric(
+            &mut out,
+            "epu_records",
+            "gauge",
+            "Number of records of the current room.",
+            &[(String::new(), records.len() as f64)],
+        ),
+        Err(e) => error!(target: "reading records", "{e:?}"),
+    }
+    let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
+    if let Ok(metas) = read_archive_metas(&archive_dir).await {
+        write_metric(
+            &mut out,
+            "epu_archives",
+            "gauge",
+            "Number of archives of the current room.",
+            &[(String::new(), metas.len() as f64)],
+        );
+        write_metric(
+            &mut out,
+            "epu_archived_records",
+            "gauge",
+            "Number of records in the archives of the current room.",
+            &[(
+                String::new(),
+                metas.iter().map(|m| m.records_num).sum::<usize>() as f64,
+            )],
+        );
+    }
+
+    if let Some(tls_config) = &state.server_config.tls_config {
+        let mut expiry = Vec::new();
+        for (name, path) in [
+            ("server", &tls_config.server_cert),
+            ("root_ca", &tls_config.root_ca),
+        ] {
+            if let Some(t) = certificate_expiry(path).await {
+                expiry.push((format!("cert=\"{name}\""), t as f64));
+            }
+        }
+        write_metric(
+            &mut out,
+            "epu_certificate_expiry_timestamp_seconds",
+            "gauge",
+            "Unix time when the tls certificate expires.",
+            &expiry,
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rcgen::{CertificateParams, KeyPair};
+    use time::OffsetDateTime;
+
+    use crate::error::Error;
+    use crate::server::metrics::{QueryMetrics, certificate_expiry, render_query_metrics};
+
+    #[test]
+    fn query_metrics() {
+        let mut metrics = QueryMetrics::default();
+        metrics.observe(Duration::from_millis(80), None);
+        metrics.observe(
+            Duration::from_secs(3),
+            Some(&Error::Ecnu("not logged in".to_string())),
+        );
+        metrics.observe(Duration::from_secs(20), Some(&Error::NoDegree));
+        let mut out = String::new();
+        render_query_metrics(&mut out, &metrics);
+        assert!(out.contains("epu_upstream_queries_total 3\n"));
+        assert!(out.contains("epu_upstream_errors_total{kind=\"no_degree\"} 1\n"));
+        assert!(out.contains("epu_upstream_errors_total{kind=\"not_logged_in\"} 1\n"));
+        assert!(out.contains("epu_upstream_query_duration_seconds_bucket{le=\"0.05\"} 0\n"));
+        assert!(out.contains("epu_upstream_query_duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(out.contains("epu_upstream_query_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("epu_upstream_query_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("epu_upstream_query_duration_seconds_count 3\n"));
+    }
+
+    #[tokio::test]
+    async fn certificate_expiry_from_pem() {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.not_after = OffsetDateTime::from_unix_timestamp(2_000_000_000).unwrap();
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.crt");
+        tokio::fs::write(&path, cert.pem()).await.unwrap();
+        assert_eq!(certificate_expiry(&path).await, Some(2_000_000_000));
+    }
+}
@@ -0,0 +1,287 @@
+//! 数据目录布局的版本管理和迁移.
+//!
+//! 数据目录下的 `layout.toml` 记录当前布局的版本, 没有此文件的旧安装视为版本 0.
+//!
+//! 版本 1 的布局:
+//!
+//! ```text
+//! <data_dir>/
+//! ├── layout.toml
+//! ├── logs/
+//! ├── backups/                      迁移前的备份
+//! └── rooms/
+//!     ├── unknown/                  没有房间配置时使用
+//!     └── <room_no>/
+//!         ├── records.csv
+//!         ├── corrections.jsonl
+//!         ├── annotations.json
+//!         ├── roommates.json
+//!         ├── anomalies.json
+//!         ├── away.json
+//!         ├── budgets.json
+//!         ├── archives/<name>.csv | <name>.toml | <name>.annotations.json
+//!         └── deleted/
+//! ```
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::config::{
+    BACKUPS_DIRNAME, LAYOUT_FILENAME, LOG_DIRNAME, RECORDS_FILENAME, ROOM_UNKNOWN_DIRNAME,
+    ROOMS_DIRNAME,
+};
+use crate::server::import::merge_records;
+use crate::{Error, Records};
+
+/// 当前程序使用的数据目录布局版本.
+pub(crate) const LAYOUT_VERSION: u32 = 1;
+
+/// 每一个版本升级到下一个版本的说明, 下标为升级前的版本.
+const MIGRATIONS: &[&str] = &["move the `unknown` room dir into `rooms/`"];
+
+#[derive(Deserialize, Serialize, Debug)]
+struct LayoutMarker {
+    version: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// 执行 (或预演时将要执行) 的操作.
+    pub actions: Vec<String>,
+    /// 迁移前的备份目录, 预演或者无需迁移时为 None.
+    pub backup: Option<PathBuf>,
+}
+
+async fn read_version(data_dir: &Path) -> crate::Result<u32> {
+    match fs::read_to_string(data_dir.join(LAYOUT_FILENAME)).await {
+        Ok(content) => Ok(toml::from_str::<LayoutMarker>(&content)?.version),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            // 全新的数据目录直接使用最新的布局.
+            let fresh = !data_dir.join(ROOMS_DIRNAME).exists()
+                && !data_dir.join(ROOM_UNKNOWN_DIRNAME).exists();
+            Ok(if fresh { LAYOUT_VERSION } else { 0 })
+        }
+        Err(e) => Err(e)?,
+    }
+}
+
+async fn write_version(data_dir: &Path, version: u32) -> crate::Result<()> {
+    fs::write(
+        data_dir.join(LAYOUT_FILENAME),
+        toml::to_string_pretty(&LayoutMarker { version })?,
+    )
+    .await?;
+    Ok(())
+}
+
+/// 检查数据目录布局的版本, 并将其迁移到当前版本.
+///
+/// 迁移前会将数据目录 (除了日志和备份) 备份到 `backups/` 中, `dry_run` 为 true 时只返回将要执行的操作.
+///
+/// # Errors
+///
+/// - [`Error::LayoutTooNew`][]: 数据目录由更新版本的程序创建, 无法识别.
+pub(crate) async fn migrate(data_dir: &Path, dry_run: bool) -> crate::Result<MigrationReport> {
+    let from_version = read_version(data_dir).await?;
+    if from_version > LAYOUT_VERSION {
+        return Err(Error::LayoutTooNew(from_version, LAYOUT_VERSION));
+    }
+    let mut report = MigrationReport {
+        from_version,
+        to_version: LAYOUT_VERSION,
+        actions: Vec::new(),
+        backup: None,
+    };
+    if from_version == LAYOUT_VERSION {
+        if !dry_run && !data_dir.join(LAYOUT_FILENAME).exists() {
+            write_version(data_dir, LAYOUT_VERSION).await?;
+        }
+        return Ok(report);
+    }
+
+    if !dry_run {
+        let backup = data_dir.join(BACKUPS_DIRNAME).join(format!(
+            "layout-v{from_version}-{}",
+            Local::now().format("%Y%m%d-%H%M%S")
+        ));
+        info!("backing up data dir to {backup:?}");
+        backup_data_dir(data_dir, &backup).await?;
+        report.backup = Some(backup);
+    }
+    for version in from_version..LAYOUT_VERSION {
+        report.actions.push(format!(
+            "v{version} -> v{}: {}",
+            version + 1,
+            MIGRATIONS[version as usize]
+        ));
+        let actions = match version {
+            0 => v0_to_v1(data_dir, dry_run).await?,
+            _ => unreachable!("missing migration from layout v{version}"),
+        };
+        report.actions.extend(actions);
+        if !dry_run {
+            write_version(data_dir, version + 1).await?;
+        }
+    }
+    Ok(report)
+}
+
+/// 复制数据目录, 跳过日志和备份目录.
+async fn backup_data_dir(data_dir: &Path, backup: &Path) -> crate::Result<()> {
+    let data_dir = data_dir.to_path_buf();
+    let backup = backup.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        copy_dir(&data_dir, &backup, &[LOG_DIRNAME, BACKUPS_DIRNAME])
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path, skip: &[&str]) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if skip.iter().any(|s| entry.file_name() == *s) {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target, &[])?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// 旧版本没有房间配置时记录保存在 `<data_dir>/unknown` 中, 将其移动到 `rooms/unknown`.
+///
+/// 目标已经存在时合并 records.csv, 其余不冲突的文件直接移动, 冲突的文件保留在原处.
+async fn v0_to_v1(data_dir: &Path, dry_run: bool) -> crate::Result<Vec<String>> {
+    let old_dir = data_dir.join(ROOM_UNKNOWN_DIRNAME);
+    let new_dir = data_dir.join(ROOMS_DIRNAME).join(ROOM_UNKNOWN_DIRNAME);
+    let mut actions = Vec::new();
+    if !old_dir.is_dir() {
+        return Ok(actions);
+    }
+    if !new_dir.exists() {
+        actions.push(format!(
+            "move {} -> {}",
+            old_dir.display(),
+            new_dir.display()
+        ));
+        if !dry_run {
+            fs::create_dir_all(data_dir.join(ROOMS_DIRNAME)).await?;
+            fs::rename(&old_dir, &new_dir).await?;
+        }
+        return Ok(actions);
+    }
+
+    let mut rd = fs::read_dir(&old_dir).await?;
+    while let Some(entry) = rd.next_entry().await? {
+        let from = entry.path();
+        let to = new_dir.join(entry.file_name());
+        if entry.file_name() == RECORDS_FILENAME && to.exists() {
+            actions.push(format!("merge {} into {}", from.display(), to.display()));
+            if !dry_run {
+                let (merged, _) = merge_records(
+                    Records::from_csv_file(&to).await?,
+                    Records::from_csv_file(&from).await?,
+                );
+                fs::write(&to, merged.to_csv().await?).await?;
+                fs::remove_file(&from).await?;
+            }
+        } else if to.exists() {
+            warn!(
+                "{} already exists, keeping {}",
+                to.display(),
+                from.display()
+            );
+            actions.push(format!(
+                "keep {} (conflicts with {})",
+                from.display(),
+                to.display()
+            ));
+        } else {
+            actions.push(format!("move {} -> {}", from.display(), to.display()));
+            if !dry_run {
+                fs::rename(&from, &to).await?;
+            }
+        }
+    }
+    if !dry_run {
+        // 目录非空 (存在冲突的文件) 时保留.
+        fs::remove_dir(&old_dir).await.ok();
+    }
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Error;
+    use crate::config::{
+        BACKUPS_DIRNAME, LAYOUT_FILENAME, RECORDS_FILENAME, ROOM_UNKNOWN_DIRNAME, ROOMS_DIRNAME,
+    };
+    use crate::server::migrate::{LAYOUT_VERSION, migrate};
+
+    #[tokio::test]
+    async fn migrate_v0() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let data_dir = data_dir.path();
+        let old_dir = data_dir.join(ROOM_UNKNOWN_DIRNAME);
+        std::fs::create_dir_all(&old_dir).unwrap();
+        std::fs::write(
+            old_dir.join(RECORDS_FILENAME),
+            "2026-01-24T14:35:32+08:00,33.43\n",
+        )
+        .unwrap();
+
+        let report = migrate(data_dir, true).await.unwrap();
+        assert_eq!(report.from_version, 0);
+        assert!(report.backup.is_none());
+        assert!(old_dir.exists());
+
+        let report = migrate(data_dir, false).await.unwrap();
+        assert_eq!(report.to_version, LAYOUT_VERSION);
+        assert!(!old_dir.exists());
+        assert!(
+            data_dir
+                .join(ROOMS_DIRNAME)
+                .join(ROOM_UNKNOWN_DIRNAME)
+                .join(RECORDS_FILENAME)
+                .exists()
+        );
+        let backup = report.backup.unwrap();
+        assert!(backup.starts_with(data_dir.join(BACKUPS_DIRNAME)));
+        assert!(
+            backup
+                .join(ROOM_UNKNOWN_DIRNAME)
+                .join(RECORDS_FILENAME)
+                .exists()
+        );
+
+        let report = migrate(data_dir, false).await.unwrap();
+        assert_eq!(report.from_version, LAYOUT_VERSION);
+        assert!(report.actions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuse_newer_layout() {
+        let data_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            data_dir.path().join(LAYOUT_FILENAME),
+            format!("version = {}\n", LAYOUT_VERSION + 1),
+        )
+        .unwrap();
+        assert!(matches!(
+            migrate(data_dir.path(), false).await,
+            Err(Error::LayoutTooNew(..))
+        ));
+    }
+}
@@ -1,11 +1,11 @@
 //! 服务端逻辑.
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::io::{Cursor, SeekFrom};
 use std::ops::Sub;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use axum::extract::DefaultBodyLimit;
@@ -15,7 +15,7 @@ use axum::{
     routing::{get, post},
 };
 use axum_server::tls_rustls::RustlsConfig;
-use chrono::{DateTime, FixedOffset, Local, TimeZone, Timelike};
+use chrono::{DateTime, FixedOffset, Local, TimeDelta, TimeZone, Timelike};
 use reqwest::{Client, Method};
 use rustls::RootCertStore;
 use rustls::pki_types::pem::PemObject;
@@ -27,18 +27,53 @@ use tokio::fs::{self, File};
 use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::sync::{Mutex, RwLock};
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
+use crate::analytics::{self, Accounting, Forecast, Recharge};
 use crate::config::{
-    RECORDS_FILENAME, ROOM_CONFIG_FILENAME, ROOM_UNKNOWN_DIRNAME, RoomConfig,
-    SERVER_CONFIG_FILENAME, ServerConfig, config_dir, data_dir, log_dir,
+    ARCHIVE_DIRNAME, RECORDS_FILENAME, ROOM_CONFIG_FILENAME, RoomConfig, SERVER_CONFIG_FILENAME,
+    ServerConfig, config_dir, data_dir, is_sanitized_filename, log_dir,
 };
 use crate::error::{CSError, Error};
 use crate::rooms::{Buildings, Districts, Floors, RoomInfo, Rooms};
+use crate::server::event::EventHub;
+use crate::server::metrics::QueryMetrics;
 use crate::{Cookies, Records};
 
+mod alert;
+mod annotation;
+mod anomaly;
+mod appliance;
+mod away;
+mod budget;
+mod correction;
+mod digest;
+mod event;
+mod grafana;
+mod import;
 mod log;
+mod metrics;
+mod migrate;
+mod mqtt;
+mod retention;
+mod roommate;
 pub(crate) mod route;
+mod span_expr;
+mod store;
+
+pub use alert::{Alert, AlertDelivery};
+pub use annotation::{AnnotatedRecords, Annotation};
+pub use anomaly::AnomalyEvent;
+pub use appliance::APPLIANCE_TAG_PREFIX;
+pub use away::{AWAY_TAG, AwayPeriod, AwayReport};
+pub use budget::{Budget, BudgetPeriod, BudgetProgress, BudgetStatus, BudgetUnit};
+pub use correction::{CorrectionEntry, CorrectionOp};
+pub use digest::{Digest, DigestFormat};
+pub use event::{EventEnvelope, ServerEvent};
+pub use import::{ImportIssue, ImportMode, ImportReport};
+pub use migrate::MigrationReport;
+pub use retention::{RetentionReport, RetentionTargetReport};
+pub use roommate::{Roommate, Settlement, SettlementRow};
 
 #[derive(serde::Deserialize)]
 struct QueryResponse {
@@ -313,17 +348,17 @@ impl Recorder {
         Ok(())
     }
 
-    /// 尝试记录一次电量变化, 只有产生了电量度数的变化才会被记录, 如果被记录了, 那么返回 Ok(true).
-    async fn record(&mut self, degree: f32) -> crate::Result<bool> {
+    /// 尝试记录一次电量变化, 只有产生了电量度数的变化才会被记录, 如果被记录了, 那么返回记录的时间.
+    async fn record(&mut self, degree: f32) -> crate::Result<Option<DateTime<FixedOffset>>> {
         let now_time = Local::now().fixed_offset().with_nanosecond(0).unwrap();
         if let Some(last_degree) = self.last_degree
             && last_degree.sub(degree).abs() < 0.01
         {
-            return Ok(false);
+            return Ok(None);
         }
 
         self.record_instant(now_time, degree).await?;
-        Ok(true)
+        Ok(Some(now_time))
     }
 
     /// 从可读可写文件中加载.
@@ -400,6 +435,16 @@ impl Recorder {
         })
     }
 
+    /// 使用给定的记录覆盖输出中的全部内容.
+    async fn replace(&mut self, records: Records) -> crate::Result<()> {
+        let mut out = self.out.write().await;
+        out.set_len(0).await?;
+        out.seek(SeekFrom::Start(0)).await?;
+        drop(out);
+        self.last_degree = None;
+        self.record_multiple(records).await
+    }
+
     /// 从文件中读取已经输出的 records.
     async fn read_records(&self) -> crate::Result<Records> {
         let mut out = self.out.write().await;
@@ -421,26 +466,120 @@ struct ArchiveHandle<'a> {
 impl ArchiveHandle<'_> {
     /// 确认 archive 操作, 如果不执行此方法, [`Recorder::archive`] 是无任何效果的.
     async fn commit(self) -> crate::Result<()> {
-        let mut out = self.recorder.out.write().await;
-        out.set_len(0).await?;
-        out.seek(SeekFrom::Start(0)).await?;
-        drop(out);
-        self.recorder.last_degree = None;
-        self.recorder.record_multiple(self.retained).await?;
-        Ok(())
+        self.recorder.replace(self.retained).await
     }
 }
 
+/// 后台查询电量连续失败的一段时间, 只保存在内存中.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Outage {
+    /// 第一次查询失败的时间.
+    pub start_time: DateTime<FixedOffset>,
+    /// 恢复后第一次查询成功的时间, 为 None 时仍未恢复.
+    pub end_time: Option<DateTime<FixedOffset>>,
+    /// 是否因为 ECNU 登录失效而失败.
+    pub logged_out: bool,
+    /// 最近一次查询失败的原因.
+    pub error: String,
+}
+
+/// 后台查询电量的状态.
+#[derive(Debug, Default, Clone)]
+struct QueryStatus {
+    /// 最近一次查询成功的时间.
+    last_success: Option<DateTime<Local>>,
+    /// 连续查询失败的次数, 不包括未登录.
+    consecutive_failures: u32,
+    /// 最近一次查询是否因为未登录而失败.
+    logged_out: bool,
+    /// 最近一次查询失败的原因.
+    last_error: Option<String>,
+    /// 最近 [`OUTAGE_RETENTION`] 内的查询中断, 按照开始时间排列.
+    outages: VecDeque<Outage>,
+    /// 最近一次查询到的剩余度数.
+    degree: Option<f32>,
+    /// 启动以来的查询次数, 耗时和错误.
+    metrics: QueryMetrics,
+}
+
+/// 查询中断在内存中保留的时长.
+const OUTAGE_RETENTION: TimeDelta = TimeDelta::days(14);
+
+impl QueryStatus {
+    fn succeed(&mut self, now: DateTime<FixedOffset>, degree: f32) {
+        self.last_success = Some(now.with_timezone(&Local));
+        self.degree = Some(degree);
+        self.consecutive_failures = 0;
+        self.logged_out = false;
+        self.last_error = None;
+        if let Some(outage) = self.outages.back_mut()
+            && outage.end_time.is_none()
+        {
+            outage.end_time = Some(now);
+        }
+        while self
+            .outages
+            .front()
+            .and_then(|o| o.end_time)
+            .is_some_and(|end| now - end > OUTAGE_RETENTION)
+        {
+            self.outages.pop_front();
+        }
+    }
+
+    fn fail(&mut self, now: DateTime<FixedOffset>, e: &Error) {
+        self.logged_out = matches!(e, Error::Ecnu(_));
+        if !self.logged_out {
+            self.consecutive_failures += 1;
+        }
+        self.last_error = Some(e.to_string());
+        match self.outages.back_mut() {
+            Some(outage) if outage.end_time.is_none() => {
+                outage.logged_out |= self.logged_out;
+                outage.error = e.to_string();
+            }
+            _ => self.outages.push_back(Outage {
+                start_time: now,
+                end_time: None,
+                logged_out: self.logged_out,
+                error: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// 服务端的共享状态.
+///
+/// 需要同时持有多个锁时, 先获取 `recorder` 的锁, 再获取 `annotations_lock` 等保护房间目录下文件的锁.
+/// 持有这些文件锁时不能再获取 `recorder` 的锁, 否则会与 [`route::create_archive`] 等先持有 `recorder`
+/// 再获取文件锁的操作互相等待.
 #[derive(Debug)]
 struct AppState {
     querier: RwLock<Querier>,
     recorder: RwLock<Recorder>,
     config_dir: PathBuf,
+    server_config: ServerConfig,
     // 当前宿舍房间的数据保存路径.
     room_dir: RwLock<PathBuf>,
+    /// 保证标注文件 (包括离开时间段) 的读写是串行的.
+    annotations_lock: Mutex<()>,
+    /// 保证室友文件的读写是串行的.
+    roommates_lock: Mutex<()>,
+    /// 保证异常事件文件的读写是串行的.
+    anomalies_lock: Mutex<()>,
+    /// 保证预算文件的读写是串行的.
+    budgets_lock: Mutex<()>,
+    /// 读数, 充值, 登录状态, 房间和 archive 的变化, 用电异常, 离开模式和预算的提醒, 供 `/subscribe` 和 MQTT 使用.
+    events: Arc<EventHub>,
+    /// 后台查询电量的状态, 由 [`record_loop`] 更新.
+    query_status: RwLock<QueryStatus>,
+    /// 最近一次的耗尽预测, 每次记录新的读数时更新.
+    forecast: RwLock<Option<Forecast>>,
+    /// 根据服务端配置计算费用和碳排放的参数.
+    accounting: Accounting,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct TimeSpan {
     /// 时间范围: 开头 (包含)
     pub start_time: Option<DateTime<FixedOffset>>,
@@ -476,6 +615,36 @@ impl TimeSpan {
     pub fn contains<Tz: TimeZone>(&self, o: &DateTime<Tz>) -> bool {
         self.start_time.is_none_or(|st| st.le(o)) && self.end_time.is_none_or(|et| et.ge(o))
     }
+
+    /// 两个时间范围是否有交集 (边界包含).
+    #[must_use]
+    pub fn overlaps(&self, other: &TimeSpan) -> bool {
+        let starts_before = match (self.start_time, other.end_time) {
+            (Some(st), Some(et)) => st <= et,
+            _ => true,
+        };
+        let ends_after = match (self.end_time, other.start_time) {
+            (Some(et), Some(st)) => et >= st,
+            _ => true,
+        };
+        starts_before && ends_after
+    }
+
+    /// `other` 是否完全处于此时间范围之内, `other` 没有边界的一侧视为无限延伸.
+    #[must_use]
+    pub fn contains_span(&self, other: &TimeSpan) -> bool {
+        let start_inside = match (self.start_time, other.start_time) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(_), Some(st)) => self.contains(&st),
+        };
+        let end_inside = match (self.end_time, other.end_time) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(_), Some(et)) => self.contains(&et),
+        };
+        start_inside && end_inside
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -485,6 +654,32 @@ pub struct ArchiveMeta {
     pub end_time: DateTime<FixedOffset>,
     pub archive_name: String,
     pub records_num: usize,
+    /// 用电量 (度), 旧版本创建的 archive 没有此项.
+    #[serde(default)]
+    pub consumption: Option<f32>,
+    /// 按照写入时配置的电价估算的费用 (元).
+    #[serde(default)]
+    pub cost: Option<f32>,
+    /// 按照写入时配置的排放因子估算的碳排放 (kg CO2).
+    #[serde(default)]
+    pub emission: Option<f32>,
+}
+
+impl ArchiveMeta {
+    /// 根据记录生成元信息, 记录为空时返回 None.
+    fn new(archive_name: String, records: &Records, accounting: &Accounting) -> Option<Self> {
+        let (start_time, end_time) = records.time_span()?;
+        let report = analytics::cost_report(records, &TimeSpan::ALL, accounting);
+        Some(Self {
+            start_time,
+            end_time,
+            archive_name,
+            records_num: records.len(),
+            consumption: Some(report.consumption),
+            cost: report.cost,
+            emission: report.emission,
+        })
+    }
 }
 
 impl PartialEq for ArchiveMeta {
@@ -522,6 +717,223 @@ impl PartialOrd for ArchiveMeta {
     }
 }
 
+/// 默认的 archive 名称: 记录的起止日期以及创建时间.
+fn default_archive_name(
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+) -> String {
+    format!(
+        "{}-{}-by-{}",
+        start_time.format("%Y%d%m"),
+        end_time.format("%Y%d%m"),
+        Local::now().format("%Y%d%m_%H%M%S")
+    )
+}
+
+/// 将记录写入 archive 目录, 生成 `<archive_name>.csv` 和 `<archive_name>.toml` 两个文件.
+///
+/// `records` 需要已经排好序, 写入失败时已经写入的文件会被移除.
+async fn save_archive(
+    archive_dir: &Path,
+    archive_name: Option<String>,
+    records: &Records,
+    accounting: &Accounting,
+) -> Result<ArchiveMeta, CSError> {
+    let Some((start_time, end_time)) = records.time_span() else {
+        // 如果 records 无法计算出时间跨度, 那么说明其为空.
+        return Err(CSError::EmptyArchive);
+    };
+    let archive_name = archive_name.unwrap_or_else(|| default_archive_name(start_time, end_time));
+    if !is_sanitized_filename(&archive_name) {
+        return Err(CSError::InvalidArchiveName);
+    }
+
+    fs::create_dir_all(archive_dir)
+        .await
+        .map_err(|_| CSError::ArchiveDir)?;
+    let archive_file = archive_dir.join(format!("{archive_name}.csv"));
+    let archive_meta_file = archive_dir.join(format!("{archive_name}.toml"));
+    if archive_file.exists() || archive_meta_file.exists() {
+        return Err(CSError::DuplicatedArchive);
+    }
+
+    let Some(archive_meta) = ArchiveMeta::new(archive_name, records, accounting) else {
+        return Err(CSError::EmptyArchive);
+    };
+    let archived_content = records.to_csv().await.map_err(|e| {
+        error!(target: "serializing records", "{e:?}");
+        CSError::SerializeRecords
+    })?;
+    let archived_meta_content = toml::to_string_pretty(&archive_meta).map_err(|e| {
+        error!(target: "serializing archive meta", "{e:?}");
+        CSError::SerializeMeta
+    })?;
+
+    if let Err(e) = fs::write(&archive_file, archived_content).await {
+        error!(target: "writing archive file", "{e:?}");
+        return Err(CSError::WriteArchive);
+    }
+    if let Err(e) = fs::write(&archive_meta_file, archived_meta_content).await {
+        error!(target: "saving archive meta", "{e:?}");
+        fs::remove_file(&archive_file).await.ok();
+        return Err(CSError::SaveArchiveMeta);
+    }
+    Ok(archive_meta)
+}
+
+/// 读取 archive 目录中所有 archive 的元信息, 按照起始时间排序.
+async fn read_archive_metas(archive_dir: &Path) -> Result<Vec<ArchiveMeta>, CSError> {
+    fs::create_dir_all(archive_dir).await.ok();
+    let Ok(mut rd) = fs::read_dir(archive_dir).await else {
+        return Err(CSError::ArchiveDir);
+    };
+    let mut archive_metas = Vec::new();
+    while let Some(entry) = rd.next_entry().await.map_err(|e| {
+        error!("listing archives: {e:?}");
+        CSError::ListArchive
+    })? {
+        let Some(meta_filename) = entry
+            .file_name()
+            .to_str()
+            .filter(|s| {
+                Path::new(s)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
+            })
+            .map(|s: &str| s.to_string())
+        else {
+            continue;
+        };
+        let meta_file = archive_dir.join(&meta_filename);
+        let Ok(meta_content) = fs::read(meta_file).await else {
+            continue;
+        };
+        let Ok(meta) = toml::from_slice::<ArchiveMeta>(&meta_content) else {
+            continue;
+        };
+        archive_metas.push(meta);
+    }
+
+    archive_metas.sort();
+    Ok(archive_metas)
+}
+
+/// 读取 archive 中的记录.
+async fn load_archive(archive_dir: &Path, archive_name: &str) -> Result<Records, CSError> {
+    if !is_sanitized_filename(archive_name) {
+        return Err(CSError::InvalidArchiveName);
+    }
+    let archive_file = archive_dir.join(format!("{archive_name}.csv"));
+    if !archive_file.exists() {
+        return Err(CSError::ArchiveNotFound);
+    }
+    Records::from_csv_file(&archive_file).await.map_err(|e| {
+        error!(target: "reading archive", "{e:?}");
+        CSError::ReadRecords
+    })
+}
+
+/// 收集当前记录, `include_archives` 为 true 时还包括与时间范围重叠的 archive 中的记录.
+///
+/// 结果按照时间排序并去除重复的时间点, 但不会按照时间范围过滤, 以便统计跨越范围边界的用电量.
+async fn collect_records(
+    state: &AppState,
+    time_span: &TimeSpan,
+    include_archives: bool,
+) -> Result<Records, CSError> {
+    let mut records = state
+        .recorder
+        .write()
+        .await
+        .read_records()
+        .await
+        .map_err(|e| {
+            error!(target: "reading records", "{e:?}");
+            CSError::ReadRecords
+        })?;
+    if include_archives {
+        let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
+        for meta in read_archive_metas(&archive_dir).await? {
+            let archive_span = TimeSpan::new(Some(meta.start_time), Some(meta.end_time));
+            if archive_span.overlaps(time_span) {
+                let archived = load_archive(&archive_dir, &meta.archive_name).await?;
+                records = import::merge_records(records, archived).0;
+            }
+        }
+    }
+    records.sort();
+    Ok(records)
+}
+
+/// 覆盖已经存在的 archive 中的记录, 并同步更新其元信息.
+///
+/// `records` 需要已经排好序.
+async fn rewrite_archive(
+    archive_dir: &Path,
+    archive_name: &str,
+    records: &Records,
+    accounting: &Accounting,
+) -> Result<ArchiveMeta, CSError> {
+    if !is_sanitized_filename(archive_name) {
+        return Err(CSError::InvalidArchiveName);
+    }
+    let archive_file = archive_dir.join(format!("{archive_name}.csv"));
+    let archive_meta_file = archive_dir.join(format!("{archive_name}.toml"));
+    if !archive_meta_file.exists() {
+        return Err(CSError::ArchiveNotFound);
+    }
+    let Some(archive_meta) = ArchiveMeta::new(archive_name.to_string(), records, accounting) else {
+        return Err(CSError::EmptyArchive);
+    };
+    let archived_content = records.to_csv().await.map_err(|e| {
+        error!(target: "serializing records", "{e:?}");
+        CSError::SerializeRecords
+    })?;
+    let archived_meta_content = toml::to_string_pretty(&archive_meta).map_err(|e| {
+        error!(target: "serializing archive meta", "{e:?}");
+        CSError::SerializeMeta
+    })?;
+    if let Err(e) = fs::write(&archive_file, archived_content).await {
+        error!(target: "writing archive file", "{e:?}");
+        return Err(CSError::WriteArchive);
+    }
+    if let Err(e) = fs::write(&archive_meta_file, archived_meta_content).await {
+        error!(target: "saving archive meta", "{e:?}");
+        return Err(CSError::SaveArchiveMeta);
+    }
+    Ok(archive_meta)
+}
+
+/// 移除 [`save_archive`] 写入的 archive 文件, 用于后续操作失败时的回滚.
+async fn remove_archive_files(archive_dir: &Path, archive_name: &str) {
+    fs::remove_file(archive_dir.join(format!("{archive_name}.csv")))
+        .await
+        .ok();
+    fs::remove_file(archive_dir.join(format!("{archive_name}.toml")))
+        .await
+        .ok();
+}
+
+/// 根据当前记录重新计算耗尽预测.
+async fn refresh_forecast(state: &AppState) -> Option<Forecast> {
+    let records = match state.recorder.write().await.read_records().await {
+        Ok(mut records) => {
+            records.sort();
+            records
+        }
+        Err(e) => {
+            error!(target: "reading records", "{e:?}");
+            return None;
+        }
+    };
+    let forecast = analytics::forecast(&records);
+    if let Some(f) = &forecast {
+        debug!("forecast: {:?}", f.depletion_time);
+    }
+    state.forecast.write().await.clone_from(&forecast);
+    forecast
+}
+
 async fn record_loop(state: Arc<AppState>) -> ! {
     enum LoopState {
         Normal,
@@ -532,27 +944,77 @@ async fn record_loop(state: Arc<AppState>) -> ! {
     let mut loop_state = LoopState::Normal;
     loop {
         interval.tick().await;
-        match state.querier.read().await.query_electricity_degree().await {
+        let started = Instant::now();
+        let result = state.querier.read().await.query_electricity_degree().await;
+        state
+            .query_status
+            .write()
+            .await
+            .metrics
+            .observe(started.elapsed(), result.as_ref().err());
+        match result {
             Ok(degree) => {
                 info!("degree: {degree:.2}");
-                if let Err(e) = state.recorder.write().await.record(degree).await {
-                    error!("recording: {e:?}");
+                let restored = {
+                    let mut status = state.query_status.write().await;
+                    let restored = status.logged_out || status.last_success.is_none();
+                    status.succeed(Local::now().fixed_offset(), degree);
+                    restored
+                };
+                if restored {
+                    state.events.send(ServerEvent::LoginRestored);
+                }
+                // 先释放 recorder 的锁, refresh_forecast 需要重新获取.
+                let (last_degree, recorded) = {
+                    let mut recorder = state.recorder.write().await;
+                    (recorder.last_degree, recorder.record(degree).await)
+                };
+                match recorded {
+                    Ok(Some(time)) => {
+                        refresh_forecast(&state).await;
+                        if let Some(before) = last_degree
+                            && analytics::is_recharge(before, degree)
+                        {
+                            let recharge = Recharge {
+                                time,
+                                before,
+                                after: degree,
+                                amount: degree - before,
+                            };
+                            state.events.send(ServerEvent::Recharge(recharge));
+                        }
+                        state.events.send(ServerEvent::Reading { time, degree });
+                    }
+                    Ok(None) => (),
+                    Err(e) => error!("recording: {e:?}"),
                 }
                 loop_state = LoopState::Normal;
             }
-            Err(e) => match loop_state {
-                LoopState::Normal => {
-                    error!("querying: {e:?}");
-                    if matches!(e, Error::Ecnu(_)) {
-                        loop_state = LoopState::NotLogined;
-                    }
+            Err(e) => {
+                let lost = {
+                    let mut status = state.query_status.write().await;
+                    let logged_out = status.logged_out;
+                    status.fail(Local::now().fixed_offset(), &e);
+                    !logged_out && status.logged_out
+                };
+                if lost {
+                    let error = e.to_string();
+                    state.events.send(ServerEvent::LoginLost { error });
                 }
-                LoopState::NotLogined => {
-                    if !matches!(e, Error::Ecnu(_)) {
+                match loop_state {
+                    LoopState::Normal => {
                         error!("querying: {e:?}");
+                        if matches!(e, Error::Ecnu(_)) {
+                            loop_state = LoopState::NotLogined;
+                        }
+                    }
+                    LoopState::NotLogined => {
+                        if !matches!(e, Error::Ecnu(_)) {
+                            error!("querying: {e:?}");
+                        }
                     }
                 }
-            },
+            }
         }
     }
 }
@@ -574,6 +1036,13 @@ pub async fn run_app() -> anyhow::Result<()> {
     info!("config dir: {config_dir:?}");
     info!("log dir: {log_dir:?}");
 
+    let migration = migrate::migrate(&data_dir, false)
+        .await
+        .with_context(|| "failed to migrate data dir")?;
+    for action in &migration.actions {
+        info!("migrate: {action}");
+    }
+
     let room_config = RoomConfig::from_toml_file(config_dir.join(ROOM_CONFIG_FILENAME)).await;
     info!("room config: {room_config:#?}");
 
@@ -590,7 +1059,7 @@ pub async fn run_app() -> anyhow::Result<()> {
         .as_ref()
         // 可以不存在房间配置.
         // 但是不能是无效的房间配置
-        .map_or_else(|_| Ok(data_dir.join(ROOM_UNKNOWN_DIRNAME)), RoomConfig::dir)
+        .map_or_else(|_| RoomConfig::empty().dir(), RoomConfig::dir)
         .with_context(|| "failed to create room dir")?;
     fs::create_dir_all(&room_dir)
         .await
@@ -602,8 +1071,18 @@ pub async fn run_app() -> anyhow::Result<()> {
         querier: RwLock::new(querier),
         recorder: RwLock::new(recorder),
         config_dir,
+        server_config: server_config.clone(),
         room_dir: RwLock::new(room_dir),
+        annotations_lock: Mutex::new(()),
+        roommates_lock: Mutex::new(()),
+        anomalies_lock: Mutex::new(()),
+        budgets_lock: Mutex::new(()),
+        events: Arc::new(EventHub::new(Local::now().timestamp())),
+        query_status: RwLock::new(QueryStatus::default()),
+        forecast: RwLock::new(None),
+        accounting: server_config.accounting(),
     });
+    refresh_forecast(&app_state).await;
     let router = Router::new()
         .route("/post-room", post(post_room))
         .route("/post-cookies", post(post_cookies))
@@ -617,8 +1096,80 @@ pub async fn run_app() -> anyhow::Result<()> {
         .route("/clear-room", post(clear_room))
         .route("/get-room", get(get_room))
         .route("/get-room-info", get(get_room_info))
+        .route("/import-records", post(import_records))
+        .route("/insert-record", post(insert_record))
+        .route("/delete-records", post(delete_records))
+        .route("/correct-record", post(correct_record))
+        .route("/undo-correction", post(undo_correction))
+        .route("/list-corrections", get(list_corrections))
+        .route("/add-annotation", post(add_annotation))
+        .route("/update-annotation", post(update_annotation))
+        .route("/delete-annotation", post(delete_annotation))
+        .route("/list-annotations", get(list_annotations))
+        .route("/retention-report", get(retention_report))
+        .route("/get-usage-stats", get(get_usage_stats))
+        .route("/get-forecast", get(get_forecast))
+        .route("/plan-recharge", get(plan_recharge))
+        .route("/get-costs", get(get_costs))
+        .route("/get-baseline", get(get_baseline))
+        .route("/get-appliances", get(get_appliances))
+        .route("/compare", get(compare))
+        .route("/resolve-span", get(resolve_span))
+        .route("/get-calendar", get(get_calendar))
+        .route("/get-academic-stats", get(get_academic_stats))
+        .route("/add-roommate", post(add_roommate))
+        .route("/update-roommate", post(update_roommate))
+        .route("/delete-roommate", post(delete_roommate))
+        .route("/list-roommates", get(list_roommates))
+        .route("/get-settlement", get(get_settlement))
+        .route("/get-anomalies", get(get_anomalies))
+        .route("/set-away", post(set_away))
+        .route("/cancel-away", post(cancel_away))
+        .route("/list-away", get(list_away))
+        .route("/set-budget", post(set_budget))
+        .route("/delete-budget", post(delete_budget))
+        .route("/get-budgets", get(get_budgets))
+        .route("/test-alert", post(test_alert))
+        .route("/get-digest", get(get_digest))
+        .route("/metrics", get(get_metrics))
+        .route("/subscribe", get(subscribe))
+        .route("/grafana", get(grafana_test))
+        .route("/grafana/search", post(grafana_search))
+        .route("/grafana/query", post(grafana_query))
+        .route("/grafana/annotations", post(grafana_annotations))
         .with_state(Arc::clone(&app_state))
         .layer(DefaultBodyLimit::max(10 * 1024 * 1024));
+    if let Some(retention_config) = server_config.retention.clone() {
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { retention::retention_loop(app_state, retention_config).await });
+    }
+    {
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { anomaly::anomaly_loop(app_state).await });
+    }
+    {
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { away::away_loop(app_state).await });
+    }
+    {
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { budget::budget_loop(app_state).await });
+    }
+    if server_config.alerts.is_some() {
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { alert::alert_loop(app_state).await });
+    }
+    if server_config.digest.is_some() {
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { digest::digest_loop(app_state).await });
+    }
+    if let Some(mqtt_config) = &server_config.mqtt {
+        let options = mqtt::options(mqtt_config)
+            .await
+            .with_context(|| "invalid mqtt config")?;
+        let app_state = Arc::clone(&app_state);
+        tokio::spawn(async move { mqtt::mqtt_loop(app_state, options).await });
+    }
     let handle = tokio::spawn(async move { record_loop(app_state).await });
 
     if let Some(server_tls_config) = server_config.tls_config {
@@ -666,6 +1217,70 @@ pub async fn run_app() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// 离线导入 csv 记录, 不需要服务端运行, 但服务端运行时不应使用此函数 (请使用 `/import-records`).
+///
+/// `room_no` 为 None 时导入到配置文件中当前房间的数据目录.
+pub async fn run_import(
+    file: impl AsRef<Path>,
+    room_no: Option<String>,
+    mode: ImportMode,
+    archive_name: Option<String>,
+) -> anyhow::Result<ImportReport> {
+    let file = file.as_ref();
+    let content = fs::read_to_string(file)
+        .await
+        .with_context(|| format!("failed to read {}", file.display()))?;
+    let room_config = match room_no {
+        Some(room_no) => Ok(RoomConfig {
+            room_no,
+            ..RoomConfig::empty()
+        }),
+        None => RoomConfig::from_toml_file(config_dir()?.join(ROOM_CONFIG_FILENAME)).await,
+    };
+    let accounting =
+        ServerConfig::from_toml_file(config_dir()?.join(SERVER_CONFIG_FILENAME), false)
+            .await
+            .unwrap_or_default()
+            .accounting();
+    migrate::migrate(&data_dir()?, false)
+        .await
+        .with_context(|| "failed to migrate data dir")?;
+    let room_dir = room_config
+        .as_ref()
+        .map_or_else(|_| RoomConfig::empty().dir(), RoomConfig::dir)
+        .with_context(|| "failed to create room dir")?;
+    fs::create_dir_all(&room_dir)
+        .await
+        .with_context(|| "failed to create room dir")?;
+
+    let report = match mode {
+        ImportMode::Merge => {
+            let mut recorder = Recorder::load_from_path(room_dir.join(RECORDS_FILENAME))
+                .await
+                .with_context(|| "failed to load records")?;
+            import::import_into_recorder(&mut recorder, &content).await?
+        }
+        ImportMode::Archive => {
+            import::import_as_archive(
+                &room_dir.join(ARCHIVE_DIRNAME),
+                archive_name,
+                &content,
+                &accounting,
+            )
+            .await?
+        }
+    };
+    Ok(report)
+}
+
+/// 将数据目录迁移到当前的布局版本, 服务启动时也会自动执行.
+///
+/// `dry_run` 为 true 时只返回将要执行的操作.
+pub async fn run_migrate(dry_run: bool) -> anyhow::Result<MigrationReport> {
+    let data_dir = data_dir().with_context(|| "failed to access data dir")?;
+    Ok(migrate::migrate(&data_dir, dry_run).await?)
+}
+
 async fn load_certificate_der(
     cert_paths: &[impl AsRef<Path>],
 ) -> anyhow::Result<Vec<CertificateDer<'static>>> {
@@ -694,6 +1309,21 @@ mod tests {
         server::{Recorder, TimeSpan},
     };
 
+    #[test]
+    fn time_span_overlaps() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |d| offset.with_ymd_and_hms(2026, 10, d, 0, 0, 0).unwrap();
+        let oct_1_7 = TimeSpan::new(Some(t(1)), Some(t(7)));
+        assert!(oct_1_7.overlaps(&TimeSpan::new(Some(t(7)), Some(t(9)))));
+        assert!(oct_1_7.overlaps(&TimeSpan::new_before(t(1))));
+        assert!(oct_1_7.overlaps(&TimeSpan::ALL));
+        assert!(!oct_1_7.overlaps(&TimeSpan::new_after(t(8))));
+
+        assert!(TimeSpan::new_before(t(7)).contains_span(&oct_1_7));
+        assert!(!TimeSpan::new_before(t(6)).contains_span(&oct_1_7));
+        assert!(!oct_1_7.contains_span(&TimeSpan::new_after(t(2))));
+    }
+
     #[tokio::test]
     async fn archive() {
         let records = Records::from_csv(Cursor::new(
@@ -0,0 +1,446 @@
+//! MQTT 推送.
+//!
+//! 在 `server.toml` 的 `[mqtt]` 中配置 broker 后, 服务端把剩余度数, 最近一次充值以及 ECNU 登录状态以 retained 消息发布到
+//! `<topic_prefix>` 下的主题, 并发布 Home Assistant 的自动发现配置, 使这些实体自动出现在 Home Assistant 中.
+//!
+//! | 主题 | 内容 |
+//! | --- | --- |
+//! | `<topic_prefix>/status` | `online` 或 `offline` (遗嘱消息) |
+//! | `<topic_prefix>/degree` | `{"degree": 12.34, "time": "2026-09-01T12:00:00+08:00"}` |
+//! | `<topic_prefix>/recharge` | 最近一次充值, 格式同 [`Recharge`] |
+//! | `<topic_prefix>/login` | `ON` 或 `OFF` |
+//!
+//! 连接断开后自动重连, 每次连接成功后重新发布自动发现配置和最新的状态.
+//!
+//! [`Recharge`]: crate::analytics::Recharge
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use rumqttc::{
+    AsyncClient, Event, LastWill, MqttOptions, Packet, QoS, TlsConfiguration, Transport,
+};
+use rustls::RootCertStore;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::pki_types::pem::PemObject;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::analytics;
+use crate::config::{MqttConfig, MqttTlsConfig};
+use crate::server::{AppState, EventEnvelope, ServerEvent, load_certificate_der};
+
+/// 连接失败后重试的间隔.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// 一条 retained 消息 (主题, 内容).
+type Message = (String, String);
+
+struct Topics {
+    status: String,
+    degree: String,
+    recharge: String,
+    login: String,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Self {
+        Self {
+            status: format!("{prefix}/status"),
+            degree: format!("{prefix}/degree"),
+            recharge: format!("{prefix}/recharge"),
+            login: format!("{prefix}/login"),
+        }
+    }
+}
+
+/// 事件对应的状态消息, 不对应任何主题的事件返回 `None`.
+fn state_message(topics: &Topics, event: &ServerEvent) -> Option<Message> {
+    let message = match event {
+        ServerEvent::Reading { time, degree } => (
+            topics.degree.clone(),
+            json!({ "degree": degree, "time": time }).to_string(),
+        ),
+        ServerEvent::Recharge(recharge) => (topics.recharge.clone(), json!(recharge).to_string()),
+        ServerEvent::LoginLost { .. } => (topics.login.clone(), "OFF".to_string()),
+        ServerEvent::LoginRestored => (topics.login.clone(), "ON".to_string()),
+        ServerEvent::RoomChanged(_)
+        | ServerEvent::ArchiveCreated(_)
+        | ServerEvent::Anomaly(_)
+        | ServerEvent::AwayLimitExceeded(_)
+        | ServerEvent::AwayEnded(_)
+        | ServerEvent::BudgetOverPace(_)
+        | ServerEvent::BudgetExceeded(_)
+        | ServerEvent::Resync => return None,
+    };
+    Some(message)
+}
+
+/// Home Assistant 的自动发现配置.
+fn discovery_messages(config: &MqttConfig, topics: &Topics) -> Vec<Message> {
+    let node_id: String = config
+        .client_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let device = json!({
+        "identifiers": [node_id],
+        "name": "ECNU 宿舍电量",
+        "model": env!("CARGO_PKG_NAME"),
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    [
+        (
+            "sensor",
+            "degree",
+            json!({
+                "name": "剩余电量",
+                "state_topic": topics.degree,
+                "value_template": "{{ value_json.degree }}",
+                "json_attributes_topic": topics.degree,
+                "unit_of_measurement": "kWh",
+                "device_class": "energy_storage",
+                "state_class": "measurement",
+            }),
+        ),
+        (
+            "sensor",
+            "recharge",
+            json!({
+                "name": "最近充值",
+                "state_topic": topics.recharge,
+                "value_template": "{{ value_json.amount }}",
+                "json_attributes_topic": topics.recharge,
+                "unit_of_measurement": "kWh",
+                "icon": "mdi:cash-plus",
+            }),
+        ),
+        (
+            "binary_sensor",
+            "login",
+            json!({
+                "name": "ECNU 登录",
+                "state_topic": topics.login,
+                "device_class": "connectivity",
+            }),
+        ),
+    ]
+    .into_iter()
+    .map(|(component, object_id, mut entity)| {
+        entity["unique_id"] = json!(format!("{node_id}_{object_id}"));
+        entity["availability_topic"] = json!(topics.status);
+        entity["device"] = device.clone();
+        (
+            format!(
+                "{}/{component}/{node_id}/{object_id}/config",
+                config.discovery_prefix
+            ),
+            entity.to_string(),
+        )
+    })
+    .collect()
+}
+
+async fn client_tls_config(tls: &MqttTlsConfig) -> anyhow::Result<rustls::ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    match &tls.root_ca {
+        Some(root_ca) => {
+            for cert in load_certificate_der(&[root_ca]).await? {
+                roots
+                    .add(cert)
+                    .with_context(|| "load mqtt root certs failed")?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let config = match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => {
+            let key = PrivateKeyDer::from_pem_file(key)
+                .with_context(|| format!("read mqtt client key failed: {}", key.display()))?;
+            builder
+                .with_client_auth_cert(load_certificate_der(&[cert]).await?, key)
+                .with_context(|| "mqtt tls client config create failed")?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(config)
+}
+
+/// 根据配置生成连接选项, tls 证书在此时加载.
+pub(crate) async fn options(config: &MqttConfig) -> anyhow::Result<MqttOptions> {
+    let port = config
+        .port
+        .unwrap_or(if config.tls.is_some() { 8883 } else { 1883 });
+    let mut options = MqttOptions::new(&config.client_id, &config.host, port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(
+        Topics::new(&config.topic_prefix).status,
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    if let Some(tls) = &config.tls {
+        let tls_config = client_tls_config(tls).await?;
+        options.set_transport(Transport::tls_with_config(TlsConfiguration::Rustls(
+            Arc::new(tls_config),
+        )));
+    }
+    Ok(options)
+}
+
+/// 启动时的状态: 最后一条记录, 最近一次充值和登录状态.
+async fn current_state(state: &AppState) -> Vec<ServerEvent> {
+    let mut events = Vec::new();
+    match state.recorder.write().await.read_records().await {
+        Ok(mut records) => {
+            records.sort();
+            if let Some(recharge) = analytics::recharges(&records).pop() {
+                events.push(ServerEvent::Recharge(recharge));
+            }
+            if let Some(&(time, degree)) = records.last() {
+                events.push(ServerEvent::Reading { time, degree });
+            }
+        }
+        Err(e) => error!(target: "reading records", "{e:?}"),
+    }
+    let status = state.query_status.read().await;
+    if status.logged_out {
+        events.push(ServerEvent::LoginLost {
+            error: status.last_error.clone().unwrap_or_default(),
+        });
+    } else if status.last_success.is_some() {
+        events.push(ServerEvent::LoginRestored);
+    }
+    events
+}
+
+/// 连接 broker 并发布事件, 事件通道关闭时返回.
+async fn publish_loop(
+    config: &MqttConfig,
+    options: MqttOptions,
+    initial: Vec<ServerEvent>,
+    mut rx: broadcast::Receiver<EventEnvelope>,
+) {
+    let topics = Topics::new(&config.topic_prefix);
+    // 每个主题最新的状态, 重新连接后再次发布.
+    let mut retained: BTreeMap<String, String> = initial
+        .iter()
+        .filter_map(|event| state_message(&topics, event))
+        .collect();
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    let publish = |(topic, payload): (&String, &String)| {
+        if let Err(e) = client.try_publish(topic, QoS::AtLeastOnce, true, payload.as_bytes()) {
+            error!(target: "mqtt publish", "{topic}: {e:?}");
+        }
+    };
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("mqtt connected");
+                    if config.discovery {
+                        for (topic, payload) in discovery_messages(config, &topics) {
+                            publish((&topic, &payload));
+                        }
+                    }
+                    publish((&topics.status, &"online".to_string()));
+                    retained.iter().for_each(publish);
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    error!("mqtt connection: {e:?}");
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            },
+            event = rx.recv() => match event {
+                Ok(envelope) => {
+                    if let Some((topic, payload)) = state_message(&topics, &envelope.event) {
+                        publish((&topic, &payload));
+                        retained.insert(topic, payload);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => warn!("mqtt: {n} events skipped"),
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+pub(crate) async fn mqtt_loop(state: Arc<AppState>, options: MqttOptions) -> ! {
+    let config = state
+        .server_config
+        .mqtt
+        .clone()
+        .expect("mqtt loop requires mqtt config");
+    let rx = state.events.subscribe();
+    let initial = current_state(&state).await;
+    publish_loop(&config, options, initial, rx).await;
+    unreachable!("event sender lives as long as app state");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, TimeZone};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::{broadcast, mpsc};
+
+    use crate::config::MqttConfig;
+    use crate::server::mqtt::{options, publish_loop};
+    use crate::server::{EventEnvelope, ServerEvent};
+
+    /// 读取一个 MQTT 控制报文, 返回 (固定报头的第一个字节, 剩余部分).
+    async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> Option<(u8, Vec<u8>)> {
+        let header = stream.read_u8().await.ok()?;
+        let (mut len, mut shift) = (0usize, 0);
+        loop {
+            let b = stream.read_u8().await.ok()?;
+            len |= usize::from(b & 0x7f) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.ok()?;
+        Some((header, body))
+    }
+
+    /// 本地的 MQTT broker 替身, 把收到的 CONNECT 和 PUBLISH 发送到 `tx`.
+    async fn broker_stand_in(listener: TcpListener, tx: mpsc::UnboundedSender<(String, String)>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        while let Some((header, body)) = read_packet(&mut stream).await {
+            match header >> 4 {
+                // CONNECT
+                1 => {
+                    tx.send(("CONNECT".to_string(), String::from_utf8_lossy(&body).into()))
+                        .unwrap();
+                    stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+                }
+                // PUBLISH
+                3 => {
+                    let qos = (header >> 1) & 0x03;
+                    let topic_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                    let topic = String::from_utf8(body[2..2 + topic_len].to_vec()).unwrap();
+                    let mut rest = &body[2 + topic_len..];
+                    if qos > 0 {
+                        stream
+                            .write_all(&[0x40, 0x02, rest[0], rest[1]])
+                            .await
+                            .unwrap();
+                        rest = &rest[2..];
+                    }
+                    assert_eq!(header & 0x01, 1, "state messages are retained");
+                    tx.send((topic, String::from_utf8(rest.to_vec()).unwrap()))
+                        .unwrap();
+                }
+                // PINGREQ
+                12 => stream.write_all(&[0xd0, 0x00]).await.unwrap(),
+                _ => (),
+            }
+        }
+    }
+
+    async fn next(rx: &mut mpsc::UnboundedReceiver<(String, String)>) -> (String, String) {
+        tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("broker received nothing")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn publish_to_local_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (received_tx, mut received) = mpsc::unbounded_channel();
+        tokio::spawn(broker_stand_in(listener, received_tx));
+
+        let config: MqttConfig = toml::from_str(&format!(
+            "host = \"127.0.0.1\"\nport = {port}\nusername = \"dorm\"\npassword = \"secret\"\nclient_id = \"epu test\""
+        ))
+        .unwrap();
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let time = tz.with_ymd_and_hms(2026, 9, 1, 12, 0, 0).unwrap();
+        let (tx, rx) = broadcast::channel(16);
+        let send = |id: &str, event| {
+            tx.send(EventEnvelope {
+                id: id.to_string(),
+                event,
+            })
+            .unwrap();
+        };
+        let options = options(&config).await.unwrap();
+        let initial = vec![ServerEvent::Reading { time, degree: 12.5 }];
+        let handle = tokio::spawn(async move { publish_loop(&config, options, initial, rx).await });
+
+        let (kind, connect) = next(&mut received).await;
+        assert_eq!(kind, "CONNECT");
+        assert!(connect.contains("dorm") && connect.contains("secret"));
+        assert!(connect.contains("epu/status") && connect.contains("offline"));
+
+        // 自动发现配置, 在线状态以及最新的读数.
+        let mut messages = Vec::new();
+        for _ in 0..5 {
+            messages.push(next(&mut received).await);
+        }
+        let topics: Vec<_> = messages.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/sensor/epu_test/degree/config",
+                "homeassistant/sensor/epu_test/recharge/config",
+                "homeassistant/binary_sensor/epu_test/login/config",
+                "epu/status",
+                "epu/degree",
+            ]
+        );
+        let discovery: serde_json::Value = serde_json::from_str(&messages[0].1).unwrap();
+        assert_eq!(discovery["state_topic"], "epu/degree");
+        assert_eq!(discovery["unique_id"], "epu_test_degree");
+        assert_eq!(messages[3].1, "online");
+        let degree: serde_json::Value = serde_json::from_str(&messages[4].1).unwrap();
+        assert_eq!(degree["degree"], 12.5);
+
+        send(
+            "1-1",
+            ServerEvent::LoginLost {
+                error: "not logged in".to_string(),
+            },
+        );
+        // 没有对应主题的事件不发布.
+        send("1-2", ServerEvent::Resync);
+        assert_eq!(
+            next(&mut received).await,
+            ("epu/login".to_string(), "OFF".to_string())
+        );
+        send(
+            "1-3",
+            ServerEvent::Reading {
+                time,
+                degree: 11.25,
+            },
+        );
+        let (topic, payload) = next(&mut received).await;
+        assert_eq!(topic, "epu/degree");
+        assert!(payload.contains("11.25"));
+
+        drop(tx);
+        handle.await.unwrap();
+    }
+}
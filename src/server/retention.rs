@@ -0,0 +1,263 @@
+//! 保留策略: 超过保留天数的原始记录会被降采样到每小时/每天.
+//!
+//! 降采样后每个时间段保留第一条, 最后一条和度数最低的记录, 充值前后的两条记录也会被完整保留,
+//! 因此降采样后各时间段的用电量 (除去充值) 保持不变, 记录格式也与原始记录相同.
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, Timelike};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::analytics::{Accounting, is_recharge};
+use crate::config::{ARCHIVE_DIRNAME, Resolution, RetentionConfig};
+use crate::server::{AppState, load_archive, read_archive_metas, rewrite_archive};
+use crate::{CSError, Records};
+
+/// 对一组记录 (当前记录或者一个 archive) 执行保留策略的结果.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RetentionTargetReport {
+    /// 为 None 时表示当前记录.
+    pub archive: Option<String>,
+    pub records_before: usize,
+    pub records_after: usize,
+    /// 以 csv 格式保存时的字节数.
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RetentionReport {
+    /// 早于此时间的记录会被降采样.
+    pub cutoff: DateTime<FixedOffset>,
+    pub targets: Vec<RetentionTargetReport>,
+}
+
+impl RetentionReport {
+    /// 可以节省的字节数.
+    #[must_use]
+    pub fn reclaimed_bytes(&self) -> usize {
+        self.targets
+            .iter()
+            .map(|t| t.bytes_before.saturating_sub(t.bytes_after))
+            .sum()
+    }
+}
+
+/// 记录所属的时间段, 按照服务端时区划分.
+fn bucket_key(time: &DateTime<FixedOffset>, resolution: Resolution) -> (i32, u32, u32) {
+    let time = time.with_timezone(&Local);
+    match resolution {
+        Resolution::Hourly => (time.year(), time.ordinal(), time.hour()),
+        Resolution::Daily => (time.year(), time.ordinal(), 0),
+    }
+}
+
+/// 对早于 `cutoff` 的记录进行降采样, `records` 需要已经排好序.
+pub(crate) fn compact(
+    records: &Records,
+    cutoff: DateTime<FixedOffset>,
+    resolution: Resolution,
+) -> Records {
+    let n = records.len();
+    let mut keep = vec![false; n];
+    let mut bucket_start = 0;
+    for i in 0..n {
+        if records[i].0 >= cutoff {
+            keep[i] = true;
+        }
+        if i > 0 && is_recharge(records[i - 1].1, records[i].1) {
+            keep[i - 1] = true;
+            keep[i] = true;
+        }
+        let bucket_ends = i + 1 == n
+            || bucket_key(&records[i].0, resolution) != bucket_key(&records[i + 1].0, resolution);
+        if bucket_ends {
+            let bucket = bucket_start..=i;
+            keep[bucket_start] = true;
+            keep[i] = true;
+            if let Some(min) = bucket
+                .clone()
+                .min_by(|&a, &b| records[a].1.total_cmp(&records[b].1))
+            {
+                keep[min] = true;
+            }
+            bucket_start = i + 1;
+        }
+    }
+    Records(
+        records
+            .iter()
+            .zip(keep)
+            .filter_map(|(rec, keep)| keep.then_some(*rec))
+            .collect(),
+    )
+}
+
+fn cutoff(config: &RetentionConfig) -> DateTime<FixedOffset> {
+    (Local::now() - chrono::Duration::days(i64::from(config.keep_raw_days))).fixed_offset()
+}
+
+async fn target_report(
+    archive: Option<String>,
+    before: &Records,
+    after: &Records,
+) -> crate::Result<RetentionTargetReport> {
+    Ok(RetentionTargetReport {
+        archive,
+        records_before: before.len(),
+        records_after: after.len(),
+        bytes_before: before.to_csv().await?.len(),
+        bytes_after: after.to_csv().await?.len(),
+    })
+}
+
+/// 对当前记录和所有 archive 执行保留策略, `dry_run` 为 true 时只计算结果而不修改.
+pub(crate) async fn run(
+    state: &AppState,
+    config: &RetentionConfig,
+    dry_run: bool,
+) -> Result<RetentionReport, CSError> {
+    let cutoff = cutoff(config);
+    let mut targets = Vec::new();
+
+    let mut recorder = state.recorder.write().await;
+    let room_dir = state.room_dir.read().await.clone();
+    let mut records = recorder.read_records().await.map_err(|e| {
+        error!(target: "reading records", "{e:?}");
+        CSError::ReadRecords
+    })?;
+    records.sort();
+    let compacted = compact(&records, cutoff, config.resolution);
+    targets.push(
+        target_report(None, &records, &compacted)
+            .await
+            .map_err(|_| CSError::SerializeRecords)?,
+    );
+    if !dry_run && compacted.len() < records.len() {
+        recorder.replace(compacted).await.map_err(|e| {
+            error!(target: "writing records", "{e:?}");
+            CSError::WriteRecords
+        })?;
+    }
+
+    // 修正 archive 时同样持有 recorder 的锁, 在压缩 archive 期间保持持有, 避免覆盖修正的结果.
+    targets.extend(run_archives(&room_dir, config, &state.accounting, cutoff, dry_run).await?);
+    drop(recorder);
+    Ok(RetentionReport { cutoff, targets })
+}
+
+async fn run_archives(
+    room_dir: &Path,
+    config: &RetentionConfig,
+    accounting: &Accounting,
+    cutoff: DateTime<FixedOffset>,
+    dry_run: bool,
+) -> Result<Vec<RetentionTargetReport>, CSError> {
+    let archive_dir = room_dir.join(ARCHIVE_DIRNAME);
+    let mut targets = Vec::new();
+    for meta in read_archive_metas(&archive_dir).await? {
+        if meta.start_time >= cutoff {
+            continue;
+        }
+        let mut records = load_archive(&archive_dir, &meta.archive_name).await?;
+        records.sort();
+        let compacted = compact(&records, cutoff, config.resolution);
+        targets.push(
+            target_report(Some(meta.archive_name.clone()), &records, &compacted)
+                .await
+                .map_err(|_| CSError::SerializeRecords)?,
+        );
+        if !dry_run && compacted.len() < records.len() {
+            rewrite_archive(&archive_dir, &meta.archive_name, &compacted, accounting).await?;
+        }
+    }
+    Ok(targets)
+}
+
+pub(crate) async fn retention_loop(state: Arc<AppState>, config: RetentionConfig) -> ! {
+    let mut interval = tokio::time::interval(Duration::from_hours(config.interval_hours.max(1)));
+    loop {
+        interval.tick().await;
+        match run(&state, &config, false).await {
+            Ok(report) => info!(
+                "retention: {} bytes reclaimed, records before {}",
+                report.reclaimed_bytes(),
+                report.cutoff
+            ),
+            Err(e) => error!("retention: {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, Local, TimeZone};
+
+    use crate::Records;
+    use crate::config::Resolution;
+    use crate::server::retention::compact;
+
+    #[test]
+    fn compact_keeps_recharges_and_consumption() {
+        // 按照本机时区分桶, 时间也需要在本机时区构造.
+        let t = |d, h, m| {
+            Local
+                .with_ymd_and_hms(2026, 1, d, h, m, 0)
+                .unwrap()
+                .fixed_offset()
+        };
+        let records = Records(vec![
+            (t(1, 0, 0), 10.0),
+            (t(1, 0, 20), 9.9),
+            (t(1, 0, 40), 9.8),
+            (t(1, 1, 0), 9.7),
+            (t(1, 1, 10), 9.6),
+            // 充值
+            (t(1, 1, 20), 29.6),
+            (t(1, 1, 50), 29.5),
+            (t(1, 2, 0), 29.4),
+            (t(1, 2, 30), 29.3),
+            (t(3, 0, 0), 29.0),
+            (t(3, 0, 30), 28.9),
+        ]);
+        let compacted = compact(&records, t(3, 0, 0), Resolution::Hourly);
+        assert_eq!(
+            compacted.0,
+            vec![
+                (t(1, 0, 0), 10.0),
+                (t(1, 0, 40), 9.8),
+                (t(1, 1, 0), 9.7),
+                (t(1, 1, 10), 9.6),
+                (t(1, 1, 20), 29.6),
+                (t(1, 1, 50), 29.5),
+                (t(1, 2, 0), 29.4),
+                (t(1, 2, 30), 29.3),
+                (t(3, 0, 0), 29.0),
+                (t(3, 0, 30), 28.9),
+            ]
+        );
+
+        let compacted = compact(&records, t(3, 0, 0), Resolution::Daily);
+        assert_eq!(
+            compacted.0,
+            vec![
+                (t(1, 0, 0), 10.0),
+                (t(1, 1, 10), 9.6),
+                (t(1, 1, 20), 29.6),
+                (t(1, 2, 30), 29.3),
+                (t(3, 0, 0), 29.0),
+                (t(3, 0, 30), 28.9),
+            ]
+        );
+    }
+
+    #[test]
+    fn compact_without_old_records() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |h| offset.with_ymd_and_hms(2026, 1, 1, h, 0, 0).unwrap();
+        let records = Records(vec![(t(1), 10.0), (t(2), 9.0), (t(3), 8.0)]);
+        assert_eq!(compact(&records, t(0), Resolution::Daily).0, records.0);
+    }
+}
@@ -0,0 +1,270 @@
+//! 室友和分摊电费.
+//!
+//! 室友信息保存在房间目录下的 `roommates.json` 中. 分摊时按照整点小时计算,
+//! 每个小时的用电量和费用按照权重分给这个小时在宿舍的室友; 如果这个小时所有人都不在宿舍,
+//! 则分给仍然住在这里 (已入住且未搬出) 的室友; 仍然没有人时记为未分摊.
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::HourUsage;
+use crate::config::ROOMMATES_FILENAME;
+use crate::server::store;
+use crate::{CSError, TimeSpan};
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Roommate {
+    pub id: u64,
+    pub name: String,
+    /// 分摊权重, 默认为 1.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    /// 入住时间, 为 None 时表示一直住在这里.
+    pub move_in: Option<DateTime<FixedOffset>>,
+    /// 搬出时间, 为 None 时表示仍然住在这里.
+    pub move_out: Option<DateTime<FixedOffset>>,
+    /// 不在宿舍的时间段, 如回家, 出差.
+    #[serde(default)]
+    pub away: Vec<TimeSpan>,
+}
+
+impl Roommate {
+    fn resident_at(&self, time: &DateTime<FixedOffset>) -> bool {
+        TimeSpan::new(self.move_in, self.move_out).contains(time)
+    }
+
+    fn present_at(&self, time: &DateTime<FixedOffset>) -> bool {
+        self.resident_at(time) && !self.away.iter().any(|span| span.contains(time))
+    }
+}
+
+/// 分摊结果中的一行.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SettlementRow {
+    /// 为 None 时表示没有人可以分摊的部分.
+    pub roommate_id: Option<u64>,
+    pub name: String,
+    pub weight: f32,
+    /// 在宿舍的小时数.
+    pub present_hours: f32,
+    pub consumption: f32,
+    /// 占总用电量的比例.
+    pub share: f32,
+    /// 费用 (元), 没有配置电价时为 None.
+    pub cost: Option<f32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Settlement {
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    pub consumption: f32,
+    pub cost: Option<f32>,
+    pub rows: Vec<SettlementRow>,
+}
+
+impl Settlement {
+    /// 导出为带表头的 csv.
+    #[allow(clippy::missing_panics_doc)]
+    pub async fn to_csv(&self) -> crate::Result<String> {
+        let mut ser = csv_async::AsyncWriterBuilder::new()
+            .has_headers(true)
+            .create_serializer(vec![]);
+        for row in &self.rows {
+            ser.serialize(row).await?;
+        }
+        // unwrap: 在内存中写入不会报错.
+        Ok(String::from_utf8(ser.into_inner().await.unwrap())?)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn hour_duration(hour: &HourUsage) -> TimeDelta {
+    TimeDelta::milliseconds((f64::from(hour.hours) * 3_600_000.0).round() as i64)
+}
+
+/// 将每个小时的用电量按照在宿舍的室友及其权重分摊.
+pub(crate) fn settle(
+    roommates: &[Roommate],
+    usage: &[HourUsage],
+    time_span: &TimeSpan,
+) -> Settlement {
+    let with_cost = usage.iter().any(|u| u.cost.is_some());
+    let new_row = |roommate_id, name: &str, weight| SettlementRow {
+        roommate_id,
+        name: name.to_string(),
+        weight,
+        present_hours: 0.0,
+        consumption: 0.0,
+        share: 0.0,
+        cost: with_cost.then_some(0.0),
+    };
+    let mut rows: Vec<_> = roommates
+        .iter()
+        .map(|r| new_row(Some(r.id), &r.name, r.weight))
+        .collect();
+    let mut unassigned = new_row(None, "unassigned", 0.0);
+
+    for hour in usage {
+        let mid = hour.start_time + hour_duration(hour) / 2;
+        let present: Vec<_> = (0..roommates.len())
+            .filter(|&i| roommates[i].present_at(&mid))
+            .collect();
+        for &i in &present {
+            rows[i].present_hours += hour.hours;
+        }
+        let mut sharing = present;
+        if sharing.is_empty() {
+            sharing = (0..roommates.len())
+                .filter(|&i| roommates[i].resident_at(&mid))
+                .collect();
+        }
+        let total_weight: f32 = sharing.iter().map(|&i| roommates[i].weight.max(0.0)).sum();
+        if total_weight <= 0.0 {
+            unassigned.consumption += hour.consumption;
+            if let (Some(cost), Some(c)) = (unassigned.cost.as_mut(), hour.cost) {
+                *cost += c;
+            }
+            continue;
+        }
+        for &i in &sharing {
+            let ratio = roommates[i].weight.max(0.0) / total_weight;
+            rows[i].consumption += hour.consumption * ratio;
+            if let (Some(cost), Some(c)) = (rows[i].cost.as_mut(), hour.cost) {
+                *cost += c * ratio;
+            }
+        }
+    }
+    if unassigned.consumption > 0.0 {
+        rows.push(unassigned);
+    }
+    let consumption: f32 = usage.iter().map(|u| u.consumption).sum();
+    for row in &mut rows {
+        if consumption > 0.0 {
+            row.share = row.consumption / consumption;
+        }
+    }
+    Settlement {
+        start_time: time_span.start_time.or(usage.first().map(|u| u.start_time)),
+        end_time: time_span
+            .end_time
+            .or(usage.last().map(|u| u.start_time + hour_duration(u))),
+        consumption,
+        cost: with_cost.then(|| usage.iter().filter_map(|u| u.cost).sum()),
+        rows,
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct RoommateStore {
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    roommates: Vec<Roommate>,
+}
+
+async fn load_store(room_dir: &Path) -> Result<RoommateStore, CSError> {
+    store::load(&room_dir.join(ROOMMATES_FILENAME), CSError::RoommateStore).await
+}
+
+async fn save_store(room_dir: &Path, store: &RoommateStore) -> Result<(), CSError> {
+    store::save(
+        &room_dir.join(ROOMMATES_FILENAME),
+        store,
+        CSError::RoommateStore,
+    )
+    .await
+}
+
+pub(crate) async fn list(room_dir: &Path) -> Result<Vec<Roommate>, CSError> {
+    Ok(load_store(room_dir).await?.roommates)
+}
+
+/// 添加室友, `roommate.id` 会被忽略, 由服务端分配.
+pub(crate) async fn add(room_dir: &Path, mut roommate: Roommate) -> Result<Roommate, CSError> {
+    let mut store = load_store(room_dir).await?;
+    roommate.id = store::next_id(&mut store.next_id);
+    store.roommates.push(roommate.clone());
+    save_store(room_dir, &store).await?;
+    Ok(roommate)
+}
+
+/// 按照编号替换室友信息.
+pub(crate) async fn update(room_dir: &Path, roommate: Roommate) -> Result<Roommate, CSError> {
+    let mut store = load_store(room_dir).await?;
+    let existing = store
+        .roommates
+        .iter_mut()
+        .find(|r| r.id == roommate.id)
+        .ok_or(CSError::RoommateNotFound)?;
+    *existing = roommate.clone();
+    save_store(room_dir, &store).await?;
+    Ok(roommate)
+}
+
+pub(crate) async fn delete(room_dir: &Path, id: u64) -> Result<(), CSError> {
+    let mut store = load_store(room_dir).await?;
+    let len = store.roommates.len();
+    store.roommates.retain(|r| r.id != id);
+    if store.roommates.len() == len {
+        return Err(CSError::RoommateNotFound);
+    }
+    save_store(room_dir, &store).await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeDelta, TimeZone};
+
+    use crate::TimeSpan;
+    use crate::analytics::HourUsage;
+    use crate::server::roommate::{Roommate, settle};
+
+    #[test]
+    fn settle_by_presence_and_weight() {
+        let offset = FixedOffset::east_opt(8 * 3600).unwrap();
+        let t = |h| offset.with_ymd_and_hms(2026, 3, 2, h, 0, 0).unwrap();
+        let usage: Vec<_> = (0..4)
+            .map(|h| HourUsage {
+                start_time: t(h),
+                hours: 1.0,
+                consumption: 1.0,
+                cost: Some(0.5),
+            })
+            .collect();
+        let roommate = |id, weight, away: Vec<TimeSpan>| Roommate {
+            id,
+            name: format!("r{id}"),
+            weight,
+            move_in: None,
+            move_out: Some(t(3)),
+            away,
+        };
+        let roommates = vec![
+            roommate(1, 1.0, Vec::new()),
+            // 0 点到 2 点不在.
+            roommate(2, 2.0, vec![TimeSpan::new(Some(t(0)), Some(t(2)))]),
+        ];
+        let settlement = settle(&roommates, &usage, &TimeSpan::ALL);
+        assert!((settlement.consumption - 4.0).abs() < 1e-4);
+        assert!((settlement.cost.unwrap() - 2.0).abs() < 1e-4);
+        let rows = &settlement.rows;
+        assert_eq!(rows.len(), 3);
+        // 0~2 点: r1 独自承担 2 度; 2~3 点: r1 : r2 = 1 : 2; 3 点之后两人都已搬出.
+        assert!((rows[0].consumption - (2.0 + 1.0 / 3.0)).abs() < 1e-4);
+        assert!((rows[1].consumption - 2.0 / 3.0).abs() < 1e-4);
+        assert!((rows[1].present_hours - 1.0).abs() < 1e-4);
+        assert_eq!(rows[2].roommate_id, None);
+        assert!((rows[2].consumption - 1.0).abs() < 1e-4);
+        assert!(
+            settlement
+                .end_time
+                .is_some_and(|et| et == t(3) + TimeDelta::hours(1))
+        );
+    }
+}
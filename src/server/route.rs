@@ -1,27 +1,42 @@
+use axum::extract::Query;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{Form, Json, response::IntoResponse};
 use axum::{
     body::Body,
     extract::State,
-    http::{Response, StatusCode},
+    http::{HeaderMap, Response, StatusCode},
 };
-use chrono::Local;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, FixedOffset, Local};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::convert::Infallible;
 use std::fmt::Debug;
-use std::path::Path;
 use std::sync::Arc;
 use tokio::fs::{self, File};
 use tokio_util::io::ReaderStream;
 use tracing::{debug, error, info, warn};
 
+use crate::analytics::{
+    self, AcademicStats, ApplianceReport, BaselineSplit, Comparison, CostReport, Forecast,
+    Grouping, RechargePlan, Severity, UsageStats,
+};
 use crate::config::{
-    ARCHIVE_DIRNAME, DELETED_DIRNAME, RECORDS_FILENAME, ROOM_CONFIG_FILENAME, RoomConfig,
-    is_sanitized_filename,
+    ARCHIVE_DIRNAME, AcademicPeriod, DELETED_DIRNAME, DigestSchedule, PeriodKind, RECORDS_FILENAME,
+    ROOM_CONFIG_FILENAME, RoomConfig, is_sanitized_filename,
 };
 use crate::error::{CSError, CSResult, Error};
 use crate::rooms::RoomInfo;
 use crate::{ArchiveMeta, Cookies, Records, TimeSpan};
 
-use crate::server::{AppState, Recorder};
+use crate::server::grafana::{GrafanaAnnotations, GrafanaQuery, GrafanaSearch};
+use crate::server::{
+    AlertDelivery, AnnotatedRecords, Annotation, AnomalyEvent, AppState, AwayPeriod, Budget,
+    BudgetPeriod, BudgetProgress, BudgetUnit, CorrectionEntry, CorrectionOp, Digest, DigestFormat,
+    ImportMode, ImportReport, Recorder, RetentionReport, Roommate, ServerEvent, Settlement, alert,
+    annotation, anomaly, appliance, away, budget, collect_records, correction, digest, grafana,
+    import, load_archive, metrics, read_archive_metas, refresh_forecast, remove_archive_files,
+    retention, roommate, save_archive, span_expr::academic_period,
+};
 
 pub(super) async fn post_room(
     State(state): State<Arc<AppState>>,
@@ -73,6 +88,8 @@ pub(super) async fn post_room(
         .write()
         .await
         .set_room_config(room_config.clone());
+    refresh_forecast(&state).await;
+    state.events.send(ServerEvent::RoomChanged(room_config));
     (StatusCode::OK, Json(Ok(())))
 }
 
@@ -85,19 +102,76 @@ pub(super) async fn post_cookies(
     StatusCode::OK
 }
 
+/// 请求中的时间范围: 给出 `span` 表达式时按照服务端时区解析, 否则使用起止时间.
+fn request_span(
+    state: &AppState,
+    span: Option<&str>,
+    start_time: Option<DateTime<FixedOffset>>,
+    end_time: Option<DateTime<FixedOffset>>,
+) -> Result<TimeSpan, CSError> {
+    match span {
+        Some(expr) => TimeSpan::resolve(expr, &Local::now(), &state.server_config.calendar),
+        None => Ok(TimeSpan::new(start_time, end_time)),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct GetRecordsArgs {
+    pub(crate) start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) end_time: Option<DateTime<FixedOffset>>,
+    /// 时间范围表达式, 如 `last:7d`, `month:2026-09`, 给出时忽略起止时间.
+    #[serde(default)]
+    pub(crate) span: Option<String>,
+    /// 为 true 时返回 [`AnnotatedRecords`], 附带与时间范围重叠的标注.
+    #[serde(default)]
+    pub(crate) annotations: bool,
+}
+
+/// 获取时间范围内的记录, 参数需要使用 reqwest `.query()` 的方式给入, 不给入时返回全部记录.
 pub(super) async fn get_records(
     State(state): State<Arc<AppState>>,
-) -> (StatusCode, Json<CSResult<Records>>) {
+    Query(args): Query<GetRecordsArgs>,
+) -> Response<Body> {
     debug!("get records request.");
-    match state.recorder.write().await.read_records().await {
-        Ok(records) => (StatusCode::OK, Json(Ok(records))),
+    let time_span = match request_span(&state, args.span.as_deref(), args.start_time, args.end_time)
+    {
+        Ok(x) => x,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(CSResult::<Records>::Err(e))).into_response();
+        }
+    };
+    let mut records = match state.recorder.write().await.read_records().await {
+        Ok(records) => records,
         Err(e) => {
             error!("reading records from file: {e:?}");
-            (
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(Err(CSError::ReadRecords)),
+                Json(CSResult::<Records>::Err(CSError::ReadRecords)),
             )
+                .into_response();
         }
+    };
+    records.retain(|x| time_span.contains(&x.0));
+    if !args.annotations {
+        return (StatusCode::OK, Json(CSResult::Ok(records))).into_response();
+    }
+
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    match annotation::overlapping(&room_dir, None, &time_span).await {
+        Ok(annotations) => (
+            StatusCode::OK,
+            Json(CSResult::Ok(AnnotatedRecords {
+                records,
+                annotations,
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(CSResult::<AnnotatedRecords>::Err(e)),
+        )
+            .into_response(),
     }
 }
 
@@ -126,7 +200,11 @@ pub(super) async fn get_degree(
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub(crate) struct CreateArchiveArgs {
+    #[serde(default)]
     pub(crate) time_span: TimeSpan,
+    /// 时间范围表达式, 如 `semester:last`, 给出时忽略 `time_span`.
+    #[serde(default)]
+    pub(crate) span: Option<String>,
     /// 默认名称为创建的 archive 时间跨度.
     pub(crate) archive_name: Option<String>,
 }
@@ -134,7 +212,6 @@ pub(crate) struct CreateArchiveArgs {
 /// 创建 archive, 将符合时间范围的 records 保存到 archives 之中.
 ///
 /// fixme: 这里的原子化实践仍然可能由于 async cancellation 而取消, 这点需要解决.
-#[allow(clippy::too_many_lines)]
 pub(super) async fn create_archive(
     State(state): State<Arc<AppState>>,
     Json(args): Json<CreateArchiveArgs>,
@@ -143,8 +220,26 @@ pub(super) async fn create_archive(
 
     let CreateArchiveArgs {
         time_span,
+        span,
         archive_name,
     } = args;
+    // 以校历时间段创建 archive 时, 默认使用时间段的名称.
+    let archive_name = archive_name.or_else(|| {
+        let today = Local::now().date_naive();
+        let calendar = &state.server_config.calendar;
+        span.as_deref()
+            .and_then(|expr| academic_period(expr, today, calendar))
+            .map(|p| p.name.clone())
+    });
+    let time_span = match span {
+        Some(expr) => {
+            match TimeSpan::resolve(&expr, &Local::now(), &state.server_config.calendar) {
+                Ok(x) => x,
+                Err(e) => return (StatusCode::BAD_REQUEST, Json(Err(e))),
+            }
+        }
+        None => time_span,
+    };
 
     if let Some(archive_name) = archive_name.as_ref()
         && !is_sanitized_filename(archive_name)
@@ -156,7 +251,7 @@ pub(super) async fn create_archive(
     }
 
     let mut recorder = state.recorder.write().await;
-    let mut handle = match recorder.archive(time_span).await {
+    let mut handle = match recorder.archive(time_span.clone()).await {
         Ok(x) => x,
         Err(e) => {
             error!(target: "reading records", "{e:?}");
@@ -168,120 +263,55 @@ pub(super) async fn create_archive(
     };
 
     handle.archived.sort();
-    let Some((start_time, end_time)) = handle.archived.time_span() else {
-        // 如果 records 无法计算出时间跨度, 那么说明其为空.
-        return (StatusCode::OK, Json(Err(CSError::EmptyArchive)));
-    };
-
-    let archive_name = match archive_name {
-        Some(x) => x,
-        None => {
-            format!(
-                "{}-{}-by-{}",
-                start_time.format("%Y%d%m"),
-                end_time.format("%Y%d%m"),
-                Local::now().format("%Y%d%m_%H%M%S")
-            )
-        }
-    };
-
     let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
-    let archive_file = archive_dir.join(format!("{archive_name}.csv"));
-    let archive_meta_file = archive_dir.join(format!("{archive_name}.toml"));
-
-    fs::create_dir_all(&archive_dir).await.ok(); // 如果失败了会在下面报错;
-
-    match fs::read_dir(&archive_dir).await {
-        Ok(mut rd) => {
-            while let Some(entry) = match rd.next_entry().await {
-                Ok(x) => x,
-                Err(e) => {
-                    error!(target: "reading archive dir", "{e:?}");
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(Err(CSError::ArchiveDir)),
-                    );
-                }
-            } {
-                if entry.file_name() == archive_file {
-                    return (
-                        StatusCode::BAD_REQUEST,
-                        Json(Err(CSError::DuplicatedArchive)),
-                    );
-                }
-            }
-        }
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(Err(CSError::ArchiveDir)),
-            );
-        }
-    }
 
-    let archive_meta = ArchiveMeta {
-        start_time,
-        end_time,
+    // fixme: 这下面的操作仍然有可能被 async cancelled 而导致非原子化, 但是暂时无法使用 tokio::spawn 解决, 因为
+    // state 和 recorder 的生命周期不够长 (handle 需要).
+    let archive_meta = match save_archive(
+        &archive_dir,
         archive_name,
-        records_num: handle.archived.len(),
-    };
-
-    let archived_content = match handle.archived.to_csv().await {
-        Ok(x) => x,
-        Err(e) => {
-            error!(target: "serializing records", "{e:?}");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(Err(CSError::SerializeRecords)),
-            );
-        }
-    };
-
-    let archived_meta_content = match toml::to_string_pretty(&archive_meta) {
+        &handle.archived,
+        &state.accounting,
+    )
+    .await
+    {
         Ok(x) => x,
-        Err(e) => {
-            error!(target: "serializing archive meta", "{e:?}");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(Err(CSError::SerializeMeta)),
-            );
+        Err(e @ CSError::EmptyArchive) => return (StatusCode::OK, Json(Err(e))),
+        Err(e @ (CSError::DuplicatedArchive | CSError::InvalidArchiveName)) => {
+            return (StatusCode::BAD_REQUEST, Json(Err(e)));
         }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
     };
 
-    // fixme: 这下面的操作仍然有可能被 async cancelled 而导致非原子化, 但是暂时无法使用 tokio::spawn 解决, 因为
-    // state 和 recorder 的生命周期不够长 (handle 需要).
-    if let Err(e) = fs::write(&archive_file, archived_content).await {
-        error!(target: "writing archive file", "{e:?}");
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(Err(CSError::WriteArchive)),
-        );
-    }
-
-    if let Err(e) = fs::write(&archive_meta_file, archived_meta_content).await {
-        error!(target: "saving archive meta", "{e:?}");
-        fs::remove_file(&archive_file).await.ok();
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(Err(CSError::SaveArchiveMeta)),
-        );
-    }
-
     if let Err(e) = handle.commit().await {
         error!(target: "commiting archive", "{e:?}");
-        fs::remove_file(archive_file).await.ok();
-        fs::remove_file(archive_meta_file).await.ok();
+        remove_archive_files(&archive_dir, &archive_meta.archive_name).await;
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(Err(CSError::WriteArchive)),
         );
     }
+
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    if let Err(e) =
+        annotation::move_into_archive(&room_dir, &archive_meta.archive_name, &time_span).await
+    {
+        // 标注仍然保留在当前记录中, 依然可以通过时间范围查询到, 因此不视为失败.
+        warn!("moving annotations into archive: {e:?}");
+    }
+    state
+        .events
+        .send(ServerEvent::ArchiveCreated(archive_meta.clone()));
     (StatusCode::OK, Json(Ok(archive_meta)))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub(crate) struct DownloadArchiveArgs {
     pub(crate) name: String,
+    /// 为 true 时以 json 形式返回 [`AnnotatedRecords`], 而不是 csv 文件.
+    #[serde(default)]
+    pub(crate) annotations: bool,
 }
 
 /// 这里的 Form 需要使用 reqwest `.query()` 的方式给入, 而不是 `.form()`.
@@ -299,6 +329,10 @@ pub(super) async fn download_archive(
         return (StatusCode::BAD_REQUEST, Json(CSError::InvalidArchiveName)).into_response();
     };
 
+    if args.annotations {
+        return download_annotated_archive(&state, &archive_name).await;
+    }
+
     match File::open(archive_dir.join(format!("{archive_name}.csv"))).await {
         Ok(file) => {
             let stream = ReaderStream::new(file);
@@ -318,54 +352,54 @@ pub(super) async fn download_archive(
     }
 }
 
+/// archive 的记录, 附带其中被归档的标注以及当前记录中与其时间范围重叠的标注.
+async fn download_annotated_archive(state: &AppState, archive_name: &str) -> Response<Body> {
+    let room_dir = state.room_dir.read().await.clone();
+    let records = match load_archive(&room_dir.join(ARCHIVE_DIRNAME), archive_name).await {
+        Ok(x) => x,
+        Err(e @ CSError::ArchiveNotFound) => {
+            return (StatusCode::NOT_FOUND, Json(e)).into_response();
+        }
+        Err(e @ CSError::InvalidArchiveName) => {
+            return (StatusCode::BAD_REQUEST, Json(e)).into_response();
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
+    };
+    let (start_time, end_time) = records.time_span().unzip();
+    let time_span = TimeSpan::new(start_time, end_time);
+    let _lock = state.annotations_lock.lock().await;
+    let annotations = match (
+        annotation::list(&room_dir, Some(archive_name)).await,
+        annotation::overlapping(&room_dir, None, &time_span).await,
+    ) {
+        (Ok(mut archived), Ok(live)) => {
+            archived.extend(live);
+            archived
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response();
+        }
+    };
+    (
+        StatusCode::OK,
+        Json(AnnotatedRecords {
+            records,
+            annotations,
+        }),
+    )
+        .into_response()
+}
+
 pub(super) async fn list_archives(
     State(state): State<Arc<AppState>>,
 ) -> (StatusCode, Json<CSResult<Vec<ArchiveMeta>>>) {
     info!("list archives request.");
 
     let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
-    fs::create_dir_all(&archive_dir).await.ok();
-    let Ok(mut rd) = fs::read_dir(&archive_dir).await else {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(Err(CSError::ArchiveDir)),
-        );
-    };
-    let mut archive_metas = Vec::new();
-    while let Some(entry) = match rd.next_entry().await {
-        Ok(x) => x,
-        Err(e) => {
-            error!("listing archives: {e:?}");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(Err(CSError::ListArchive)),
-            );
-        }
-    } {
-        let Some(meta_filename) = entry
-            .file_name()
-            .to_str()
-            .filter(|s| {
-                Path::new(s)
-                    .extension()
-                    .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"))
-            })
-            .map(|s: &str| s.to_string())
-        else {
-            continue;
-        };
-        let meta_file = archive_dir.join(&meta_filename);
-        let Ok(meta_content) = fs::read(meta_file).await else {
-            continue;
-        };
-        let Ok(meta) = toml::from_slice::<ArchiveMeta>(&meta_content) else {
-            continue;
-        };
-        archive_metas.push(meta);
+    match read_archive_metas(&archive_dir).await {
+        Ok(archive_metas) => (StatusCode::OK, Json(Ok(archive_metas))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
     }
-
-    archive_metas.sort();
-    (StatusCode::OK, Json(Ok(archive_metas)))
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -401,6 +435,8 @@ pub(super) async fn delete_archive(
     let mut deleted_archive_file = deleted_dir.join(format!("{}.csv.{}.{}", archive_name, now, 0));
     let mut deleted_archive_meta_file =
         deleted_dir.join(format!("{}.toml.{}.{}", archive_name, now, 0));
+    let mut deleted_annotations_file =
+        deleted_dir.join(format!("{}.annotations.json.{}.{}", archive_name, now, 0));
     // 已删除归档名称去重.
     while deleted_archive_file.exists() {
         let Some(prev_ext) = deleted_archive_file.extension().and_then(|s| s.to_str()) else {
@@ -416,6 +452,7 @@ pub(super) async fn delete_archive(
         };
         deleted_archive_file = deleted_archive_file.with_extension(format!("{num}"));
         deleted_archive_meta_file = deleted_archive_meta_file.with_extension(format!("{num}"));
+        deleted_annotations_file = deleted_annotations_file.with_extension(format!("{num}"));
     }
 
     fs::create_dir_all(&archive_dir).await.ok();
@@ -442,6 +479,17 @@ pub(super) async fn delete_archive(
             Json(Err(CSError::DeletedArchiveFailed)),
         );
     }
+    let annotations_file = annotation::archive_annotations_file(&archive_dir, &archive_name);
+    if annotations_file.exists() {
+        info!("renaming: {annotations_file:?} -> {deleted_annotations_file:?}");
+        if let Err(e) = fs::rename(&annotations_file, &deleted_annotations_file).await {
+            error!("renaming failed: {e:?}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(Err(CSError::DeletedArchiveFailed)),
+            );
+        }
+    }
     (StatusCode::OK, Json(Ok(())))
 }
 
@@ -460,6 +508,9 @@ pub(super) async fn clear_room(
         .write()
         .await
         .set_room_config(RoomConfig::empty());
+    state
+        .events
+        .send(ServerEvent::RoomChanged(RoomConfig::empty()));
     if let Err(e) = RoomConfig::empty()
         .save_to_file(state.config_dir.join(ROOM_CONFIG_FILENAME))
         .await
@@ -494,3 +545,1249 @@ pub(super) async fn get_room_info(
         }
     }
 }
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ImportRecordsArgs {
+    #[serde(default)]
+    pub(crate) mode: ImportMode,
+    /// 仅在 [`ImportMode::Archive`] 时使用, 默认名称为导入记录的时间跨度.
+    pub(crate) archive_name: Option<String>,
+}
+
+/// 导入请求体中的 csv 内容, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn import_records(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<ImportRecordsArgs>,
+    body: String,
+) -> (StatusCode, Json<CSResult<ImportReport>>) {
+    info!("import records request: {args:?}");
+    match args.mode {
+        ImportMode::Merge => {
+            let mut recorder = state.recorder.write().await;
+            match import::import_into_recorder(&mut recorder, &body).await {
+                Ok(report) => (StatusCode::OK, Json(Ok(report))),
+                Err(e) => {
+                    error!(target: "importing records", "{e:?}");
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(Err(CSError::WriteRecords)),
+                    )
+                }
+            }
+        }
+        ImportMode::Archive => {
+            let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
+            match import::import_as_archive(
+                &archive_dir,
+                args.archive_name,
+                &body,
+                &state.accounting,
+            )
+            .await
+            {
+                Ok(report) => {
+                    if let Some(archive_meta) = &report.archive {
+                        state
+                            .events
+                            .send(ServerEvent::ArchiveCreated(archive_meta.clone()));
+                    }
+                    (StatusCode::OK, Json(Ok(report)))
+                }
+                Err(
+                    e @ (CSError::EmptyArchive
+                    | CSError::DuplicatedArchive
+                    | CSError::InvalidArchiveName),
+                ) => (StatusCode::BAD_REQUEST, Json(Err(e))),
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+            }
+        }
+    }
+}
+
+/// 修正请求的状态码: 找不到目标返回 404, 请求本身有误返回 400.
+fn correction_status(e: &CSError) -> StatusCode {
+    match e {
+        CSError::ArchiveNotFound | CSError::RecordNotFound => StatusCode::NOT_FOUND,
+        CSError::InvalidArchiveName
+        | CSError::DuplicatedRecord
+        | CSError::EmptyArchive
+        | CSError::NothingToUndo
+        | CSError::NoRecordsToDelete => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn correction_response(
+    result: CSResult<CorrectionEntry>,
+) -> (StatusCode, Json<CSResult<CorrectionEntry>>) {
+    match result {
+        Ok(entry) => (StatusCode::OK, Json(Ok(entry))),
+        Err(e) => (correction_status(&e), Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct InsertRecordArgs {
+    /// 为 None 时插入到当前记录中.
+    pub(crate) archive: Option<String>,
+    pub(crate) time: DateTime<FixedOffset>,
+    pub(crate) degree: f32,
+    /// 读数来源, 如 "meter" 表示从电表上手动抄录.
+    pub(crate) source: String,
+}
+
+/// 插入一条手动读数.
+pub(super) async fn insert_record(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<InsertRecordArgs>,
+) -> (StatusCode, Json<CSResult<CorrectionEntry>>) {
+    info!("insert record request: {args:?}");
+    let InsertRecordArgs {
+        archive,
+        time,
+        degree,
+        source,
+    } = args;
+    let mut recorder = state.recorder.write().await;
+    let room_dir = state.room_dir.read().await.clone();
+    correction_response(
+        correction::correct(
+            &mut recorder,
+            &room_dir,
+            &state.accounting,
+            archive,
+            CorrectionOp::Insert { source },
+            |records| {
+                if records.iter().any(|x| x.0 == time) {
+                    Err(CSError::DuplicatedRecord)
+                } else {
+                    Ok((Records(Vec::new()), Records(vec![(time, degree)])))
+                }
+            },
+        )
+        .await,
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct DeleteRecordsArgs {
+    /// 为 None 时删除当前记录中的读数.
+    pub(crate) archive: Option<String>,
+    pub(crate) times: Vec<DateTime<FixedOffset>>,
+}
+
+/// 按时间点删除读数, 任何一个时间点没有对应的读数时不做任何修改.
+pub(super) async fn delete_records(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<DeleteRecordsArgs>,
+) -> (StatusCode, Json<CSResult<CorrectionEntry>>) {
+    info!("delete records request: {args:?}");
+    let DeleteRecordsArgs { archive, times } = args;
+    if times.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(Err(CSError::NoRecordsToDelete)),
+        );
+    }
+    let mut recorder = state.recorder.write().await;
+    let room_dir = state.room_dir.read().await.clone();
+    correction_response(
+        correction::correct(
+            &mut recorder,
+            &room_dir,
+            &state.accounting,
+            archive,
+            CorrectionOp::Delete,
+            |records| {
+                let removed = times
+                    .iter()
+                    .map(|t| correction::find_record(records, t))
+                    .collect::<CSResult<Vec<_>>>()?;
+                Ok((Records(removed), Records(Vec::new())))
+            },
+        )
+        .await,
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct CorrectRecordArgs {
+    /// 为 None 时修改当前记录中的读数.
+    pub(crate) archive: Option<String>,
+    pub(crate) time: DateTime<FixedOffset>,
+    pub(crate) degree: f32,
+}
+
+/// 修改某一时间点读数的度数.
+pub(super) async fn correct_record(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<CorrectRecordArgs>,
+) -> (StatusCode, Json<CSResult<CorrectionEntry>>) {
+    info!("correct record request: {args:?}");
+    let CorrectRecordArgs {
+        archive,
+        time,
+        degree,
+    } = args;
+    let mut recorder = state.recorder.write().await;
+    let room_dir = state.room_dir.read().await.clone();
+    correction_response(
+        correction::correct(
+            &mut recorder,
+            &room_dir,
+            &state.accounting,
+            archive,
+            CorrectionOp::Correct,
+            |records| {
+                let old = correction::find_record(records, &time)?;
+                Ok((Records(vec![old]), Records(vec![(old.0, degree)])))
+            },
+        )
+        .await,
+    )
+}
+
+/// 撤销最后一次修正, 返回撤销操作对应的日志记录.
+pub(super) async fn undo_correction(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<CorrectionEntry>>) {
+    info!("undo correction request.");
+    let mut recorder = state.recorder.write().await;
+    let room_dir = state.room_dir.read().await.clone();
+    correction_response(correction::undo_last(&mut recorder, &room_dir, &state.accounting).await)
+}
+
+/// 列出当前房间的修正日志.
+pub(super) async fn list_corrections(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Vec<CorrectionEntry>>>) {
+    debug!("list corrections request.");
+    let room_dir = state.room_dir.read().await.clone();
+    match correction::read_journal(&room_dir).await {
+        Ok(entries) => (StatusCode::OK, Json(Ok(entries))),
+        Err(e) => {
+            error!(target: "reading correction journal", "{e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(Err(CSError::CorrectionJournal)),
+            )
+        }
+    }
+}
+
+fn annotation_status(e: &CSError) -> StatusCode {
+    match e {
+        CSError::ArchiveNotFound | CSError::AnnotationNotFound => StatusCode::NOT_FOUND,
+        CSError::InvalidArchiveName => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn annotation_response<T>(result: CSResult<T>) -> (StatusCode, Json<CSResult<T>>) {
+    match result {
+        Ok(x) => (StatusCode::OK, Json(Ok(x))),
+        Err(e) => (annotation_status(&e), Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct AddAnnotationArgs {
+    /// 为 None 时添加到当前记录的标注中.
+    pub(crate) archive: Option<String>,
+    pub(crate) text: String,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    pub(crate) time_span: TimeSpan,
+}
+
+pub(super) async fn add_annotation(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<AddAnnotationArgs>,
+) -> (StatusCode, Json<CSResult<Annotation>>) {
+    info!("add annotation request: {args:?}");
+    let AddAnnotationArgs {
+        archive,
+        text,
+        tags,
+        time_span,
+    } = args;
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    annotation_response(
+        annotation::add(
+            &room_dir,
+            archive.as_deref(),
+            text,
+            tags,
+            time_span,
+            Local::now().fixed_offset(),
+        )
+        .await,
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct UpdateAnnotationArgs {
+    pub(crate) archive: Option<String>,
+    pub(crate) id: u64,
+    pub(crate) text: String,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    pub(crate) time_span: TimeSpan,
+}
+
+pub(super) async fn update_annotation(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<UpdateAnnotationArgs>,
+) -> (StatusCode, Json<CSResult<Annotation>>) {
+    info!("update annotation request: {args:?}");
+    let UpdateAnnotationArgs {
+        archive,
+        id,
+        text,
+        tags,
+        time_span,
+    } = args;
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    annotation_response(
+        annotation::update(&room_dir, archive.as_deref(), id, text, tags, time_span).await,
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct DeleteAnnotationArgs {
+    pub(crate) archive: Option<String>,
+    pub(crate) id: u64,
+}
+
+pub(super) async fn delete_annotation(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<DeleteAnnotationArgs>,
+) -> (StatusCode, Json<CSResult<()>>) {
+    info!("delete annotation request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    annotation_response(annotation::delete(&room_dir, args.archive.as_deref(), args.id).await)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct ListAnnotationsArgs {
+    /// 为 None 时列出当前记录的标注.
+    pub(crate) archive: Option<String>,
+}
+
+/// 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn list_annotations(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<ListAnnotationsArgs>,
+) -> (StatusCode, Json<CSResult<Vec<Annotation>>>) {
+    debug!("list annotations request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    annotation_response(annotation::list(&room_dir, args.archive.as_deref()).await)
+}
+
+/// 按照配置的保留策略预演一次降采样, 不修改任何记录.
+pub(super) async fn retention_report(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<RetentionReport>>) {
+    info!("retention report request.");
+    let Some(config) = state.server_config.retention.as_ref() else {
+        return (StatusCode::OK, Json(Err(CSError::RetentionDisabled)));
+    };
+    match retention::run(&state, config, true).await {
+        Ok(report) => (StatusCode::OK, Json(Ok(report))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct GetUsageStatsArgs {
+    pub(crate) start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) end_time: Option<DateTime<FixedOffset>>,
+    /// 时间范围表达式, 如 `last:7d`, `month:2026-09`, 给出时忽略起止时间.
+    #[serde(default)]
+    pub(crate) span: Option<String>,
+    pub(crate) grouping: Grouping,
+    /// 为 true 时同时统计与时间范围重叠的 archive 中的记录.
+    #[serde(default)]
+    pub(crate) include_archives: bool,
+}
+
+/// 按照小时/星期/日期/月份统计用电量分布, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn get_usage_stats(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<GetUsageStatsArgs>,
+) -> (StatusCode, Json<CSResult<UsageStats>>) {
+    debug!("get usage stats request: {args:?}");
+    let time_span = match request_span(&state, args.span.as_deref(), args.start_time, args.end_time)
+    {
+        Ok(x) => x,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(Err(e))),
+    };
+    match collect_records(&state, &time_span, args.include_archives).await {
+        Ok(records) => (
+            StatusCode::OK,
+            Json(Ok(analytics::usage_stats(
+                &records,
+                &time_span,
+                args.grouping,
+            ))),
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+/// 获取电量耗尽的预测, 预测会在每次记录新的读数时更新.
+pub(super) async fn get_forecast(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Forecast>>) {
+    debug!("get forecast request.");
+    let cached = state.forecast.read().await.clone();
+    let forecast = match cached {
+        Some(forecast) => Some(forecast),
+        None => refresh_forecast(&state).await,
+    };
+    match forecast {
+        Some(forecast) => (StatusCode::OK, Json(Ok(forecast))),
+        None => (StatusCode::OK, Json(Err(CSError::InsufficientRecords))),
+    }
+}
+
+/// 默认在预计用电量之上额外预留 10%.
+const DEFAULT_RECHARGE_MARGIN: f32 = 0.1;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct PlanRechargeArgs {
+    pub(crate) target_time: DateTime<FixedOffset>,
+    /// 在预计用电量之上额外预留的比例, 为 None 时使用 [`DEFAULT_RECHARGE_MARGIN`].
+    pub(crate) margin: Option<f32>,
+}
+
+/// 根据最近的用电情况计算到目标时间为止需要充值的度数, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn plan_recharge(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<PlanRechargeArgs>,
+) -> (StatusCode, Json<CSResult<RechargePlan>>) {
+    debug!("plan recharge request: {args:?}");
+    let records = match collect_records(&state, &TimeSpan::ALL, false).await {
+        Ok(records) => records,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    };
+    if records.last().is_some_and(|x| x.0 >= args.target_time) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(Err(CSError::InvalidTargetTime)),
+        );
+    }
+    let Some(plan) = analytics::plan_recharge(
+        &records,
+        args.target_time,
+        args.margin.unwrap_or(DEFAULT_RECHARGE_MARGIN),
+        &state.accounting,
+    ) else {
+        return (StatusCode::OK, Json(Err(CSError::InsufficientRecords)));
+    };
+    (StatusCode::OK, Json(Ok(plan)))
+}
+
+/// 查询参数中的布尔值.
+///
+/// 含有 `#[serde(flatten)]` 的参数中, 查询参数的值都以字符串的形式给出, 需要自行解析.
+fn query_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Bool(bool),
+        Str(String),
+    }
+    match Value::deserialize(deserializer)? {
+        Value::Bool(b) => Ok(b),
+        Value::Str(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+/// 统计接口共用的参数: 时间范围, 或者某个 archive.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct SpanArgs {
+    pub(crate) start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) end_time: Option<DateTime<FixedOffset>>,
+    /// 时间范围表达式, 如 `last:7d`, `month:2026-09`, 给出时忽略起止时间.
+    #[serde(default)]
+    pub(crate) span: Option<String>,
+    /// 统计某个 archive 中的记录, 此时忽略时间范围.
+    pub(crate) archive: Option<String>,
+    /// 为 true 时同时统计与时间范围重叠的 archive 中的记录.
+    #[serde(default, deserialize_with = "query_bool")]
+    pub(crate) include_archives: bool,
+}
+
+impl SpanArgs {
+    /// 统计使用的记录和时间范围, 参见 [`records_for_accounting`].
+    async fn records(&self, state: &AppState) -> Result<(Records, TimeSpan), CSError> {
+        let time_span = request_span(state, self.span.as_deref(), self.start_time, self.end_time)?;
+        records_for_accounting(
+            state,
+            time_span,
+            self.archive.clone(),
+            self.include_archives,
+        )
+        .await
+    }
+}
+
+/// 读取统计所用的记录出错时的状态码: 参数有误返回 400.
+fn span_error_status(e: &CSError) -> StatusCode {
+    match e {
+        CSError::ArchiveNotFound | CSError::InvalidArchiveName | CSError::InvalidSpanExpr(_) => {
+            StatusCode::BAD_REQUEST
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// 统计费用时使用的记录和时间范围: 给出 `archive` 时为 archive 中的全部记录, 否则为时间范围内的记录.
+async fn records_for_accounting(
+    state: &AppState,
+    time_span: TimeSpan,
+    archive: Option<String>,
+    include_archives: bool,
+) -> Result<(Records, TimeSpan), CSError> {
+    let (mut records, time_span) = match archive {
+        Some(name) => {
+            let archive_dir = state.room_dir.read().await.join(ARCHIVE_DIRNAME);
+            (load_archive(&archive_dir, &name).await?, TimeSpan::ALL)
+        }
+        None => (
+            collect_records(state, &time_span, include_archives).await?,
+            time_span,
+        ),
+    };
+    records.sort();
+    Ok((records, time_span))
+}
+
+/// 按照配置的电价和排放因子统计费用和碳排放, 并按月汇总, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn get_costs(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<SpanArgs>,
+) -> (StatusCode, Json<CSResult<CostReport>>) {
+    debug!("get costs request: {args:?}");
+    match args.records(&state).await {
+        Ok((records, time_span)) => (
+            StatusCode::OK,
+            Json(Ok(analytics::cost_report(
+                &records,
+                &time_span,
+                &state.accounting,
+            ))),
+        ),
+        Err(e) => (span_error_status(&e), Json(Err(e))),
+    }
+}
+
+/// 估计基础负载功率, 并将用电量划分为基础负载和主动用电, 参数需要使用 reqwest `.query()` 的方式给入.
+///
+/// 时间范围太短无法估计基础负载时, 使用全部记录估计.
+pub(super) async fn get_baseline(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<SpanArgs>,
+) -> (StatusCode, Json<CSResult<BaselineSplit>>) {
+    debug!("get baseline request: {args:?}");
+    let (records, time_span) = match args.records(&state).await {
+        Ok(x) => x,
+        Err(e) => return (span_error_status(&e), Json(Err(e))),
+    };
+    let Some(rate) = analytics::baseline_rate(&records, &time_span)
+        .or_else(|| analytics::baseline_rate(&records, &TimeSpan::ALL))
+    else {
+        return (StatusCode::OK, Json(Err(CSError::InsufficientRecords)));
+    };
+    (
+        StatusCode::OK,
+        Json(Ok(analytics::split_baseline(&records, &time_span, rate))),
+    )
+}
+
+/// 根据带有电器标签的标注估计各个电器的用电量和平均每次使用的费用, 参数需要使用 reqwest `.query()` 的方式给入.
+///
+/// 使用当前记录中与时间范围重叠的标注, 统计 archive 时还包括 archive 中的标注.
+pub(super) async fn get_appliances(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<SpanArgs>,
+) -> (StatusCode, Json<CSResult<ApplianceReport>>) {
+    debug!("get appliances request: {args:?}");
+    let result = async {
+        let (records, time_span) = args.records(&state).await?;
+        let room_dir = state.room_dir.read().await.clone();
+        let annotations = {
+            let _lock = state.annotations_lock.lock().await;
+            let covered = match records.time_span() {
+                Some((st, et)) if args.archive.is_some() => TimeSpan::new(Some(st), Some(et)),
+                _ => time_span.clone(),
+            };
+            let mut annotations = annotation::overlapping(&room_dir, None, &covered).await?;
+            if let Some(archive) = args.archive.as_deref() {
+                annotations.extend(annotation::list(&room_dir, Some(archive)).await?);
+            }
+            annotations
+        };
+        Ok::<_, CSError>((records, time_span, appliance::intervals(&annotations)))
+    }
+    .await;
+    let (records, time_span, intervals) = match result {
+        Ok(x) => x,
+        Err(e) => return (span_error_status(&e), Json(Err(e))),
+    };
+    let Some(rate) = analytics::baseline_rate(&records, &time_span)
+        .or_else(|| analytics::baseline_rate(&records, &TimeSpan::ALL))
+    else {
+        return (StatusCode::OK, Json(Err(CSError::InsufficientRecords)));
+    };
+    (
+        StatusCode::OK,
+        Json(Ok(analytics::attribute_appliances(
+            &records,
+            &time_span,
+            &intervals,
+            rate,
+            &state.accounting,
+        ))),
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct CompareArgs {
+    pub(crate) first_start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) first_end_time: Option<DateTime<FixedOffset>>,
+    /// 使用某个 archive 作为第一段时间, 此时忽略第一段的时间范围.
+    pub(crate) first_archive: Option<String>,
+    /// 第一段时间的时间范围表达式, 给出时忽略第一段的起止时间.
+    #[serde(default)]
+    pub(crate) first_span: Option<String>,
+    pub(crate) second_start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) second_end_time: Option<DateTime<FixedOffset>>,
+    pub(crate) second_archive: Option<String>,
+    #[serde(default)]
+    pub(crate) second_span: Option<String>,
+    /// 为 true 时同时统计与时间范围重叠的 archive 中的记录.
+    #[serde(default)]
+    pub(crate) include_archives: bool,
+}
+
+impl CompareArgs {
+    /// 第一段和第二段时间各自的参数.
+    fn spans(self) -> (SpanArgs, SpanArgs) {
+        (
+            SpanArgs {
+                start_time: self.first_start_time,
+                end_time: self.first_end_time,
+                span: self.first_span,
+                archive: self.first_archive,
+                include_archives: self.include_archives,
+            },
+            SpanArgs {
+                start_time: self.second_start_time,
+                end_time: self.second_end_time,
+                span: self.second_span,
+                archive: self.second_archive,
+                include_archives: self.include_archives,
+            },
+        )
+    }
+}
+
+/// 对比两段时间 (或两个 archive) 的用电情况, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn compare(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<CompareArgs>,
+) -> (StatusCode, Json<CSResult<Comparison>>) {
+    debug!("compare request: {args:?}");
+    let (first, second) = args.spans();
+    let records =
+        async { Ok::<_, CSError>((first.records(&state).await?, second.records(&state).await?)) }
+            .await;
+    match records {
+        Ok(((first_records, first_span), (second_records, second_span))) => (
+            StatusCode::OK,
+            Json(Ok(analytics::compare(
+                &first_records,
+                &first_span,
+                &second_records,
+                &second_span,
+            ))),
+        ),
+        Err(e) => (span_error_status(&e), Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct ResolveSpanArgs {
+    pub(crate) expr: String,
+}
+
+/// 按照服务端时区和校历解析时间范围表达式, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn resolve_span(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<ResolveSpanArgs>,
+) -> (StatusCode, Json<CSResult<TimeSpan>>) {
+    debug!("resolve span request: {args:?}");
+    match request_span(&state, Some(&args.expr), None, None) {
+        Ok(time_span) => (StatusCode::OK, Json(Ok(time_span))),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(Err(e))),
+    }
+}
+
+/// 获取服务端配置的校历.
+pub(super) async fn get_calendar(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Vec<AcademicPeriod>>>) {
+    debug!("get calendar request.");
+    (
+        StatusCode::OK,
+        Json(Ok(state.server_config.calendar.clone())),
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct GetAcademicStatsArgs {
+    pub(crate) start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) end_time: Option<DateTime<FixedOffset>>,
+    /// 时间范围表达式, 如 `year:2026`, 给出时忽略起止时间.
+    #[serde(default)]
+    pub(crate) span: Option<String>,
+    /// 只统计此种类的时间段.
+    pub(crate) kind: Option<PeriodKind>,
+    /// 为 true 时同时统计与时间范围重叠的 archive 中的记录.
+    #[serde(default)]
+    pub(crate) include_archives: bool,
+}
+
+/// 按照校历中与时间范围重叠的各个时间段统计用电情况, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn get_academic_stats(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<GetAcademicStatsArgs>,
+) -> (StatusCode, Json<CSResult<Vec<AcademicStats>>>) {
+    debug!("get academic stats request: {args:?}");
+    let time_span = match request_span(&state, args.span.as_deref(), args.start_time, args.end_time)
+    {
+        Ok(x) => x,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(Err(e))),
+    };
+    let periods: Vec<_> = state
+        .server_config
+        .calendar
+        .iter()
+        .filter(|p| args.kind.is_none_or(|k| p.kind == k))
+        .filter(|p| p.time_span(&Local).is_some_and(|s| s.overlaps(&time_span)))
+        .cloned()
+        .collect();
+    if periods.is_empty() {
+        return (StatusCode::OK, Json(Ok(Vec::new())));
+    }
+    // 只读取覆盖这些时间段的记录.
+    let covering = TimeSpan::new(
+        periods
+            .iter()
+            .filter_map(|p| p.time_span(&Local)?.start_time)
+            .min(),
+        periods
+            .iter()
+            .filter_map(|p| p.time_span(&Local)?.end_time)
+            .max(),
+    );
+    match collect_records(&state, &covering, args.include_archives).await {
+        Ok(mut records) => {
+            records.sort();
+            (
+                StatusCode::OK,
+                Json(Ok(analytics::academic_stats(&records, &periods))),
+            )
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+fn roommate_response<T>(result: CSResult<T>) -> (StatusCode, Json<CSResult<T>>) {
+    match result {
+        Ok(x) => (StatusCode::OK, Json(Ok(x))),
+        Err(e @ CSError::RoommateNotFound) => (StatusCode::NOT_FOUND, Json(Err(e))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct AddRoommateArgs {
+    pub(crate) name: String,
+    /// 分摊权重, 为 None 时为 1.
+    pub(crate) weight: Option<f32>,
+    pub(crate) move_in: Option<DateTime<FixedOffset>>,
+    pub(crate) move_out: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub(crate) away: Vec<TimeSpan>,
+}
+
+pub(super) async fn add_roommate(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<AddRoommateArgs>,
+) -> (StatusCode, Json<CSResult<Roommate>>) {
+    info!("add roommate request: {args:?}");
+    let roommate = Roommate {
+        id: 0,
+        name: args.name,
+        weight: args.weight.unwrap_or(1.0),
+        move_in: args.move_in,
+        move_out: args.move_out,
+        away: args.away,
+    };
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.roommates_lock.lock().await;
+    roommate_response(roommate::add(&room_dir, roommate).await)
+}
+
+/// 按照编号替换室友信息, 包括入住/搬出时间和不在宿舍的时间段.
+pub(super) async fn update_roommate(
+    State(state): State<Arc<AppState>>,
+    Json(roommate): Json<Roommate>,
+) -> (StatusCode, Json<CSResult<Roommate>>) {
+    info!("update roommate request: {roommate:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.roommates_lock.lock().await;
+    roommate_response(roommate::update(&room_dir, roommate).await)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct DeleteRoommateArgs {
+    pub(crate) id: u64,
+}
+
+pub(super) async fn delete_roommate(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<DeleteRoommateArgs>,
+) -> (StatusCode, Json<CSResult<()>>) {
+    info!("delete roommate request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.roommates_lock.lock().await;
+    roommate_response(roommate::delete(&room_dir, args.id).await)
+}
+
+pub(super) async fn list_roommates(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Vec<Roommate>>>) {
+    debug!("list roommates request.");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.roommates_lock.lock().await;
+    roommate_response(roommate::list(&room_dir).await)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct GetSettlementArgs {
+    /// 分摊的时间范围或者 archive.
+    #[serde(flatten)]
+    pub(crate) span: SpanArgs,
+    /// 为 true 时以 csv 文件的形式返回分摊表.
+    #[serde(default, deserialize_with = "query_bool")]
+    pub(crate) csv: bool,
+}
+
+/// 按照室友在宿舍的时间和权重分摊用电量和费用, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn get_settlement(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<GetSettlementArgs>,
+) -> Response<Body> {
+    debug!("get settlement request: {args:?}");
+    let settlement = async {
+        let (records, time_span) = args.span.records(&state).await?;
+        let room_dir = state.room_dir.read().await.clone();
+        let roommates = {
+            let _lock = state.roommates_lock.lock().await;
+            roommate::list(&room_dir).await?
+        };
+        let usage = analytics::hourly_usage(&records, &time_span, &state.accounting);
+        Ok::<_, CSError>(roommate::settle(&roommates, &usage, &time_span))
+    }
+    .await;
+    let settlement = match settlement {
+        Ok(x) => x,
+        Err(e) => {
+            return (span_error_status(&e), Json(CSResult::<Settlement>::Err(e))).into_response();
+        }
+    };
+    if !args.csv {
+        return (StatusCode::OK, Json(CSResult::Ok(settlement))).into_response();
+    }
+    match settlement.to_csv().await {
+        Ok(content) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/csv")
+            .header(
+                "Content-Disposition",
+                "attachment; filename=\"settlement.csv\"",
+            )
+            .body(Body::from(content))
+            .unwrap()
+            .into_response(),
+        Err(e) => {
+            error!(target: "serializing settlement", "{e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(CSResult::<Settlement>::Err(CSError::SerializeRecords)),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct GetAnomaliesArgs {
+    pub(crate) start_time: Option<DateTime<FixedOffset>>,
+    pub(crate) end_time: Option<DateTime<FixedOffset>>,
+    /// 时间范围表达式, 如 `last:7d`, `month:2026-09`, 给出时忽略起止时间.
+    #[serde(default)]
+    pub(crate) span: Option<String>,
+    /// 只返回严重程度不低于此值的异常.
+    pub(crate) min_severity: Option<Severity>,
+}
+
+/// 获取与时间范围重叠的用电异常事件, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn get_anomalies(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<GetAnomaliesArgs>,
+) -> (StatusCode, Json<CSResult<Vec<AnomalyEvent>>>) {
+    debug!("get anomalies request: {args:?}");
+    let time_span = match request_span(&state, args.span.as_deref(), args.start_time, args.end_time)
+    {
+        Ok(x) => x,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(Err(e))),
+    };
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.anomalies_lock.lock().await;
+    match anomaly::list(&room_dir, &time_span, args.min_severity).await {
+        Ok(events) => (StatusCode::OK, Json(Ok(events))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+fn away_response<T>(result: CSResult<T>) -> (StatusCode, Json<CSResult<T>>) {
+    match result {
+        Ok(x) => (StatusCode::OK, Json(Ok(x))),
+        Err(e @ CSError::AwayNotFound) => (StatusCode::NOT_FOUND, Json(Err(e))),
+        Err(e @ CSError::InvalidAwayPeriod) => (StatusCode::BAD_REQUEST, Json(Err(e))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct SetAwayArgs {
+    pub(crate) start_time: DateTime<FixedOffset>,
+    pub(crate) end_time: DateTime<FixedOffset>,
+    /// 允许超出基础负载的用电量 (度), 为 None 时使用服务端配置的默认值.
+    pub(crate) limit: Option<f32>,
+    /// 时间线上标注的文本, 为 None 时使用默认文本.
+    pub(crate) text: Option<String>,
+}
+
+/// 设置离开时间段, 并在时间线上添加对应的标注.
+pub(super) async fn set_away(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<SetAwayArgs>,
+) -> (StatusCode, Json<CSResult<AwayPeriod>>) {
+    info!("set away request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    away_response(
+        away::set(
+            &room_dir,
+            args.start_time,
+            args.end_time,
+            args.limit.unwrap_or(state.server_config.away_limit),
+            args.text,
+        )
+        .await,
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct CancelAwayArgs {
+    pub(crate) id: u64,
+}
+
+/// 取消离开时间段, 同时删除对应的标注.
+pub(super) async fn cancel_away(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<CancelAwayArgs>,
+) -> (StatusCode, Json<CSResult<()>>) {
+    info!("cancel away request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    away_response(away::cancel(&room_dir, args.id).await)
+}
+
+/// 列出离开时间段及其用电报告.
+pub(super) async fn list_away(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Vec<AwayPeriod>>>) {
+    debug!("list away request.");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.annotations_lock.lock().await;
+    away_response(away::list(&room_dir).await)
+}
+
+fn budget_response<T>(result: CSResult<T>) -> (StatusCode, Json<CSResult<T>>) {
+    match result {
+        Ok(x) => (StatusCode::OK, Json(Ok(x))),
+        Err(e @ CSError::BudgetNotFound) => (StatusCode::NOT_FOUND, Json(Err(e))),
+        Err(e @ CSError::InvalidBudget) => (StatusCode::BAD_REQUEST, Json(Err(e))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct SetBudgetArgs {
+    pub(crate) period: BudgetPeriod,
+    pub(crate) unit: BudgetUnit,
+    pub(crate) amount: f32,
+}
+
+/// 设置预算, 已经有相同周期和单位的预算时修改其数额.
+pub(super) async fn set_budget(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<SetBudgetArgs>,
+) -> (StatusCode, Json<CSResult<Budget>>) {
+    info!("set budget request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.budgets_lock.lock().await;
+    budget_response(
+        budget::set(
+            &room_dir,
+            args.period,
+            args.unit,
+            args.amount,
+            &state.accounting,
+        )
+        .await,
+    )
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct DeleteBudgetArgs {
+    pub(crate) id: u64,
+}
+
+pub(super) async fn delete_budget(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<DeleteBudgetArgs>,
+) -> (StatusCode, Json<CSResult<()>>) {
+    info!("delete budget request: {args:?}");
+    let room_dir = state.room_dir.read().await.clone();
+    let _lock = state.budgets_lock.lock().await;
+    budget_response(budget::delete(&room_dir, args.id).await)
+}
+
+/// 获取各个预算在当前周期的执行情况, 没有配置电价时不包含以元为单位的预算.
+pub(super) async fn get_budgets(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Vec<BudgetProgress>>>) {
+    debug!("get budgets request.");
+    let room_dir = state.room_dir.read().await.clone();
+    let budgets = {
+        let _lock = state.budgets_lock.lock().await;
+        match budget::list(&room_dir).await {
+            Ok(x) => x,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+        }
+    };
+    let mut records = match state.recorder.write().await.read_records().await {
+        Ok(records) => records,
+        Err(e) => {
+            error!(target: "reading records", "{e:?}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(Err(CSError::ReadRecords)),
+            );
+        }
+    };
+    records.sort();
+    let now = Local::now();
+    let progresses = budgets
+        .iter()
+        .filter_map(|b| budget::progress(b, &records, &now, &state.accounting))
+        .collect();
+    (StatusCode::OK, Json(Ok(progresses)))
+}
+
+/// 发送一条测试告警到所有渠道, 返回各渠道的发送结果.
+pub(super) async fn test_alert(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<CSResult<Vec<AlertDelivery>>>) {
+    info!("test alert request.");
+    match alert::test(&state).await {
+        Ok(x) => (StatusCode::OK, Json(Ok(x))),
+        Err(e @ CSError::AlertsDisabled) => (StatusCode::NOT_FOUND, Json(Err(e))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(Err(e))),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct GetDigestArgs {
+    #[serde(default)]
+    pub(crate) schedule: DigestSchedule,
+    /// 为 text 或 html 时直接返回渲染后的摘要.
+    #[serde(default)]
+    pub(crate) format: DigestFormat,
+}
+
+/// 按照当前的记录生成用电摘要, 参数需要使用 reqwest `.query()` 的方式给入.
+pub(super) async fn get_digest(
+    State(state): State<Arc<AppState>>,
+    Query(args): Query<GetDigestArgs>,
+) -> Response<Body> {
+    debug!("get digest request: {args:?}");
+    let digest = match digest::generate(&state, args.schedule).await {
+        Ok(x) => x,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(CSResult::<Digest>::Err(e)),
+            )
+                .into_response();
+        }
+    };
+    let (content_type, body) = match args.format {
+        DigestFormat::Json => return (StatusCode::OK, Json(CSResult::Ok(digest))).into_response(),
+        DigestFormat::Text => ("text/plain; charset=utf-8", digest.to_text()),
+        DigestFormat::Html => ("text/html; charset=utf-8", digest.to_html()),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .body(Body::from(body))
+        .unwrap()
+        .into_response()
+}
+
+/// Prometheus 文本格式的指标.
+pub(super) async fn get_metrics(State(state): State<Arc<AppState>>) -> Response<Body> {
+    debug!("get metrics request.");
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+        .body(Body::from(metrics::render(&state).await))
+        .unwrap()
+        .into_response()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub(crate) struct SubscribeArgs {
+    /// 最后收到的事件编号, 不支持设置请求头的客户端可以通过此参数续接, `Last-Event-ID` 请求头优先.
+    pub(crate) last_event_id: Option<String>,
+}
+
+/// 以 Server-Sent Events 推送服务端事件, 给出最后收到的事件编号时先补发之后的事件.
+pub(super) async fn subscribe(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(args): Query<SubscribeArgs>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string)
+        .or(args.last_event_id);
+    info!("subscribe request: {last_event_id:?}");
+    let stream = state
+        .events
+        .stream(last_event_id.as_deref())
+        .map(|envelope| {
+            Ok(Event::default()
+                .id(envelope.id)
+                .json_data(&envelope.event)
+                .expect("server event serializes to json"))
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Grafana 添加数据源时的连通性测试.
+pub(super) async fn grafana_test() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Grafana 接口成功时直接返回结果本身, 不包装为 [`CSResult`].
+fn grafana_response<T: Serialize>(result: Result<T, CSError>) -> Response<Body> {
+    match result {
+        Ok(x) => (StatusCode::OK, Json(x)).into_response(),
+        Err(e @ (CSError::UnknownGrafanaTarget | CSError::InvalidArchiveName)) => {
+            (StatusCode::BAD_REQUEST, Json(CSResult::<()>::Err(e))).into_response()
+        }
+        Err(e @ CSError::ArchiveNotFound) => {
+            (StatusCode::NOT_FOUND, Json(CSResult::<()>::Err(e))).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(CSResult::<()>::Err(e)),
+        )
+            .into_response(),
+    }
+}
+
+pub(super) async fn grafana_search(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<GrafanaSearch>,
+) -> Response<Body> {
+    debug!("grafana search request: {args:?}");
+    grafana_response(grafana::search(&state, &args).await)
+}
+
+pub(super) async fn grafana_query(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<GrafanaQuery>,
+) -> Response<Body> {
+    debug!("grafana query request: {args:?}");
+    grafana_response(grafana::query(&state, &args).await)
+}
+
+pub(super) async fn grafana_annotations(
+    State(state): State<Arc<AppState>>,
+    Json(args): Json<GrafanaAnnotations>,
+) -> Response<Body> {
+    debug!("grafana annotations request: {args:?}");
+    grafana_response(grafana::annotations(&state, &args).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Query;
+    use axum::http::Uri;
+
+    use crate::server::route::GetSettlementArgs;
+
+    #[test]
+    fn flattened_query_args() {
+        let uri: Uri = "/get-settlement?span=last%3A7d&include_archives=true&csv=true"
+            .parse()
+            .unwrap();
+        let Query(args) = Query::<GetSettlementArgs>::try_from_uri(&uri).unwrap();
+        assert_eq!(args.span.span.as_deref(), Some("last:7d"));
+        assert!(args.span.include_archives);
+        assert!(args.csv);
+
+        let uri: Uri = "/get-settlement?start_time=2026-09-01T00%3A00%3A00%2B08%3A00&csv=false"
+            .parse()
+            .unwrap();
+        let Query(args) = Query::<GetSettlementArgs>::try_from_uri(&uri).unwrap();
+        assert_eq!(
+            args.span.start_time.map(|t| t.timestamp()),
+            Some(1_788_192_000)
+        );
+        assert!(!args.csv);
+
+        let uri: Uri = "/get-settlement?archive=2026-spring".parse().unwrap();
+        let Query(args) = Query::<GetSettlementArgs>::try_from_uri(&uri).unwrap();
+        assert_eq!(args.span.archive.as_deref(), Some("2026-spring"));
+        assert!(!args.span.include_archives && !args.csv);
+
+        let uri: Uri = "/get-settlement?csv=yes".parse().unwrap();
+        assert!(Query::<GetSettlementArgs>::try_from_uri(&uri).is_err());
+    }
+}
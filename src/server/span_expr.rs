@@ -0,0 +1,315 @@
+//! 时间范围表达式.
+//!
+//! 除了直接给出起止时间, 查询参数中的 `span` 也可以是下面的表达式, 由服务端按照服务端时区解析,
+//! 这样不同时区的客户端得到的 "今天", "本月" 都是一样的:
+//!
+//! | 表达式                                   | 含义                                                     |
+//! | ---------------------------------------- | -------------------------------------------------------- |
+//! | `today`, `yesterday`                     | 今天, 昨天                                               |
+//! | `last:<n><h\|d\|w>`                      | 到现在为止最近 n 小时/天/周, 如 `last:7d`                |
+//! | `day:<YYYY-MM-DD>`                       | 某一天                                                   |
+//! | `week:<this\|last\|YYYY-Www>`            | 本周, 上周, 或者某个 ISO 周 (周一开始), 如 `week:2026-W37` |
+//! | `month:<this\|last\|YYYY-MM>`            | 本月, 上月, 或者某个月, 如 `month:2026-09`               |
+//! | `year:<this\|last\|YYYY>`                | 今年, 去年, 或者某一年                                   |
+//! | `semester:<this\|last\|name>`            | 当前学期, 上一个学期, 或者校历中的某个学期               |
+//! | `break:<this\|last\|name>`               | 当前假期, 上一个假期, 或者校历中的某个假期               |
+//! | `exam:<this\|last\|name>`                | 当前考试周, 上一个考试周, 或者校历中的某个考试周         |
+//! | `<from>..<to>`                           | 日期 (`YYYY-MM-DD`) 或 RFC 3339 时间的范围, 任一端可以省略 |
+//!
+//! 解析结果与 [`TimeSpan`] 一样两端都包含: 以日期为单位的表达式从第一天的 0 点开始,
+//! 到最后一天结束前的最后一纳秒 (下一天 0 点前 1 纳秒) 为止; `last:` 的结束时间为当前时间;
+//! 范围表达式中的日期同样包含整天.
+use chrono::{
+    DateTime, Datelike, Days, FixedOffset, Months, NaiveDate, TimeDelta, TimeZone, Weekday,
+};
+
+use crate::config::{AcademicPeriod, PeriodKind};
+use crate::{CSError, TimeSpan};
+
+/// `date` 当天 0 点, 0 点因为夏令时不存在时取之后第一个存在的整点.
+fn day_start<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> Option<DateTime<FixedOffset>> {
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    (0..3)
+        .find_map(|h| {
+            tz.from_local_datetime(&(midnight + TimeDelta::hours(h)))
+                .earliest()
+        })
+        .map(|t| t.fixed_offset())
+}
+
+/// 从 `first` 的 0 点到 `last` 结束的时间范围.
+fn days<Tz: TimeZone>(tz: &Tz, first: NaiveDate, last: NaiveDate) -> Option<TimeSpan> {
+    let start = day_start(tz, first)?;
+    let end = day_start(tz, last.succ_opt()?)? - TimeDelta::nanoseconds(1);
+    (start <= end).then_some(TimeSpan::new(Some(start), Some(end)))
+}
+
+fn month_days(year: i32, month: u32) -> Option<(NaiveDate, NaiveDate)> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let last = first.checked_add_months(Months::new(1))?.pred_opt()?;
+    Some((first, last))
+}
+
+/// 解析范围表达式的一端, `is_end` 为 true 时日期取当天结束.
+fn range_bound<Tz: TimeZone>(
+    tz: &Tz,
+    s: &str,
+    is_end: bool,
+) -> Option<Option<DateTime<FixedOffset>>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Some(None);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let span = days(tz, date, date)?;
+        return Some(if is_end {
+            span.end_time
+        } else {
+            span.start_time
+        });
+    }
+    DateTime::parse_from_rfc3339(s).ok().map(Some)
+}
+
+/// 校历表达式 (如 `semester:this`, `break:2027-winter-break`) 指向的时间段:
+/// `this` 为包含今天的, `last` 为已经结束的最近一个, 否则按照名称查找.
+///
+/// 不是校历表达式或者校历中没有对应的时间段时返回 None.
+pub(crate) fn academic_period<'a>(
+    expr: &str,
+    today: NaiveDate,
+    calendar: &'a [AcademicPeriod],
+) -> Option<&'a AcademicPeriod> {
+    let (kind, which) = expr.trim().split_once(':')?;
+    let kind = match kind {
+        "semester" => PeriodKind::Semester,
+        "break" => PeriodKind::Break,
+        "exam" => PeriodKind::Exam,
+        _ => return None,
+    };
+    let mut periods = calendar.iter().filter(|p| p.kind == kind);
+    match which {
+        "this" => periods.find(|p| p.start <= today && today <= p.end),
+        "last" => periods.filter(|p| p.end < today).max_by_key(|p| p.end),
+        name => periods.find(|p| p.name == name),
+    }
+}
+
+impl AcademicPeriod {
+    /// 按照 `tz` 时区划分的时间范围, 从开始日期 0 点到结束日期结束.
+    pub fn time_span<Tz: TimeZone>(&self, tz: &Tz) -> Option<TimeSpan> {
+        days(tz, self.start, self.end)
+    }
+}
+
+impl TimeSpan {
+    /// 按照 `now` 的时区解析时间范围表达式, `calendar` 用于 `semester:`, `break:` 和 `exam:` 表达式.
+    ///
+    /// 表达式的语法参见 `span_expr` 模块的文档.
+    ///
+    /// # Errors
+    ///
+    /// - [`CSError::InvalidSpanExpr`][]: 表达式无法解析, 或者校历中没有对应的时间段.
+    pub fn resolve<Tz: TimeZone>(
+        expr: &str,
+        now: &DateTime<Tz>,
+        calendar: &[AcademicPeriod],
+    ) -> Result<TimeSpan, CSError> {
+        let invalid = || CSError::InvalidSpanExpr(expr.to_string());
+        let expr = expr.trim();
+        let tz = now.timezone();
+        let today = now.date_naive();
+
+        if let Some((from, to)) = expr.split_once("..") {
+            let start = range_bound(&tz, from, false).ok_or_else(invalid)?;
+            let end = range_bound(&tz, to, true).ok_or_else(invalid)?;
+            return match (start, end) {
+                (Some(st), Some(et)) if st > et => Err(invalid()),
+                _ => Ok(TimeSpan::new(start, end)),
+            };
+        }
+
+        let span = match expr.split_once(':') {
+            None => match expr {
+                "today" => days(&tz, today, today),
+                "yesterday" => today.pred_opt().and_then(|d| days(&tz, d, d)),
+                _ => None,
+            },
+            Some(("last", amount)) => {
+                let parse = |unit| {
+                    amount
+                        .strip_suffix(unit)
+                        .and_then(|n| n.parse::<i64>().ok())
+                };
+                let delta = parse("h")
+                    .and_then(TimeDelta::try_hours)
+                    .or_else(|| parse("d").and_then(TimeDelta::try_days))
+                    .or_else(|| parse("w").and_then(TimeDelta::try_weeks))
+                    .filter(|d| *d > TimeDelta::zero())
+                    .ok_or_else(invalid)?;
+                let now = now.fixed_offset();
+                Some(TimeSpan::new(Some(now - delta), Some(now)))
+            }
+            Some(("day", day)) => NaiveDate::parse_from_str(day, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| days(&tz, d, d)),
+            Some(("week", week)) => {
+                let this_monday = today.week(Weekday::Mon).first_day();
+                let monday = match week {
+                    "this" => Some(this_monday),
+                    "last" => this_monday.checked_sub_days(Days::new(7)),
+                    iso => iso.split_once("-W").and_then(|(y, w)| {
+                        NaiveDate::from_isoywd_opt(y.parse().ok()?, w.parse().ok()?, Weekday::Mon)
+                    }),
+                };
+                monday.and_then(|m| days(&tz, m, m.checked_add_days(Days::new(6))?))
+            }
+            Some(("month", month)) => {
+                let ym = match month {
+                    "this" => Some((today.year(), today.month())),
+                    "last" => today
+                        .with_day(1)
+                        .and_then(|d| d.pred_opt())
+                        .map(|d| (d.year(), d.month())),
+                    ym => ym
+                        .split_once('-')
+                        .and_then(|(y, m)| Some((y.parse().ok()?, m.parse().ok()?))),
+                };
+                ym.and_then(|(y, m)| month_days(y, m))
+                    .and_then(|(first, last)| days(&tz, first, last))
+            }
+            Some(("year", year)) => {
+                let year = match year {
+                    "this" => Some(today.year()),
+                    "last" => Some(today.year() - 1),
+                    y => y.parse().ok(),
+                };
+                year.and_then(|y| {
+                    days(
+                        &tz,
+                        NaiveDate::from_ymd_opt(y, 1, 1)?,
+                        NaiveDate::from_ymd_opt(y, 12, 31)?,
+                    )
+                })
+            }
+            Some(("semester" | "break" | "exam", _)) => {
+                academic_period(expr, today, calendar).and_then(|p| p.time_span(&tz))
+            }
+            Some(_) => None,
+        };
+        span.ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset, NaiveDate, TimeDelta, TimeZone};
+
+    use crate::TimeSpan;
+    use crate::config::{AcademicPeriod, PeriodKind};
+    use crate::server::span_expr::academic_period;
+
+    fn t(m: u32, d: u32, h: u32) -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(8 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2026, m, d, h, 0, 0)
+            .unwrap()
+    }
+
+    /// 从 `start` 开始, 到 `end` 之前 1 纳秒为止.
+    fn span(start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> TimeSpan {
+        TimeSpan::new(Some(start), Some(end - TimeDelta::nanoseconds(1)))
+    }
+
+    #[test]
+    fn resolve_expressions() {
+        // 2026-09-16 是周三.
+        let now = t(9, 16, 10);
+        let resolve = |expr| TimeSpan::resolve(expr, &now, &[]).unwrap();
+        assert_eq!(resolve("today"), span(t(9, 16, 0), t(9, 17, 0)));
+        assert_eq!(resolve("yesterday"), span(t(9, 15, 0), t(9, 16, 0)));
+        assert_eq!(
+            resolve("last:7d"),
+            TimeSpan::new(Some(t(9, 9, 10)), Some(now))
+        );
+        assert_eq!(resolve("day:2026-09-01"), span(t(9, 1, 0), t(9, 2, 0)));
+        assert_eq!(resolve("week:this"), span(t(9, 14, 0), t(9, 21, 0)));
+        assert_eq!(resolve("week:last"), span(t(9, 7, 0), t(9, 14, 0)));
+        assert_eq!(resolve("week:2026-W37"), span(t(9, 7, 0), t(9, 14, 0)));
+        assert_eq!(resolve("month:2026-09"), span(t(9, 1, 0), t(10, 1, 0)));
+        assert_eq!(resolve("month:last"), span(t(8, 1, 0), t(9, 1, 0)));
+        assert_eq!(resolve("year:this").start_time, Some(t(1, 1, 0)),);
+        assert_eq!(
+            resolve("2026-09-01..2026-09-02"),
+            span(t(9, 1, 0), t(9, 3, 0))
+        );
+        assert_eq!(
+            resolve("2026-09-01T08:00:00+08:00.."),
+            TimeSpan::new(Some(t(9, 1, 8)), None)
+        );
+
+        for expr in [
+            "",
+            "tomorrow",
+            "last:7",
+            "last:-1d",
+            "last:7天",
+            "last:天",
+            "month:2026-13",
+            "semester:this",
+            "2026-09-02..2026-09-01",
+        ] {
+            assert!(TimeSpan::resolve(expr, &now, &[]).is_err(), "{expr}");
+        }
+    }
+
+    #[test]
+    fn resolve_academic_periods() {
+        let date = |m, d| NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+        let calendar = [
+            AcademicPeriod {
+                name: "2026-spring".to_string(),
+                kind: PeriodKind::Semester,
+                start: date(2, 23),
+                end: date(6, 30),
+            },
+            AcademicPeriod {
+                name: "2026-summer-break".to_string(),
+                kind: PeriodKind::Break,
+                start: date(7, 1),
+                end: date(9, 6),
+            },
+            AcademicPeriod {
+                name: "2026-fall".to_string(),
+                kind: PeriodKind::Semester,
+                start: date(9, 7),
+                end: date(12, 31),
+            },
+            AcademicPeriod {
+                name: "2026-fall-midterm".to_string(),
+                kind: PeriodKind::Exam,
+                start: date(9, 14),
+                end: date(9, 20),
+            },
+        ];
+        let now = t(9, 16, 10);
+        let resolve = |expr| TimeSpan::resolve(expr, &now, &calendar).unwrap();
+        assert_eq!(
+            resolve("semester:this"),
+            span(t(9, 7, 0), t(12, 31, 0) + TimeDelta::days(1))
+        );
+        assert_eq!(resolve("semester:last"), span(t(2, 23, 0), t(7, 1, 0)));
+        assert_eq!(
+            resolve("break:2026-summer-break"),
+            span(t(7, 1, 0), t(9, 7, 0))
+        );
+        assert_eq!(resolve("exam:this"), span(t(9, 14, 0), t(9, 21, 0)));
+        assert_eq!(
+            academic_period("semester:this", now.date_naive(), &calendar).map(|p| &p.name[..]),
+            Some("2026-fall")
+        );
+        assert!(academic_period("month:this", now.date_naive(), &calendar).is_none());
+        assert!(TimeSpan::resolve("break:this", &now, &calendar).is_err());
+        assert!(TimeSpan::resolve("semester:2026-summer-break", &now, &calendar).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+//! 房间目录下以 json 保存的状态文件.
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::fs;
+use tracing::error;
+
+use crate::CSError;
+
+/// 读取 `path` 中的状态, 文件不存在时返回默认值, 其他错误记录日志后返回 `err`.
+pub(crate) async fn load<T: DeserializeOwned + Default>(
+    path: &Path,
+    err: CSError,
+) -> Result<T, CSError> {
+    match fs::read(path).await {
+        Ok(content) => serde_json::from_slice(&content).map_err(|e| {
+            error!(target: "json store", "{}: {e:?}", path.display());
+            err
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => {
+            error!(target: "json store", "{}: {e:?}", path.display());
+            Err(err)
+        }
+    }
+}
+
+/// 将状态写入 `path`, 失败时记录日志后返回 `err`.
+pub(crate) async fn save<T: Serialize>(
+    path: &Path,
+    store: &T,
+    err: CSError,
+) -> Result<(), CSError> {
+    let content = serde_json::to_vec_pretty(store).map_err(|e| {
+        error!(target: "json store", "{}: {e:?}", path.display());
+        err.clone()
+    })?;
+    fs::write(path, content).await.map_err(|e| {
+        error!(target: "json store", "{}: {e:?}", path.display());
+        err
+    })
+}
+
+/// 分配下一个编号, 编号从 1 开始.
+pub(crate) fn next_id(next_id: &mut u64) -> u64 {
+    let id = (*next_id).max(1);
+    *next_id = id + 1;
+    id
+}
@@ -5,7 +5,7 @@ use chromiumoxide::BrowserConfig;
 use chrono::{DateTime, FixedOffset};
 use ecnu_power_usage::{
     ArchiveMeta, CSError, Cookies, Records, TimeSpan, client::BrowserExecutor, config::RoomConfig,
-    rooms::RoomInfo,
+    rooms::RoomInfo, server::AwayPeriod,
 };
 use tauri::State;
 use tauri_plugin_dialog::DialogExt;
@@ -284,6 +284,52 @@ pub(crate) async fn get_room_info(app_state: State<'_, AppState>) -> Result<Room
     })
 }
 
+/// 设置离开时间段, `limit` 为 None 时使用服务端的默认用电上限.
+#[tauri::command]
+pub(crate) async fn set_away(
+    app_state: State<'_, AppState>,
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+    limit: Option<f32>,
+    text: Option<String>,
+) -> Result<AwayPeriod, String> {
+    app_state
+        .client
+        .read()
+        .await
+        .set_away(start_time, end_time, limit, text)
+        .await
+        .map_err(|e| {
+            error!("set away failed: {e:?}");
+            format!("set away failed: {e}")
+        })
+}
+
+#[tauri::command]
+pub(crate) async fn list_away(app_state: State<'_, AppState>) -> Result<Vec<AwayPeriod>, String> {
+    app_state
+        .client
+        .read()
+        .await
+        .list_away()
+        .await
+        .map_err(|e| format!("list away failed: {e}"))
+}
+
+#[tauri::command]
+pub(crate) async fn cancel_away(app_state: State<'_, AppState>, id: u64) -> Result<(), String> {
+    app_state
+        .client
+        .read()
+        .await
+        .cancel_away(id)
+        .await
+        .map_err(|e| {
+            error!("cancel away failed: {e:?}");
+            format!("cancel away failed: {e}")
+        })
+}
+
 #[tauri::command]
 pub(crate) async fn quit_app(app: tauri::AppHandle) {
     app.exit(0);
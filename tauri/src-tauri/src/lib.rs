@@ -16,6 +16,7 @@ mod tray;
 use commands::*;
 use config::AppState;
 use error::{Error, Result};
+use routine::away::away_check_routine;
 use routine::degree::degree_check_routine;
 use routine::health::health_check_routine;
 
@@ -54,6 +55,9 @@ pub async fn run() -> anyhow::Result<()> {
             clear_room,
             clear_cookies,
             get_room_info,
+            set_away,
+            list_away,
+            cancel_away,
             quit_app
         ])
         .setup(|app| {
@@ -64,6 +68,9 @@ pub async fn run() -> anyhow::Result<()> {
 
             let handle = app.handle().clone();
             tauri::async_runtime::spawn(async move { degree_check_routine(handle).await });
+
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move { away_check_routine(handle).await });
             Ok(())
         })
         .on_window_event(|window, evt| {
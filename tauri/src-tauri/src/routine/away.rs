@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use tauri::Manager;
+use tracing::{error, info};
+
+use crate::{commands::sys_notify, config::AppState};
+
+const CHECK_INTERVAL: Duration = Duration::from_mins(1);
+
+/// 离开期间用电超过上限, 以及离开时间段结束时发送系统通知.
+pub(crate) async fn away_check_routine(handle: tauri::AppHandle) -> ! {
+    // 已经通知过的离开时间段, 第一次获取时已经处于该状态的不再通知.
+    let mut alerted: Option<HashSet<u64>> = None;
+    let mut finished: Option<HashSet<u64>> = None;
+
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+
+        let state = handle.state::<AppState>();
+        let periods = match state.client.read().await.list_away().await {
+            Ok(periods) => periods,
+            Err(e) => {
+                error!(target: "away check", "failed to list away periods: {e:?}");
+                continue;
+            }
+        };
+        let first = alerted.is_none();
+        let alerted = alerted.get_or_insert_default();
+        let finished = finished.get_or_insert_default();
+        for period in periods {
+            let Some(report) = &period.report else {
+                continue;
+            };
+            if period.alerted && alerted.insert(period.id) && !first {
+                let message = format!(
+                    "离开期间已用电 {:.2} 度, 超出基础负载 {:.2} 度 (上限 {:.2} 度), 请检查是否有电器没有关闭.",
+                    report.consumption, report.excess, period.limit
+                );
+                if let Err(e) = sys_notify(handle.clone(), "离开期间用电异常".to_string(), message)
+                {
+                    error!(target: "away notification", "failed to send notification: {e}");
+                } else {
+                    info!(target: "away notification", "away limit notification sent: {}", period.id);
+                }
+            }
+            if report.finished && finished.insert(period.id) && !first {
+                let message = format!(
+                    "离开期间共用电 {:.2} 度, 其中超出基础负载 {:.2} 度.",
+                    report.consumption, report.excess
+                );
+                if let Err(e) = sys_notify(handle.clone(), "离开时间段已结束".to_string(), message)
+                {
+                    error!(target: "away notification", "failed to send notification: {e}");
+                } else {
+                    info!(target: "away notification", "away report notification sent: {}", period.id);
+                }
+            }
+        }
+    }
+}
@@ -1,2 +1,3 @@
+pub(crate) mod away;
 pub(crate) mod degree;
 pub(crate) mod health;
@@ -0,0 +1,70 @@
+import { invoke } from "@tauri-apps/api/core";
+import { formatRFC3339, parseISO } from "date-fns";
+
+interface RawAwayReport {
+    until: string,
+    consumption: number,
+    baseline_rate: number | null,
+    expected: number,
+    excess: number,
+    finished: boolean,
+}
+
+interface RawAwayPeriod {
+    id: number,
+    start_time: string,
+    end_time: string,
+    limit: number,
+    alerted: boolean,
+    report: RawAwayReport | null,
+}
+
+export interface AwayReport {
+    until: Date,
+    consumption: number,
+    baselineRate: number | null,
+    expected: number,
+    excess: number,
+    finished: boolean,
+}
+
+export interface AwayPeriod {
+    id: number,
+    startTime: Date,
+    endTime: Date,
+    limit: number,
+    alerted: boolean,
+    report: AwayReport | null,
+}
+
+function fromRawPeriod(raw: RawAwayPeriod): AwayPeriod {
+    return {
+        id: raw.id,
+        startTime: parseISO(raw.start_time),
+        endTime: parseISO(raw.end_time),
+        limit: raw.limit,
+        alerted: raw.alerted,
+        report: raw.report && {
+            until: parseISO(raw.report.until),
+            consumption: raw.report.consumption,
+            baselineRate: raw.report.baseline_rate,
+            expected: raw.report.expected,
+            excess: raw.report.excess,
+            finished: raw.report.finished,
+        },
+    };
+}
+
+export async function listAwayCmd(): Promise<AwayPeriod[]> {
+    let raws: RawAwayPeriod[] = await invoke("list_away");
+    return raws.map(fromRawPeriod);
+}
+
+export async function setAwayCmd(startTime: Date, endTime: Date, limit: number | null, text: string | null): Promise<AwayPeriod> {
+    let raw: RawAwayPeriod = await invoke("set_away", { startTime: formatRFC3339(startTime), endTime: formatRFC3339(endTime), limit, text });
+    return fromRawPeriod(raw);
+}
+
+export async function cancelAwayCmd(id: number) {
+    await invoke("cancel_away", { id });
+}